@@ -0,0 +1,836 @@
+use crate::config::Config;
+use crate::disks::Disks;
+use crate::forecast::{self, Forecast};
+use crate::ipc;
+use crate::record;
+use crate::services::{self, Service};
+use crate::system::{Load, Memory, Processor, SystemInfo, Uptime};
+
+use std::error::Error;
+use std::fmt;
+
+//Nagios/Icinga plugin exit codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Status {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl Status {
+    //CRITICAL beats WARNING beats UNKNOWN beats OK when combining rule results
+    fn rank(&self) -> u8 {
+        match self {
+            Status::Ok => 0,
+            Status::Unknown => 1,
+            Status::Warning => 2,
+            Status::Critical => 3,
+        }
+    }
+
+    fn worst(self, other: Status) -> Status {
+        if other.rank() > self.rank() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+            Status::Unknown => "UNKNOWN",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Metric {
+    DiskFree(String),
+    DiskUsed(String),
+    //Time until the recorded usage trend reaches the forecast threshold
+    DiskFull(String),
+    MemoryAvailable,
+    MemoryUsed,
+    LoadCurrent,
+    Load1,
+    Load5,
+    Load15,
+    Cpu,
+    Uptime,
+    //Automatic services that are not running
+    ServicesStopped,
+    ServicesFailed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Operator {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Threshold {
+    Percent(f64),
+    Bytes(f64),
+    Seconds(f64),
+    Number(f64),
+    //Multiple of the number of logical processors, e.g. cores*2
+    Cores(f64),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Rule {
+    pub(crate) text: String,
+    pub(crate) metric: Metric,
+    pub(crate) operator: Operator,
+    pub(crate) threshold: Threshold,
+    pub(crate) severity: Status,
+}
+
+//One value a rule was evaluated against, e.g. the free space on a single drive
+#[derive(Debug, Clone)]
+struct Measurement {
+    label: String,
+    subject: String,
+    value: f64,
+    //Upper bound used for percentage thresholds and perfdata max
+    total: Option<f64>,
+}
+
+#[derive(Default, Debug)]
+struct Collected {
+    uptime: Option<Uptime>,
+    memory: Option<Memory>,
+    load: Option<Load>,
+    processor: Option<Processor>,
+    disks: Option<Vec<Disks>>,
+    services: Option<Vec<Service>>,
+    //Kept as a string so a missing or unreadable store is reported per rule
+    forecasts: Option<Result<Vec<Forecast>, String>>,
+}
+
+#[derive(Debug)]
+struct PerfData {
+    label: String,
+    value: String,
+    uom: &'static str,
+    warning: String,
+    critical: String,
+    min: String,
+    max: String,
+}
+
+impl Operator {
+    fn parse(s: &str) -> Option<Operator> {
+        match s {
+            "<" => Some(Operator::Less),
+            "<=" => Some(Operator::LessEqual),
+            ">" => Some(Operator::Greater),
+            ">=" => Some(Operator::GreaterEqual),
+            _ => None,
+        }
+    }
+
+    fn compare(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Operator::Less => value < threshold,
+            Operator::LessEqual => value <= threshold,
+            Operator::Greater => value > threshold,
+            Operator::GreaterEqual => value >= threshold,
+        }
+    }
+
+    //Nagios range syntax: "10:" alerts below 10, "10" alerts above 10
+    fn perf_range(&self, threshold: f64) -> String {
+        match self {
+            Operator::Less | Operator::LessEqual => format!("{}:", format_number(threshold)),
+            Operator::Greater | Operator::GreaterEqual => format_number(threshold),
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Metric {
+    fn parse(tokens: &[&str]) -> Result<Metric, Box<dyn Error>> {
+        let lower: Vec<String> = tokens.iter().map(|t| t.to_lowercase()).collect();
+        let lower: Vec<&str> = lower.iter().map(|t| t.as_str()).collect();
+
+        match lower.as_slice() {
+            ["disk", drive, "free"] => Ok(Metric::DiskFree(normalise_drive(drive)?)),
+            ["disk", drive, "used"] => Ok(Metric::DiskUsed(normalise_drive(drive)?)),
+            ["disk", drive, "full"] => Ok(Metric::DiskFull(normalise_drive(drive)?)),
+            ["memory", "available"] | ["memory", "free"] => Ok(Metric::MemoryAvailable),
+            ["memory", "used"] => Ok(Metric::MemoryUsed),
+            ["load"] => Ok(Metric::LoadCurrent),
+            ["load1"] => Ok(Metric::Load1),
+            ["load5"] => Ok(Metric::Load5),
+            ["load15"] => Ok(Metric::Load15),
+            ["cpu"] => Ok(Metric::Cpu),
+            ["uptime"] => Ok(Metric::Uptime),
+            ["services", "stopped"] | ["services", "not", "running"] => Ok(Metric::ServicesStopped),
+            ["services", "failed"] => Ok(Metric::ServicesFailed),
+            _ => Err(format!("Unknown metric '{}'", tokens.join(" ")).into()),
+        }
+    }
+
+    fn is_load(&self) -> bool {
+        matches!(
+            self,
+            Metric::LoadCurrent | Metric::Load1 | Metric::Load5 | Metric::Load15 | Metric::Cpu
+        )
+    }
+
+    fn is_disk(&self) -> bool {
+        matches!(self, Metric::DiskFree(_) | Metric::DiskUsed(_))
+    }
+
+    fn is_memory(&self) -> bool {
+        matches!(self, Metric::MemoryAvailable | Metric::MemoryUsed)
+    }
+
+    fn is_services(&self) -> bool {
+        matches!(self, Metric::ServicesStopped | Metric::ServicesFailed)
+    }
+}
+
+//Accepts C, C:, C:\ or * and returns the drive letter (or *)
+fn normalise_drive(drive: &str) -> Result<String, Box<dyn Error>> {
+    let trimmed = drive.trim_end_matches('\\').trim_end_matches(':');
+
+    if trimmed == "*" {
+        return Ok(trimmed.to_string());
+    }
+
+    let mut chars = trimmed.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Ok(c.to_ascii_uppercase().to_string()),
+        _ => Err(format!("Invalid drive '{}'", drive).into()),
+    }
+}
+
+//Durations such as "90s", "10m", "2h" or "30d" as seconds, a bare number is seconds
+pub(crate) fn parse_duration(s: &str) -> Result<u64, Box<dyn Error>> {
+    match Threshold::parse(s)? {
+        Threshold::Seconds(v) | Threshold::Number(v) if v >= 0.0 => Ok(v as u64),
+        _ => Err(format!("Invalid duration '{}', expected e.g. 30s, 10m, 2h or 7d", s).into()),
+    }
+}
+
+impl Threshold {
+    fn parse(s: &str) -> Result<Threshold, Box<dyn Error>> {
+        let s = s.trim().to_lowercase();
+
+        if let Some(multiplier) = s.strip_prefix("cores") {
+            let multiplier = multiplier.trim_start_matches('*');
+            if multiplier.is_empty() {
+                return Ok(Threshold::Cores(1.0));
+            }
+            return Ok(Threshold::Cores(parse_number(multiplier)?));
+        }
+
+        if let Some(pct) = s.strip_suffix('%') {
+            return Ok(Threshold::Percent(parse_number(pct)?));
+        }
+
+        //Find where the number ends and the unit begins
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number = parse_number(number)?;
+
+        let bytes = |factor: f64| Ok(Threshold::Bytes(number * factor));
+        let seconds = |factor: f64| Ok(Threshold::Seconds(number * factor));
+
+        match unit {
+            "" => Ok(Threshold::Number(number)),
+            //Sizes always spell out the unit, as a bare "m" is minutes
+            "b" => bytes(1.0),
+            "kib" => bytes(1024.0),
+            "kb" => bytes(1000.0),
+            "mib" => bytes(1024.0 * 1024.0),
+            "mb" => bytes(1000.0 * 1000.0),
+            "gib" => bytes(1024.0 * 1024.0 * 1024.0),
+            "gb" => bytes(1000.0 * 1000.0 * 1000.0),
+            "tib" => bytes(1024.0 * 1024.0 * 1024.0 * 1024.0),
+            "tb" => bytes(1000.0 * 1000.0 * 1000.0 * 1000.0),
+            "s" | "sec" => seconds(1.0),
+            "m" | "min" => seconds(60.0),
+            "h" => seconds(3600.0),
+            "d" => seconds(86400.0),
+            "w" => seconds(604800.0),
+            _ => Err(format!("Unknown unit '{}' in '{}'", unit, s).into()),
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Result<f64, Box<dyn Error>> {
+    s.trim()
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid number '{}'", s).into())
+}
+
+fn format_number(v: f64) -> String {
+    if v.fract() == 0.0 {
+        format!("{}", v as i64)
+    } else {
+        format!("{:.2}", v)
+    }
+}
+
+impl Rule {
+    pub(crate) fn parse(text: &str, severity: Status) -> Result<Rule, Box<dyn Error>> {
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+
+        let op_index = match tokens.iter().position(|t| Operator::parse(t).is_some()) {
+            Some(i) => i,
+            None => return Err(format!("No comparison operator in rule '{}'", text).into()),
+        };
+
+        if op_index == 0 || op_index == tokens.len() - 1 {
+            return Err(format!("Incomplete rule '{}'", text).into());
+        }
+
+        let metric = Metric::parse(&tokens[..op_index])?;
+        let operator = Operator::parse(tokens[op_index]).unwrap();
+        let threshold = Threshold::parse(&tokens[op_index + 1..].join(""))?;
+
+        //Reject combinations that have no sensible meaning
+        let valid = match (&metric, threshold) {
+            (_, Threshold::Number(_)) => true,
+            (m, Threshold::Percent(_)) => m.is_disk() || m.is_memory() || *m == Metric::Cpu,
+            (m, Threshold::Bytes(_)) => m.is_disk() || m.is_memory(),
+            (m, Threshold::Cores(_)) => m.is_load() && *m != Metric::Cpu,
+            (m, Threshold::Seconds(_)) => matches!(m, Metric::Uptime | Metric::DiskFull(_)),
+        };
+
+        if !valid && (metric.is_disk() || metric.is_memory()) && matches!(threshold, Threshold::Seconds(_)) {
+            return Err(format!("Threshold is a time in rule '{}', sizes need a unit such as MiB, GiB or GB", text).into());
+        }
+        if !valid {
+            return Err(format!("Threshold does not apply to metric in rule '{}'", text).into());
+        }
+
+        return Ok(Rule {
+            text: text.trim().to_string(),
+            metric,
+            operator,
+            threshold,
+            severity,
+        });
+    }
+}
+
+impl Collected {
+    fn gather(rules: &[Rule], config: &Config) -> Collected {
+        let mut collected = Collected::default();
+
+        //Only pay for the ~2 second load sample when a rule needs it
+        if rules.iter().any(|r| r.metric.is_load()) {
+            collected.load = Some(ipc::load(config));
+        }
+
+        if rules.iter().any(|r| matches!(r.threshold, Threshold::Cores(_))) {
+            let mut p: Processor = SystemInfo::new();
+            collected.processor = Some(p.info().clone());
+        }
+
+        if rules.iter().any(|r| r.metric.is_memory()) {
+            let mut m: Memory = SystemInfo::new();
+            collected.memory = Some(m.info().clone());
+        }
+
+        if rules.iter().any(|r| r.metric == Metric::Uptime) {
+            let mut u: Uptime = SystemInfo::new();
+            collected.uptime = Some(u.info().clone());
+        }
+
+        if rules.iter().any(|r| r.metric.is_disk()) {
+            let mut d = Disks::new();
+            let disks = unsafe { d.info() };
+            collected.disks = Some(disks.into_iter().filter(|d| config.include_disk(d)).collect());
+        }
+
+        if rules.iter().any(|r| r.metric.is_services()) {
+            let list = services::snapshot();
+            collected.services = Some(list.into_iter().filter(|s| services::included(s, config)).collect());
+        }
+
+        if rules.iter().any(|r| matches!(r.metric, Metric::DiskFull(_))) {
+            let now = record::now();
+            let from = now - config.forecast_history as i64;
+            let list = forecast::forecast_disks(&config.record_dir, from, now, config.forecast_threshold);
+            collected.forecasts = Some(list.map_err(|e| e.to_string()));
+        }
+
+        return collected;
+    }
+
+    fn measure(&self, metric: &Metric) -> Result<Vec<Measurement>, Box<dyn Error>> {
+        let single = |label: &str, subject: &str, value: f64, total: Option<f64>| {
+            Ok(vec![Measurement {
+                label: label.to_string(),
+                subject: subject.to_string(),
+                value,
+                total,
+            }])
+        };
+
+        match metric {
+            Metric::DiskFree(drive) | Metric::DiskUsed(drive) => {
+                let free = matches!(metric, Metric::DiskFree(_));
+                let disks = self.disks.as_ref().ok_or("Disk information unavailable")?;
+                let found: Vec<Measurement> = disks
+                    .iter()
+                    .filter(|d| drive == "*" || d.drive.starts_with(drive.as_str()))
+                    .map(|d| {
+                        let letter = d.drive.trim_end_matches('\\').trim_end_matches(':');
+                        let (value, kind) = if free {
+                            (d.available_bytes as f64, "free")
+                        } else {
+                            (d.total_bytes.saturating_sub(d.available_bytes) as f64, "used")
+                        };
+                        Measurement {
+                            label: format!("disk_{}_{}", letter, kind),
+                            subject: format!("disk {}: {}", letter, kind),
+                            value,
+                            total: Some(d.total_bytes as f64),
+                        }
+                    })
+                    .collect();
+
+                if found.is_empty() {
+                    return Err(format!("Drive {}: not found", drive).into());
+                }
+                Ok(found)
+            }
+            Metric::DiskFull(drive) => {
+                let list = match self.forecasts.as_ref().ok_or("Forecast information unavailable")? {
+                    Ok(list) => list,
+                    Err(e) => return Err(e.clone().into()),
+                };
+                let matching: Vec<&Forecast> = list.iter().filter(|f| drive == "*" || f.drive == *drive).collect();
+                if matching.is_empty() {
+                    return Err(format!("No recorded history for drive {}:, run `uptime record`", drive).into());
+                }
+
+                //Drives that are not growing never fill up, so there is nothing to compare
+                let now = record::now();
+                Ok(matching
+                    .iter()
+                    .filter_map(|f| {
+                        f.full_at.map(|t| Measurement {
+                            label: format!("disk_{}_full", f.drive),
+                            subject: format!("disk {}: full in ({} confidence)", f.drive, f.confidence()),
+                            value: (t - now).max(0) as f64,
+                            total: None,
+                        })
+                    })
+                    .collect())
+            }
+            Metric::MemoryAvailable | Metric::MemoryUsed => {
+                let m = self.memory.as_ref().ok_or("Memory information unavailable")?;
+                //Memory reports megabytes. Inside a container the limit is what runs out first
+                let (installed, available) = match (m.limit_memory, m.limit_used) {
+                    (Some(limit), Some(used)) if limit < m.installed_ram => {
+                        let limit = (limit * 1024 * 1024) as f64;
+                        (limit, (limit - (used * 1024 * 1024) as f64).max(0.0))
+                    }
+                    _ => ((m.installed_ram * 1024 * 1024) as f64, (m.available_memory * 1024 * 1024) as f64),
+                };
+                if *metric == Metric::MemoryAvailable {
+                    single("memory_available", "memory available", available, Some(installed))
+                } else {
+                    let used = (installed - available).max(0.0);
+                    single("memory_used", "memory used", used, Some(installed))
+                }
+            }
+            Metric::LoadCurrent | Metric::Load1 | Metric::Load5 | Metric::Load15 | Metric::Cpu => {
+                let l = self.load.as_ref().ok_or("Load information unavailable")?;
+                match metric {
+                    Metric::LoadCurrent => single("load", "load", l.current_load, None),
+                    Metric::Load1 => single("load1", "load1", l.avg_1_load, None),
+                    Metric::Load5 => single("load5", "load5", l.avg_5_load, None),
+                    Metric::Load15 => single("load15", "load15", l.avg_15_load, None),
+                    _ => single("cpu", "cpu", l.processor_activity, Some(100.0)),
+                }
+            }
+            Metric::Uptime => {
+                let u = self.uptime.as_ref().ok_or("Uptime information unavailable")?;
+                single("uptime", "uptime", (u.uptime_ms / 1000) as f64, None)
+            }
+            Metric::ServicesStopped | Metric::ServicesFailed => {
+                let list = self.services.as_ref().ok_or("Service information unavailable")?;
+                let (label, kind) = match metric {
+                    Metric::ServicesStopped => ("services_stopped", "stopped"),
+                    _ => ("services_failed", "failed"),
+                };
+                let names: Vec<&str> = list
+                    .iter()
+                    .filter(|s| if *metric == Metric::ServicesStopped { s.stopped_automatic() } else { s.failed() })
+                    .map(|s| s.name.as_str())
+                    .collect();
+                //Name the services so the alert says which ones to look at
+                let subject = if names.is_empty() {
+                    format!("services {}", kind)
+                } else {
+                    format!("services {} ({})", kind, names.join(", "))
+                };
+                single(label, &subject, names.len() as f64, Some(list.len() as f64))
+            }
+        }
+    }
+
+    //Convert a threshold into the same unit as the measurement
+    fn resolve(&self, rule: &Rule, m: &Measurement) -> Result<(f64, f64), Box<dyn Error>> {
+        match rule.threshold {
+            Threshold::Percent(pct) => match m.total {
+                Some(_) if rule.metric == Metric::Cpu => Ok((m.value, pct)),
+                Some(total) if total > 0.0 => Ok((m.value / total * 100.0, pct)),
+                _ => Err(format!("Cannot compute percentage for {}", m.subject).into()),
+            },
+            Threshold::Cores(multiplier) => {
+                let p = self.processor.as_ref().ok_or("Processor information unavailable")?;
+                Ok((m.value, p.effective_processors * multiplier))
+            }
+            Threshold::Bytes(v) | Threshold::Seconds(v) | Threshold::Number(v) => Ok((m.value, v)),
+        }
+    }
+}
+
+fn describe(rule: &Rule, m: &Measurement, value: f64, threshold: f64) -> String {
+    let show = |v: f64| match rule.threshold {
+        Threshold::Percent(_) => format!("{:.1}%", v),
+        Threshold::Bytes(_) => format!("{:.2} GiB", v / (1024.0 * 1024.0 * 1024.0)),
+        Threshold::Seconds(_) if matches!(rule.metric, Metric::DiskFull(_)) => format!("{:.1}d", v / 86400.0),
+        Threshold::Seconds(_) => format!("{}s", v as u64),
+        _ => format!("{:.2}", v),
+    };
+    format!("{} {} {} {}", m.subject, show(value), rule.operator, show(threshold))
+}
+
+fn perf_for(rule: &Rule, m: &Measurement, value: f64) -> PerfData {
+    let (label, value_str, uom, min, max) = match rule.threshold {
+        Threshold::Percent(_) => (format!("{}_pct", m.label), format!("{:.2}", value), "%", "0".to_string(), "100".to_string()),
+        _ => {
+            let uom = match (&rule.metric, rule.threshold) {
+                (Metric::Uptime, _) | (Metric::DiskFull(_), _) => "s",
+                (m, _) if m.is_disk() || m.is_memory() => "B",
+                (Metric::Cpu, _) => "%",
+                _ => "",
+            };
+            let max = m.total.map(format_number).unwrap_or_default();
+            let min = if uom.is_empty() { String::new() } else { "0".to_string() };
+            (m.label.clone(), format_number(value), uom, min, max)
+        }
+    };
+
+    PerfData {
+        label,
+        value: value_str,
+        uom,
+        warning: String::new(),
+        critical: String::new(),
+        min,
+        max,
+    }
+}
+
+impl fmt::Display for PerfData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}={}{};{};{};{};{}",
+            self.label, self.value, self.uom, self.warning, self.critical, self.min, self.max
+        )
+    }
+}
+
+//Evaluate the rules and return the overall status with a single Nagios style output line
+pub(crate) fn evaluate(rules: &[Rule], config: &Config) -> (Status, String) {
+    return assess(rules, &Collected::gather(rules, config));
+}
+
+fn assess(rules: &[Rule], collected: &Collected) -> (Status, String) {
+    let mut status = Status::Ok;
+    let mut problems: Vec<String> = vec![];
+    let mut perf: Vec<PerfData> = vec![];
+
+    for rule in rules {
+        let measurements = match collected.measure(&rule.metric) {
+            Ok(m) => m,
+            Err(e) => {
+                status = status.worst(Status::Unknown);
+                problems.push(format!("{}: {}", rule.text, e));
+                continue;
+            }
+        };
+
+        for m in measurements {
+            let (value, threshold) = match collected.resolve(rule, &m) {
+                Ok(v) => v,
+                Err(e) => {
+                    status = status.worst(Status::Unknown);
+                    problems.push(e.to_string());
+                    continue;
+                }
+            };
+
+            if rule.operator.compare(value, threshold) {
+                status = status.worst(rule.severity);
+                problems.push(describe(rule, &m, value, threshold));
+            }
+
+            let p = perf_for(rule, &m, value);
+            let range = rule.operator.perf_range(threshold);
+
+            //A warning and a critical rule on the same metric share one perfdata entry
+            let entry = match perf.iter_mut().find(|e| e.label == p.label) {
+                Some(e) => e,
+                None => {
+                    perf.push(p);
+                    perf.last_mut().unwrap()
+                }
+            };
+
+            match rule.severity {
+                Status::Warning => entry.warning = range,
+                Status::Critical => entry.critical = range,
+                _ => {}
+            }
+        }
+    }
+
+    let summary = if problems.is_empty() {
+        format!("{} rules passed", rules.len())
+    } else {
+        problems.join(", ")
+    };
+
+    let perf_str: Vec<String> = perf.iter().map(|p| p.to_string()).collect();
+    let line = if perf_str.is_empty() {
+        format!("UPTIME {} - {}", status, summary)
+    } else {
+        format!("UPTIME {} - {} | {}", status, summary, perf_str.join(" "))
+    };
+
+    return (status, line);
+}
+
+fn usage() -> String {
+    "usage: uptime check [-w|--warning RULE]... [-c|--critical RULE]...\n\
+     rules: 'disk C: free < 10%', 'memory available < 1GiB', 'load15 > cores*2', 'uptime < 10m', 'services stopped > 0', 'disk C: full < 30d'"
+        .to_string()
+}
+
+//Parse the check arguments and return the rules to evaluate
+pub(crate) fn parse_args(args: &[String]) -> Result<Vec<Rule>, Box<dyn Error>> {
+    let mut rules: Vec<Rule> = vec![];
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let severity = match arg.as_str() {
+            "-w" | "--warning" => Status::Warning,
+            "-c" | "--critical" => Status::Critical,
+            "-h" | "--help" => return Err(usage().into()),
+            other => return Err(format!("Unexpected argument '{}'\n{}", other, usage()).into()),
+        };
+
+        match iter.next() {
+            Some(text) => rules.push(Rule::parse(text, severity)?),
+            None => return Err(format!("Missing rule after {}\n{}", arg, usage()).into()),
+        }
+    }
+
+    return Ok(rules);
+}
+
+//Entry point for `uptime check`, returns the plugin exit code.
+//Rules given on the command line replace the [thresholds] from the config file
+pub(crate) fn run(args: &[String], config: &Config) -> i32 {
+    let rules = match parse_args(args) {
+        Ok(rules) if rules.is_empty() => Ok(config.rules.clone()),
+        other => other,
+    };
+
+    match rules {
+        Ok(rules) if rules.is_empty() => {
+            println!("UPTIME {} - {}", Status::Unknown, usage());
+            Status::Unknown as i32
+        }
+        Ok(rules) => {
+            let (status, line) = evaluate(&rules, config);
+            println!("{}", line);
+            status as i32
+        }
+        Err(e) => {
+            println!("UPTIME {} - {}", Status::Unknown, e);
+            Status::Unknown as i32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+    fn rule(text: &str, severity: Status) -> Rule {
+        return Rule::parse(text, severity).unwrap();
+    }
+
+    #[test]
+    fn thresholds() {
+        let t = |s: &str| Threshold::parse(s).unwrap();
+        assert_eq!(t("10%"), Threshold::Percent(10.0));
+        assert_eq!(t("4"), Threshold::Number(4.0));
+        assert_eq!(t("12B"), Threshold::Bytes(12.0));
+        assert_eq!(t("2KiB"), Threshold::Bytes(2048.0));
+        assert_eq!(t("2kb"), Threshold::Bytes(2000.0));
+        assert_eq!(t("500MiB"), Threshold::Bytes(500.0 * 1024.0 * 1024.0));
+        assert_eq!(t("500MB"), Threshold::Bytes(500e6));
+        assert_eq!(t("1.5GiB"), Threshold::Bytes(1.5 * GIB));
+        assert_eq!(t("20GB"), Threshold::Bytes(20e9));
+        assert_eq!(t("1TiB"), Threshold::Bytes(1024.0 * GIB));
+        assert_eq!(t("1TB"), Threshold::Bytes(1e12));
+        assert_eq!(t("30s"), Threshold::Seconds(30.0));
+        assert_eq!(t("30sec"), Threshold::Seconds(30.0));
+        assert_eq!(t("10m"), Threshold::Seconds(600.0));
+        assert_eq!(t("10min"), Threshold::Seconds(600.0));
+        assert_eq!(t("2h"), Threshold::Seconds(7200.0));
+        assert_eq!(t("7d"), Threshold::Seconds(604800.0));
+        assert_eq!(t("2w"), Threshold::Seconds(1209600.0));
+        assert_eq!(t("cores"), Threshold::Cores(1.0));
+        assert_eq!(t("cores*2"), Threshold::Cores(2.0));
+        assert_eq!(t(" Cores*1.5 "), Threshold::Cores(1.5));
+
+        //Single letter sizes are ambiguous next to m for minutes
+        assert!(Threshold::parse("500k").is_err());
+        assert!(Threshold::parse("1g").is_err());
+        assert!(Threshold::parse("10x").is_err());
+        assert!(Threshold::parse("cores*x").is_err());
+
+        assert_eq!(parse_duration("90").unwrap(), 90);
+        assert_eq!(parse_duration("2h").unwrap(), 7200);
+        assert!(parse_duration("10%").is_err());
+        assert!(parse_duration("1GiB").is_err());
+    }
+
+    #[test]
+    fn drives() {
+        for form in ["C", "c:", "C:", "C:\\"] {
+            assert_eq!(normalise_drive(form).unwrap(), "C");
+        }
+        assert_eq!(normalise_drive("*").unwrap(), "*");
+        assert!(normalise_drive("CD").is_err());
+        assert!(normalise_drive("1:").is_err());
+        assert!(normalise_drive("").is_err());
+
+        let r = rule("disk c:\\ free < 10%", Status::Warning);
+        assert_eq!(r.metric, Metric::DiskFree("C".to_string()));
+        assert_eq!((r.operator, r.threshold), (Operator::Less, Threshold::Percent(10.0)));
+    }
+
+    #[test]
+    fn rules() {
+        let r = rule("load15 > cores * 2", Status::Critical);
+        assert_eq!((r.metric, r.threshold, r.severity), (Metric::Load15, Threshold::Cores(2.0), Status::Critical));
+        assert_eq!(rule("memory free < 1 GiB", Status::Warning).threshold, Threshold::Bytes(GIB));
+        assert_eq!(rule("services not running > 0", Status::Warning).metric, Metric::ServicesStopped);
+        assert_eq!(rule("disk * full < 30d", Status::Warning).metric, Metric::DiskFull("*".to_string()));
+
+        let err = |text: &str| Rule::parse(text, Status::Warning).unwrap_err().to_string();
+        assert!(err("uptime 10m").starts_with("No comparison operator"));
+        assert!(err("< 10").starts_with("Incomplete rule"));
+        assert!(err("uptime <").starts_with("Incomplete rule"));
+        assert!(err("swap used > 1").starts_with("Unknown metric"));
+        //Combinations without a meaning
+        assert!(err("uptime < 10%").starts_with("Threshold does not apply"));
+        assert!(err("load > 1GiB").starts_with("Threshold does not apply"));
+        assert!(err("cpu > cores").starts_with("Threshold does not apply"));
+        assert!(err("memory used > cores").starts_with("Threshold does not apply"));
+        assert!(err("load > 10m").starts_with("Threshold does not apply"));
+        assert!(err("disk C: full < 10%").starts_with("Threshold does not apply"));
+        assert!(err("disk C: free < 500m").starts_with("Threshold is a time"));
+    }
+
+    #[test]
+    fn nagios_ranges() {
+        assert_eq!(Operator::Less.perf_range(10.0), "10:");
+        assert_eq!(Operator::LessEqual.perf_range(2.5), "2.50:");
+        assert_eq!(Operator::Greater.perf_range(4.0), "4");
+        assert_eq!(Operator::GreaterEqual.perf_range(0.25), "0.25");
+    }
+
+    #[test]
+    fn worst_status() {
+        assert_eq!(Status::Ok.worst(Status::Unknown), Status::Unknown);
+        assert_eq!(Status::Unknown.worst(Status::Warning), Status::Warning);
+        assert_eq!(Status::Critical.worst(Status::Warning), Status::Critical);
+        assert_eq!(Status::Warning.worst(Status::Ok), Status::Warning);
+    }
+
+    fn collected() -> Collected {
+        return Collected {
+            load: Some(Load { avg_15_load: 5.0, ..Load::default() }),
+            processor: Some(Processor { effective_processors: 2.0, ..Processor::default() }),
+            //Megabytes
+            memory: Some(Memory { installed_ram: 4096, available_memory: 512, ..Memory::default() }),
+            ..Collected::default()
+        };
+    }
+
+    #[test]
+    fn warning_and_critical_share_perfdata() {
+        let rules = [rule("load15 > cores*2", Status::Warning), rule("load15 > cores*3", Status::Critical)];
+        let (status, line) = assess(&rules, &collected());
+        assert_eq!(status, Status::Warning);
+        assert_eq!(line, "UPTIME WARNING - load15 5.00 > 4.00 | load15=5;4;6;;");
+
+        let rules = [rule("load15 > cores*3", Status::Warning)];
+        assert_eq!(assess(&rules, &collected()), (Status::Ok, "UPTIME OK - 1 rules passed | load15=5;6;;;".to_string()));
+    }
+
+    #[test]
+    fn critical_beats_warning_and_unknown() {
+        let rules = [
+            rule("uptime < 10m", Status::Critical),
+            rule("load15 > cores*2", Status::Warning),
+            rule("memory available < 2GiB", Status::Warning),
+            rule("memory available < 1GiB", Status::Critical),
+        ];
+        let (status, line) = assess(&rules, &collected());
+        assert_eq!(status, Status::Critical);
+        assert_eq!(
+            line,
+            "UPTIME CRITICAL - uptime < 10m: Uptime information unavailable, load15 5.00 > 4.00, \
+             memory available 0.50 GiB < 2.00 GiB, memory available 0.50 GiB < 1.00 GiB | \
+             load15=5;4;;; memory_available=536870912B;2147483648:;1073741824:;0;4294967296"
+        );
+
+        //A measurement that cannot be taken is UNKNOWN when nothing else fired
+        let (status, _) = assess(&[rule("uptime < 10m", Status::Critical)], &collected());
+        assert_eq!(status, Status::Unknown);
+    }
+}
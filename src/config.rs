@@ -0,0 +1,784 @@
+use crate::check::{parse_duration, Rule, Status};
+use crate::disks::Disks;
+use crate::otlp;
+use crate::procs::SortKey;
+use crate::push::Protocol;
+use crate::remote::{self, Transport};
+use crate::report::Style;
+use crate::system;
+
+use colored::Color;
+use toml::Value;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Section {
+    Host,
+    Uptime,
+    Memory,
+    Disks,
+    Load,
+    Users,
+    Procs,
+    Net,
+    Sockets,
+    Services,
+    Pressure,
+    Cpu,
+    Power,
+}
+
+//Coloured text for people, or one delimited row per sample for spreadsheets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+    Text,
+    Csv,
+    Tsv,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Unit {
+    Mb,
+    Gb,
+    Tb,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Theme {
+    pub(crate) enabled: bool,
+    pub(crate) highlight: Color,
+    pub(crate) total: Color,
+    pub(crate) available: Color,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) sections: Vec<Section>,
+    pub(crate) format: Format,
+    //Repeat the output every N seconds until interrupted
+    pub(crate) watch: Option<u32>,
+    pub(crate) memory_unit: Unit,
+    pub(crate) disk_unit: Unit,
+    pub(crate) theme: Theme,
+    pub(crate) exclude_drives: Vec<String>,
+    pub(crate) exclude_filesystems: Vec<String>,
+    pub(crate) cpu_interval: u32,
+    pub(crate) load_interval: u32,
+    pub(crate) rules: Vec<Rule>,
+    pub(crate) procs_top: usize,
+    pub(crate) procs_sort: SortKey,
+    pub(crate) procs_filter: String,
+    pub(crate) net_filter: String,
+    pub(crate) sockets_top: usize,
+    pub(crate) services_exclude: Vec<String>,
+    //Metrics store for `uptime record`/`uptime query`, retention in seconds per tier
+    pub(crate) record_dir: PathBuf,
+    pub(crate) record_interval: u32,
+    pub(crate) record_retention: [u64; 3],
+    //Disk forecasts: fill percentage counted as full and how much recorded history to fit
+    pub(crate) forecast_threshold: f64,
+    pub(crate) forecast_history: u64,
+    //Metrics pushed by `uptime push`, target is udp://host:port or tcp://host:port
+    pub(crate) push_target: Option<String>,
+    pub(crate) push_protocol: Protocol,
+    pub(crate) push_interval: u32,
+    pub(crate) push_prefix: String,
+    pub(crate) push_retries: u32,
+    //OTLP export by `uptime otlp`, the endpoint defaults to a local collector for the protocol
+    pub(crate) otlp_endpoint: Option<String>,
+    pub(crate) otlp_protocol: otlp::Protocol,
+    pub(crate) otlp_interval: u32,
+    //Local query endpoint of a running daemon, a named pipe on Windows and a Unix socket elsewhere
+    pub(crate) ipc_enabled: bool,
+    pub(crate) ipc_path: String,
+    //Hosts queried by `--host` and how, through their agents or by running this tool over ssh
+    pub(crate) hosts: Vec<String>,
+    pub(crate) remote_transport: Transport,
+    pub(crate) remote_timeout: u32,
+    pub(crate) remote_parallel: usize,
+    pub(crate) remote_command: String,
+    //Listener for `uptime agent`, requests must carry the token when one is set
+    pub(crate) agent_listen: String,
+    pub(crate) agent_token: Option<String>,
+    //`uptime report` over a directory of JSON snapshots, hosts up this many days are due a reboot
+    pub(crate) report_dir: Option<PathBuf>,
+    pub(crate) report_style: Style,
+    pub(crate) report_top: usize,
+    pub(crate) report_reboot_days: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sections: vec![Section::Host, Section::Uptime, Section::Memory, Section::Disks, Section::Load],
+            format: Format::Text,
+            watch: None,
+            memory_unit: Unit::Mb,
+            disk_unit: Unit::Gb,
+            theme: Theme {
+                enabled: true,
+                highlight: Color::Blue,
+                total: Color::Green,
+                available: Color::Red,
+            },
+            exclude_drives: vec![],
+            exclude_filesystems: vec![],
+            cpu_interval: system::DEFAULT_CPU_INTERVAL,
+            load_interval: system::DEFAULT_LOAD_INTERVAL,
+            rules: vec![],
+            procs_top: 10,
+            procs_sort: SortKey::Cpu,
+            procs_filter: String::new(),
+            net_filter: String::new(),
+            sockets_top: 5,
+            services_exclude: vec![],
+            record_dir: default_data_dir(),
+            record_interval: 60,
+            //Raw samples for 2 days, 5 minute averages for 30 days, hourly averages for a year
+            record_retention: [2 * 86400, 30 * 86400, 365 * 86400],
+            forecast_threshold: 100.0,
+            forecast_history: 30 * 86400,
+            push_target: None,
+            push_protocol: Protocol::Influx,
+            push_interval: 10,
+            push_prefix: "uptime".to_string(),
+            push_retries: 3,
+            otlp_endpoint: None,
+            otlp_protocol: otlp::Protocol::Http,
+            otlp_interval: 60,
+            ipc_enabled: true,
+            ipc_path: default_ipc_path(),
+            hosts: vec![],
+            remote_transport: Transport::Agent,
+            remote_timeout: 15,
+            remote_parallel: 32,
+            remote_command: "uptime".to_string(),
+            agent_listen: format!("127.0.0.1:{}", remote::DEFAULT_PORT),
+            agent_token: None,
+            report_dir: None,
+            report_style: Style::Text,
+            report_top: 10,
+            report_reboot_days: 30,
+        }
+    }
+}
+
+impl Section {
+    pub(crate) fn parse(s: &str) -> Option<Section> {
+        match s.to_lowercase().as_str() {
+            "host" => Some(Section::Host),
+            "uptime" => Some(Section::Uptime),
+            "memory" => Some(Section::Memory),
+            "disks" => Some(Section::Disks),
+            "load" => Some(Section::Load),
+            "users" => Some(Section::Users),
+            "procs" => Some(Section::Procs),
+            "net" => Some(Section::Net),
+            "sockets" => Some(Section::Sockets),
+            "services" => Some(Section::Services),
+            "pressure" => Some(Section::Pressure),
+            "cpu" => Some(Section::Cpu),
+            "power" => Some(Section::Power),
+            _ => None,
+        }
+    }
+}
+
+impl Format {
+    fn parse(s: &str) -> Option<Format> {
+        match s.to_lowercase().as_str() {
+            "text" => Some(Format::Text),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+impl Unit {
+    fn parse(s: &str) -> Option<Unit> {
+        match s.to_lowercase().as_str() {
+            "mb" => Some(Unit::Mb),
+            "gb" => Some(Unit::Gb),
+            "tb" => Some(Unit::Tb),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Unit::Mb => "MB",
+            Unit::Gb => "GB",
+            Unit::Tb => "TB",
+        }
+    }
+
+    //Format a byte count in this unit, whole numbers for MB as the original output did
+    pub(crate) fn format_bytes(&self, bytes: u64) -> String {
+        match self {
+            Unit::Mb => (bytes / (1024 * 1024)).to_string(),
+            Unit::Gb => format!("{:.2}", bytes as f64 / (1024.0 * 1024.0 * 1024.0)),
+            Unit::Tb => format!("{:.2}", bytes as f64 / (1024.0 * 1024.0 * 1024.0 * 1024.0)),
+        }
+    }
+}
+
+fn key_error<T>(source: &str, key: &str, msg: &str) -> Result<T, Box<dyn Error>> {
+    Err(format!("{}: invalid key `{}`: {}", source, key, msg).into())
+}
+
+fn as_str<'a>(value: &'a Value, source: &str, key: &str) -> Result<&'a str, Box<dyn Error>> {
+    match value.as_str() {
+        Some(s) => Ok(s),
+        None => key_error(source, key, "expected a string"),
+    }
+}
+
+fn as_bool(value: &Value, source: &str, key: &str) -> Result<bool, Box<dyn Error>> {
+    match value.as_bool() {
+        Some(b) => Ok(b),
+        None => key_error(source, key, "expected true or false"),
+    }
+}
+
+fn as_seconds(value: &Value, source: &str, key: &str) -> Result<u32, Box<dyn Error>> {
+    match value.as_integer() {
+        Some(i) if (1..=3600).contains(&i) => Ok(i as u32),
+        Some(_) => key_error(source, key, "must be between 1 and 3600 seconds"),
+        None => key_error(source, key, "expected an integer number of seconds"),
+    }
+}
+
+//The same checks as as_seconds, for a flag value that has not been parsed yet
+fn parse_seconds(text: &str, source: &str, key: &str) -> Result<u32, Box<dyn Error>> {
+    match text.parse::<i64>() {
+        Ok(i) => as_seconds(&Value::Integer(i), source, key),
+        Err(_) => key_error(source, key, "expected an integer number of seconds"),
+    }
+}
+
+fn as_str_list(value: &Value, source: &str, key: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let array = match value.as_array() {
+        Some(a) => a,
+        None => return key_error(source, key, "expected an array of strings"),
+    };
+
+    let mut list = vec![];
+    for (i, item) in array.iter().enumerate() {
+        list.push(as_str(item, source, &format!("{}[{}]", key, i))?.to_string());
+    }
+
+    return Ok(list);
+}
+
+fn as_table<'a>(
+    value: &'a Value,
+    source: &str,
+    key: &str,
+) -> Result<&'a toml::value::Table, Box<dyn Error>> {
+    match value.as_table() {
+        Some(t) => Ok(t),
+        None => key_error(source, key, "expected a table"),
+    }
+}
+
+fn parse_color(value: &Value, source: &str, key: &str) -> Result<Color, Box<dyn Error>> {
+    match as_str(value, source, key)?.parse::<Color>() {
+        Ok(c) => Ok(c),
+        Err(_) => key_error(source, key, "unknown colour, expected e.g. \"red\" or \"bright blue\""),
+    }
+}
+
+fn parse_unit(s: &str, source: &str, key: &str) -> Result<Unit, Box<dyn Error>> {
+    match Unit::parse(s) {
+        Some(u) => Ok(u),
+        None => key_error(source, key, "expected \"mb\", \"gb\" or \"tb\""),
+    }
+}
+
+fn parse_format(s: &str, source: &str, key: &str) -> Result<Format, Box<dyn Error>> {
+    match Format::parse(s) {
+        Some(f) => Ok(f),
+        None => key_error(source, key, "expected \"text\", \"csv\", \"tsv\" or \"json\""),
+    }
+}
+
+fn parse_sections(list: &[String], source: &str, key: &str) -> Result<Vec<Section>, Box<dyn Error>> {
+    let mut sections = vec![];
+    for (i, name) in list.iter().enumerate() {
+        match Section::parse(name) {
+            Some(s) if sections.contains(&s) => {
+                return key_error(source, &format!("{}[{}]", key, i), "section listed twice")
+            }
+            Some(s) => sections.push(s),
+            None => {
+                return key_error(
+                    source,
+                    &format!("{}[{}]", key, i),
+                    &format!("unknown section '{}', expected host, uptime, memory, disks, load, users, procs, net, sockets, services, pressure, cpu or power", name),
+                )
+            }
+        }
+    }
+
+    return Ok(sections);
+}
+
+//Accept "C", "C:" or "C:\" and store the bare upper case letter
+fn normalise_drive(s: &str) -> String {
+    s.trim_end_matches('\\').trim_end_matches(':').to_uppercase()
+}
+
+impl Config {
+    //Overlay the keys present in a parsed config file onto the current values
+    fn apply_table(&mut self, root: &toml::value::Table, source: &str) -> Result<(), Box<dyn Error>> {
+        for (key, value) in root {
+            match key.as_str() {
+                "sections" => {
+                    let list = as_str_list(value, source, key)?;
+                    self.sections = parse_sections(&list, source, key)?;
+                }
+                "format" => self.format = parse_format(as_str(value, source, key)?, source, key)?,
+                "units" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("units.{}", k);
+                        match k.as_str() {
+                            "memory" => self.memory_unit = parse_unit(as_str(v, source, &path)?, source, &path)?,
+                            "disk" => self.disk_unit = parse_unit(as_str(v, source, &path)?, source, &path)?,
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "theme" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("theme.{}", k);
+                        match k.as_str() {
+                            "color" => self.theme.enabled = as_bool(v, source, &path)?,
+                            "highlight" => self.theme.highlight = parse_color(v, source, &path)?,
+                            "total" => self.theme.total = parse_color(v, source, &path)?,
+                            "available" => self.theme.available = parse_color(v, source, &path)?,
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "disks" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("disks.{}", k);
+                        match k.as_str() {
+                            "exclude" => {
+                                self.exclude_drives = as_str_list(v, source, &path)?
+                                    .iter()
+                                    .map(|d| normalise_drive(d))
+                                    .collect()
+                            }
+                            "exclude_filesystems" => {
+                                self.exclude_filesystems = as_str_list(v, source, &path)?
+                                    .iter()
+                                    .map(|f| f.to_uppercase())
+                                    .collect()
+                            }
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "sampling" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("sampling.{}", k);
+                        match k.as_str() {
+                            "cpu_interval" => self.cpu_interval = as_seconds(v, source, &path)?,
+                            "load_interval" => self.load_interval = as_seconds(v, source, &path)?,
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "procs" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("procs.{}", k);
+                        match k.as_str() {
+                            "top" => match v.as_integer() {
+                                Some(i) if i > 0 => self.procs_top = i as usize,
+                                _ => return key_error(source, &path, "expected a positive integer"),
+                            },
+                            "sort" => match SortKey::parse(as_str(v, source, &path)?) {
+                                Some(sort) => self.procs_sort = sort,
+                                None => return key_error(source, &path, "expected cpu, mem, private, handles or io"),
+                            },
+                            "filter" => self.procs_filter = as_str(v, source, &path)?.to_string(),
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "net" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("net.{}", k);
+                        match k.as_str() {
+                            "filter" => self.net_filter = as_str(v, source, &path)?.to_string(),
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "sockets" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("sockets.{}", k);
+                        match k.as_str() {
+                            "top" => match v.as_integer() {
+                                Some(i) if i > 0 => self.sockets_top = i as usize,
+                                _ => return key_error(source, &path, "expected a positive integer"),
+                            },
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "services" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("services.{}", k);
+                        match k.as_str() {
+                            "exclude" => self.services_exclude = as_str_list(v, source, &path)?,
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "record" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("record.{}", k);
+                        let tier = match k.as_str() {
+                            "dir" => {
+                                self.record_dir = PathBuf::from(as_str(v, source, &path)?);
+                                continue;
+                            }
+                            "interval" => {
+                                self.record_interval = as_seconds(v, source, &path)?;
+                                continue;
+                            }
+                            "raw_retention" => 0,
+                            "five_minute_retention" => 1,
+                            "hourly_retention" => 2,
+                            _ => return key_error(source, &path, "unknown key"),
+                        };
+                        match parse_duration(as_str(v, source, &path)?) {
+                            Ok(secs) if secs > 0 => self.record_retention[tier] = secs,
+                            Ok(_) => return key_error(source, &path, "must be longer than 0s"),
+                            Err(e) => return key_error(source, &path, &e.to_string()),
+                        }
+                    }
+                }
+                "forecast" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("forecast.{}", k);
+                        match k.as_str() {
+                            "threshold" => match v.as_float().or_else(|| v.as_integer().map(|i| i as f64)) {
+                                Some(t) if t > 0.0 && t <= 100.0 => self.forecast_threshold = t,
+                                Some(_) => return key_error(source, &path, "must be a percentage above 0 and up to 100"),
+                                None => return key_error(source, &path, "expected a number"),
+                            },
+                            "history" => match parse_duration(as_str(v, source, &path)?) {
+                                Ok(secs) if secs > 0 => self.forecast_history = secs,
+                                Ok(_) => return key_error(source, &path, "must be longer than 0s"),
+                                Err(e) => return key_error(source, &path, &e.to_string()),
+                            },
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "push" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("push.{}", k);
+                        match k.as_str() {
+                            "target" => self.push_target = Some(as_str(v, source, &path)?.to_string()),
+                            "protocol" => match Protocol::parse(as_str(v, source, &path)?) {
+                                Some(p) => self.push_protocol = p,
+                                None => return key_error(source, &path, "expected \"influx\", \"statsd\" or \"dogstatsd\""),
+                            },
+                            "interval" => self.push_interval = as_seconds(v, source, &path)?,
+                            "prefix" => self.push_prefix = as_str(v, source, &path)?.to_string(),
+                            "retries" => match v.as_integer() {
+                                Some(i) if (0..=10).contains(&i) => self.push_retries = i as u32,
+                                _ => return key_error(source, &path, "expected an integer between 0 and 10"),
+                            },
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "otlp" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("otlp.{}", k);
+                        match k.as_str() {
+                            "endpoint" => self.otlp_endpoint = Some(as_str(v, source, &path)?.to_string()),
+                            "protocol" => match otlp::Protocol::parse(as_str(v, source, &path)?) {
+                                Some(p) => self.otlp_protocol = p,
+                                None => return key_error(source, &path, "expected \"http\" or \"grpc\""),
+                            },
+                            "interval" => self.otlp_interval = as_seconds(v, source, &path)?,
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "ipc" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("ipc.{}", k);
+                        match k.as_str() {
+                            "enabled" => self.ipc_enabled = as_bool(v, source, &path)?,
+                            "path" => self.ipc_path = as_str(v, source, &path)?.to_string(),
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "remote" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("remote.{}", k);
+                        match k.as_str() {
+                            "transport" => match Transport::parse(as_str(v, source, &path)?) {
+                                Some(t) => self.remote_transport = t,
+                                None => return key_error(source, &path, "expected \"agent\" or \"ssh\""),
+                            },
+                            "timeout" => self.remote_timeout = as_seconds(v, source, &path)?,
+                            "parallel" => match v.as_integer() {
+                                Some(i) if (1..=256).contains(&i) => self.remote_parallel = i as usize,
+                                _ => return key_error(source, &path, "expected an integer between 1 and 256"),
+                            },
+                            "command" => self.remote_command = as_str(v, source, &path)?.to_string(),
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "agent" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("agent.{}", k);
+                        match k.as_str() {
+                            "listen" => self.agent_listen = as_str(v, source, &path)?.to_string(),
+                            "token" => self.agent_token = Some(as_str(v, source, &path)?.to_string()),
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "report" => {
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("report.{}", k);
+                        match k.as_str() {
+                            "dir" => self.report_dir = Some(PathBuf::from(as_str(v, source, &path)?)),
+                            "style" => match Style::parse(as_str(v, source, &path)?) {
+                                Some(s) => self.report_style = s,
+                                None => return key_error(source, &path, "expected \"text\", \"markdown\" or \"html\""),
+                            },
+                            "top" => match v.as_integer() {
+                                Some(i) if i > 0 => self.report_top = i as usize,
+                                _ => return key_error(source, &path, "expected a positive integer"),
+                            },
+                            "reboot_days" => match v.as_integer() {
+                                Some(i) if (1..=3650).contains(&i) => self.report_reboot_days = i as u32,
+                                _ => return key_error(source, &path, "expected an integer between 1 and 3650"),
+                            },
+                            _ => return key_error(source, &path, "unknown key"),
+                        }
+                    }
+                }
+                "thresholds" => {
+                    let mut rules = vec![];
+                    for (k, v) in as_table(value, source, key)? {
+                        let path = format!("thresholds.{}", k);
+                        let severity = match k.as_str() {
+                            "warning" => Status::Warning,
+                            "critical" => Status::Critical,
+                            _ => return key_error(source, &path, "unknown key"),
+                        };
+                        for (i, text) in as_str_list(v, source, &path)?.iter().enumerate() {
+                            match Rule::parse(text, severity) {
+                                Ok(r) => rules.push(r),
+                                Err(e) => return key_error(source, &format!("{}[{}]", path, i), &e.to_string()),
+                            }
+                        }
+                    }
+                    self.rules = rules;
+                }
+                _ => return key_error(source, key, "unknown key"),
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn apply_file(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let source = path.display().to_string();
+        let text = match std::fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) => return Err(format!("{}: {}", source, e).into()),
+        };
+
+        match text.parse::<Value>() {
+            Ok(Value::Table(root)) => self.apply_table(&root, &source),
+            Ok(_) => Err(format!("{}: expected a table at the top level", source).into()),
+            Err(e) => Err(format!("{}: {}", source, e).into()),
+        }
+    }
+
+    //Apply command line flags, which take precedence over both config files.
+    //Returns the arguments that are not global flags, e.g. a subcommand and its options
+    fn apply_args(&mut self, args: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+        let source = "command line";
+        let mut rest = vec![];
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            let mut value = |flag: &str| match iter.next() {
+                Some(v) => Ok(v.clone()),
+                None => key_error(source, flag, "missing value"),
+            };
+
+            match arg.as_str() {
+                "--config" => {
+                    value(arg)?;
+                }
+                "--sections" => {
+                    let list: Vec<String> = value(arg)?.split(',').map(|s| s.trim().to_string()).collect();
+                    self.sections = parse_sections(&list, source, arg)?;
+                }
+                "--format" => self.format = parse_format(&value(arg)?, source, arg)?,
+                "--watch" => self.watch = Some(parse_seconds(&value(arg)?, source, arg)?),
+                "--memory-unit" => self.memory_unit = parse_unit(&value(arg)?, source, arg)?,
+                "--disk-unit" => self.disk_unit = parse_unit(&value(arg)?, source, arg)?,
+                "--no-color" => self.theme.enabled = false,
+                "--no-daemon" => self.ipc_enabled = false,
+                "--host" => {
+                    let list = value(arg)?;
+                    self.hosts.extend(list.split(',').map(|h| h.trim().to_string()).filter(|h| !h.is_empty()));
+                }
+                "--transport" => match Transport::parse(&value(arg)?) {
+                    Some(t) => self.remote_transport = t,
+                    None => return key_error(source, arg, "expected \"agent\" or \"ssh\""),
+                },
+                "--host-timeout" => self.remote_timeout = parse_seconds(&value(arg)?, source, arg)?,
+                "--exclude-drive" => self.exclude_drives.push(normalise_drive(&value(arg)?)),
+                "--exclude-fs" => self.exclude_filesystems.push(value(arg)?.to_uppercase()),
+                "--cpu-interval" => self.cpu_interval = parse_seconds(&value(arg)?, source, arg)?,
+                "--load-interval" => self.load_interval = parse_seconds(&value(arg)?, source, arg)?,
+                _ => rest.push(arg.clone()),
+            }
+        }
+
+        return Ok(rest);
+    }
+
+    pub(crate) fn include_disk(&self, disk: &Disks) -> bool {
+        let letter = normalise_drive(&disk.drive);
+        !self.exclude_drives.contains(&letter)
+            && !self.exclude_filesystems.contains(&disk.file_system.to_uppercase())
+    }
+}
+
+//Where `uptime record` keeps its metrics unless [record] dir says otherwise
+fn default_data_dir() -> PathBuf {
+    if cfg!(windows) {
+        if let Some(dir) = std::env::var_os("ProgramData") {
+            return PathBuf::from(dir).join("uptime").join("metrics");
+        }
+    } else if let Some(dir) = std::env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join("uptime").join("metrics");
+    } else if let Some(dir) = std::env::var_os("HOME") {
+        return PathBuf::from(dir).join(".local").join("share").join("uptime").join("metrics");
+    }
+    return PathBuf::from("metrics");
+}
+
+//Both the daemon and the clients use this unless [ipc] path says otherwise. /run/uptime is
+//created for the systemd unit by RuntimeDirectory=
+fn default_ipc_path() -> String {
+    if cfg!(windows) {
+        return r"\\.\pipe\uptime".to_string();
+    }
+    return "/run/uptime/uptime.sock".to_string();
+}
+
+//System wide config first, then the per user config so user settings win
+fn default_locations() -> Vec<PathBuf> {
+    let mut locations = vec![];
+
+    if cfg!(windows) {
+        if let Some(dir) = std::env::var_os("ProgramData") {
+            locations.push(PathBuf::from(dir).join("uptime").join(CONFIG_FILE));
+        }
+        if let Some(dir) = std::env::var_os("APPDATA") {
+            locations.push(PathBuf::from(dir).join("uptime").join(CONFIG_FILE));
+        }
+    } else {
+        locations.push(PathBuf::from("/etc/uptime").join(CONFIG_FILE));
+        if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+            locations.push(PathBuf::from(dir).join("uptime").join(CONFIG_FILE));
+        } else if let Some(dir) = std::env::var_os("HOME") {
+            locations.push(PathBuf::from(dir).join(".config").join("uptime").join(CONFIG_FILE));
+        }
+    }
+
+    return locations;
+}
+
+//Build the configuration from the config files and command line flags.
+//An explicit --config file replaces the default locations and must exist
+pub(crate) fn load(args: &[String]) -> Result<(Config, Vec<String>), Box<dyn Error>> {
+    let mut config = Config::default();
+
+    let explicit = args
+        .iter()
+        .position(|a| a == "--config")
+        .map(|i| args.get(i + 1).map(PathBuf::from));
+
+    match explicit {
+        Some(Some(path)) => config.apply_file(&path)?,
+        Some(None) => return key_error("command line", "--config", "missing value"),
+        None => {
+            for path in default_locations() {
+                if path.is_file() {
+                    config.apply_file(&path)?;
+                }
+            }
+        }
+    }
+
+    let rest = config.apply_args(args)?;
+
+    return Ok((config, rest));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn seconds_flags_are_parsed() {
+        let mut config = Config::default();
+        let rest = config
+            .apply_args(&args(&["check", "--cpu-interval", "5", "--watch", "10", "--host-timeout", "30"]))
+            .unwrap();
+        assert_eq!(rest, args(&["check"]));
+        assert_eq!(config.cpu_interval, 5);
+        assert_eq!(config.watch, Some(10));
+        assert_eq!(config.remote_timeout, 30);
+    }
+
+    #[test]
+    fn non_numeric_seconds_flag_is_rejected() {
+        for flag in ["--watch", "--cpu-interval", "--load-interval", "--host-timeout"] {
+            let err = Config::default().apply_args(&args(&[flag, "abc"])).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("command line: invalid key `{}`: expected an integer number of seconds", flag)
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range_seconds_flag_is_rejected() {
+        let err = Config::default().apply_args(&args(&["--watch", "0"])).unwrap_err();
+        assert_eq!(err.to_string(), "command line: invalid key `--watch`: must be between 1 and 3600 seconds");
+        assert!(Config::default().apply_args(&args(&["--load-interval", "3601"])).is_err());
+    }
+}
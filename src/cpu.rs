@@ -0,0 +1,179 @@
+use crate::config::Config;
+
+use std::error::Error;
+
+//Where CPU time went over the sampling interval. The categories differ by platform so they
+//are kept as labelled percentages of all processors, in the order the OS reports them
+#[derive(Default, Debug, Clone)]
+pub(crate) struct CpuTimes {
+    pub(crate) times: Vec<(&'static str, f64)>,
+    pub(crate) context_switches: f64,
+    pub(crate) interrupts: f64,
+}
+
+//Cumulative jiffies from the "cpu" line of /proc/stat plus the ctxt and intr totals
+#[cfg(not(windows))]
+#[derive(Default, Debug, Clone, Copy)]
+struct Ticks {
+    user: u64,
+    nice: u64,
+    system: u64,
+    idle: u64,
+    iowait: u64,
+    irq: u64,
+    softirq: u64,
+    steal: u64,
+    guest: u64,
+    guest_nice: u64,
+    ctxt: u64,
+    intr: u64,
+}
+
+#[cfg(not(windows))]
+fn parse_stat(text: &str) -> Option<Ticks> {
+    let mut t = Ticks::default();
+    let mut found = false;
+
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("cpu") => {
+                let v: Vec<u64> = parts.filter_map(|p| p.parse().ok()).collect();
+                //Older kernels stop after steal or guest, missing columns stay 0
+                let at = |i: usize| v.get(i).copied().unwrap_or(0);
+                t.user = at(0);
+                t.nice = at(1);
+                t.system = at(2);
+                t.idle = at(3);
+                t.iowait = at(4);
+                t.irq = at(5);
+                t.softirq = at(6);
+                t.steal = at(7);
+                t.guest = at(8);
+                t.guest_nice = at(9);
+                found = true;
+            }
+            Some("ctxt") => t.ctxt = parts.next()?.parse().ok()?,
+            //First column is the total, the rest are per interrupt line
+            Some("intr") => t.intr = parts.next()?.parse().ok()?,
+            _ => {}
+        }
+    }
+
+    if found {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(windows))]
+fn read_ticks() -> Result<Ticks, Box<dyn Error>> {
+    let text = std::fs::read_to_string("/proc/stat")?;
+    return parse_stat(&text).ok_or_else(|| "/proc/stat: no cpu line".into());
+}
+
+#[cfg(not(windows))]
+pub(crate) fn sample(interval: u32) -> Result<CpuTimes, Box<dyn Error>> {
+    let first = read_ticks()?;
+    let start = std::time::Instant::now();
+    std::thread::sleep(std::time::Duration::from_secs(interval.max(1) as u64));
+    let second = read_ticks()?;
+    let seconds = start.elapsed().as_secs_f64();
+
+    let d = |a: u64, b: u64| b.saturating_sub(a) as f64;
+    //user and nice already include guest time, split it out so the columns add up to 100%
+    let guest = d(first.guest, second.guest);
+    let guest_nice = d(first.guest_nice, second.guest_nice);
+    let times = [
+        ("user", (d(first.user, second.user) - guest).max(0.0)),
+        ("nice", (d(first.nice, second.nice) - guest_nice).max(0.0)),
+        ("system", d(first.system, second.system)),
+        ("iowait", d(first.iowait, second.iowait)),
+        ("irq", d(first.irq, second.irq)),
+        ("softirq", d(first.softirq, second.softirq)),
+        ("steal", d(first.steal, second.steal)),
+        ("guest", guest + guest_nice),
+        ("idle", d(first.idle, second.idle)),
+    ];
+    let total: f64 = times.iter().map(|(_, v)| v).sum::<f64>().max(1.0);
+
+    return Ok(CpuTimes {
+        times: times.iter().map(|(name, v)| (*name, v * 100.0 / total)).collect(),
+        context_switches: d(first.ctxt, second.ctxt) / seconds,
+        interrupts: d(first.intr, second.intr) / seconds,
+    });
+}
+
+//Privileged time already includes interrupt and DPC time, take them out of it
+//so that the columns add up to 100% like they do on Linux
+#[cfg(windows)]
+fn windows_times(v: &[f64]) -> CpuTimes {
+    return CpuTimes {
+        times: vec![
+            ("user", v[0]),
+            ("kernel", (v[1] - v[2] - v[3]).max(0.0)),
+            ("interrupt", v[2]),
+            ("dpc", v[3]),
+            ("idle", v[4]),
+        ],
+        context_switches: v[5],
+        interrupts: v[6],
+    };
+}
+
+#[cfg(windows)]
+pub(crate) fn sample(interval: u32) -> Result<CpuTimes, Box<dyn Error>> {
+    let v = unsafe { crate::system::get_cpu_times(interval)? };
+    return Ok(windows_times(&v));
+}
+
+pub(crate) fn print_cpu_times(c: &CpuTimes) {
+    let times: Vec<String> = c.times.iter().map(|(name, pct)| format!("{}: {:.1}%", name, pct)).collect();
+    println!("CPU Time: {}", times.join(" "));
+    println!("Context Switches: {:.0}/s | Interrupts: {:.0}/s", c.context_switches, c.interrupts);
+}
+
+//Entry point for `uptime cpu`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    if !args.is_empty() {
+        return Err("usage: uptime cpu".into());
+    }
+
+    print_cpu_times(&sample(config.cpu_interval)?);
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    #[test]
+    fn kernel_time_excludes_interrupt_and_dpc() {
+        let c = windows_times(&[30.0, 20.0, 3.0, 2.0, 50.0, 1000.0, 500.0]);
+        assert_eq!(c.times, vec![("user", 30.0), ("kernel", 15.0), ("interrupt", 3.0), ("dpc", 2.0), ("idle", 50.0)]);
+        assert_eq!(c.times.iter().map(|(_, v)| v).sum::<f64>(), 100.0);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn proc_stat() {
+        let text = "cpu  100 5 50 1000 20 1 2 3 10 1\n\
+                    cpu0 50 2 25 500 10 0 1 1 5 0\n\
+                    intr 12345 10 20 30\n\
+                    ctxt 67890\n\
+                    btime 1700000000\n";
+        let t = parse_stat(text).unwrap();
+        assert_eq!((t.user, t.nice, t.system, t.idle), (100, 5, 50, 1000));
+        assert_eq!((t.iowait, t.irq, t.softirq, t.steal), (20, 1, 2, 3));
+        assert_eq!((t.guest, t.guest_nice), (10, 1));
+        assert_eq!((t.intr, t.ctxt), (12345, 67890));
+
+        //Kernels before 2.6.33 have no guest_nice column
+        let old = parse_stat("cpu  1 2 3 4 5 6 7 8\n").unwrap();
+        assert_eq!((old.steal, old.guest, old.guest_nice), (8, 0, 0));
+        assert!(parse_stat("intr 1\n").is_none());
+    }
+}
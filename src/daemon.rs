@@ -0,0 +1,666 @@
+use crate::config::{self, Config};
+use crate::{ipc, otlp, push, record, remote};
+
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+//Set by the SCM control handler or a signal handler and polled by the background loops
+static STOP: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+//Background modes that can run as a service
+const MODES: [&str; 4] = ["record", "push", "otlp", "agent"];
+
+//How often wait() looks for a stop or reload request
+const POLL: Duration = Duration::from_millis(250);
+
+//Sleep in a background loop. Returns false as soon as a stop or reload is requested, the loop
+//should then return so the service can shut down or start again with the new config
+pub(crate) fn wait(duration: Duration) -> bool {
+    let end = Instant::now() + duration;
+    loop {
+        if STOP.load(Ordering::SeqCst) || RELOAD.load(Ordering::SeqCst) {
+            return false;
+        }
+        #[cfg(not(windows))]
+        systemd::watchdog();
+
+        let now = Instant::now();
+        if now >= end {
+            return true;
+        }
+        std::thread::sleep(POLL.min(end - now));
+    }
+}
+
+fn run_mode(mode: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    match mode.first().map(|m| m.as_str()) {
+        Some("record") => record::run_record(&mode[1..], config),
+        Some("push") => push::run(&mode[1..], config),
+        Some("otlp") => otlp::run(&mode[1..], config),
+        Some("agent") => remote::run_agent(&mode[1..], config),
+        _ => Err(format!("Service mode must be one of {}", MODES.join(", ")).into()),
+    }
+}
+
+//The mode and its options from `service run <mode> ...` once global flags are removed
+fn mode_args(rest: &[String]) -> Vec<String> {
+    match rest.iter().position(|a| a == "run") {
+        Some(i) => rest[i + 1..].to_vec(),
+        None => vec![],
+    }
+}
+
+//Run a background mode until stopped. On reload the config files and flags are read again from
+//the command line; a broken config is reported and the previous one kept
+fn serve(args: &[String], mut config: Config, mut mode: Vec<String>) -> Result<(), Box<dyn Error>> {
+    #[cfg(windows)]
+    scm::set_state(winapi::um::winsvc::SERVICE_RUNNING, 0);
+    #[cfg(not(windows))]
+    systemd::notify(&format!("READY=1\nSTATUS=Running {}", mode.join(" ")));
+    ipc::start(&config);
+
+    loop {
+        let result = run_mode(&mode, &config);
+
+        if STOP.load(Ordering::SeqCst) {
+            #[cfg(not(windows))]
+            systemd::notify("STOPPING=1");
+            ipc::stop(&config);
+            return Ok(());
+        }
+        //The mode ended on its own, e.g. an error or --once
+        if !RELOAD.swap(false, Ordering::SeqCst) {
+            ipc::stop(&config);
+            return result;
+        }
+
+        #[cfg(not(windows))]
+        systemd::notify("RELOADING=1");
+        match config::load(args) {
+            Ok((c, rest)) => {
+                config = c;
+                mode = mode_args(&rest);
+            }
+            Err(e) => eprintln!("Reload failed, keeping the previous configuration: {}", e),
+        }
+        #[cfg(not(windows))]
+        systemd::notify(&format!("READY=1\nSTATUS=Running {}", mode.join(" ")));
+    }
+}
+
+//Arguments after `service install` become the service command line, `service run <mode> ...`.
+//They come from the raw command line so global flags such as --config are kept as well
+fn install_args(raw: &[String]) -> Result<(Option<String>, Vec<String>), Box<dyn Error>> {
+    let service = raw.iter().position(|a| a == "service").unwrap_or(0);
+    let install = raw.iter().position(|a| a == "install").unwrap_or(service);
+
+    let mut name = None;
+    let mut args = vec![];
+    let mut iter = raw[install + 1..].iter().chain(raw[..service].iter());
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--name" => name = Some(iter.next().ok_or("--name needs a value")?.clone()),
+            _ => args.push(arg.clone()),
+        }
+    }
+
+    if !args.first().map(|m| MODES.contains(&m.as_str())).unwrap_or(false) {
+        return Err(format!("Service mode must be one of {}", MODES.join(", ")).into());
+    }
+    return Ok((name, args));
+}
+
+//`uptime-record` for the record mode unless a name is given
+fn service_name(name: Option<&String>, mode: &str) -> String {
+    match name {
+        Some(n) if MODES.contains(&n.as_str()) => format!("uptime-{}", n),
+        Some(n) => n.clone(),
+        None => format!("uptime-{}", mode),
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+//Windows service control manager: the dispatcher, control handler and install/uninstall
+#[cfg(windows)]
+mod scm {
+    use super::*;
+    use std::ptr::null_mut;
+    use std::sync::atomic::AtomicUsize;
+    use winapi::shared::minwindef::{DWORD, LPVOID};
+    use winapi::shared::winerror::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_FAILED_SERVICE_CONTROLLER_CONNECT, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR};
+    use winapi::um::winnt::{DELETE, LPWSTR, SERVICE_AUTO_START, SERVICE_ERROR_NORMAL, SERVICE_WIN32_OWN_PROCESS};
+    use winapi::um::winsvc::{
+        ChangeServiceConfig2W, CloseServiceHandle, ControlService, CreateServiceW, DeleteService, OpenSCManagerW, OpenServiceW,
+        QueryServiceStatus, RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW, StartServiceW, SC_HANDLE,
+        SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE, SERVICE_ACCEPT_PARAMCHANGE, SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP,
+        SERVICE_ALL_ACCESS, SERVICE_CONFIG_DESCRIPTION, SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_PARAMCHANGE,
+        SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_DESCRIPTIONW, SERVICE_QUERY_STATUS, SERVICE_RUNNING,
+        SERVICE_START_PENDING, SERVICE_STATUS, SERVICE_STATUS_HANDLE, SERVICE_STOP, SERVICE_STOPPED, SERVICE_STOP_PENDING,
+        SERVICE_TABLE_ENTRYW,
+    };
+
+    //SERVICE_STATUS_HANDLE from RegisterServiceCtrlHandlerExW, 0 when not started by the SCM
+    static HANDLE: AtomicUsize = AtomicUsize::new(0);
+
+    pub(super) fn set_state(state: DWORD, exit_code: DWORD) {
+        let handle = HANDLE.load(Ordering::SeqCst);
+        if handle == 0 {
+            return;
+        }
+        let pending = state == SERVICE_START_PENDING || state == SERVICE_STOP_PENDING;
+        let mut status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: if state == SERVICE_RUNNING {
+                SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN | SERVICE_ACCEPT_PARAMCHANGE
+            } else {
+                0
+            },
+            //A service specific code makes the SCM log event 7024 with it
+            dwWin32ExitCode: if exit_code != 0 { ERROR_SERVICE_SPECIFIC_ERROR } else { NO_ERROR },
+            dwServiceSpecificExitCode: exit_code,
+            dwCheckPoint: 0,
+            //A collection can take a few seconds before the loop sees the stop request
+            dwWaitHint: if pending { 30_000 } else { 0 },
+        };
+        unsafe { SetServiceStatus(handle as SERVICE_STATUS_HANDLE, &mut status) };
+    }
+
+    unsafe extern "system" fn control_handler(control: DWORD, _event: DWORD, _data: LPVOID, _context: LPVOID) -> DWORD {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                STOP.store(true, Ordering::SeqCst);
+                set_state(SERVICE_STOP_PENDING, 0);
+                NO_ERROR
+            }
+            //sc control <name> paramchange
+            SERVICE_CONTROL_PARAMCHANGE => {
+                RELOAD.store(true, Ordering::SeqCst);
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    unsafe extern "system" fn service_main(_argc: DWORD, _argv: *mut LPWSTR) {
+        //The name is not checked for SERVICE_WIN32_OWN_PROCESS
+        let name = to_wide("");
+        let handle = RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), null_mut());
+        if handle.is_null() {
+            return;
+        }
+        HANDLE.store(handle as usize, Ordering::SeqCst);
+        set_state(SERVICE_START_PENDING, 0);
+
+        //The SCM starts us with the command line given at install, read it again here
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let result = config::load(&args).and_then(|(config, rest)| serve(&args, config, mode_args(&rest)));
+        set_state(SERVICE_STOPPED, if result.is_ok() { 0 } else { 1 });
+    }
+
+    //Hand the main thread to the SCM, which calls service_main on another thread. Started from a
+    //console the dispatcher fails straight away and the mode runs in the foreground instead
+    pub(super) fn dispatch(args: &[String], config: &Config, mode: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let mut name = to_wide("");
+        let table = [
+            SERVICE_TABLE_ENTRYW { lpServiceName: name.as_mut_ptr(), lpServiceProc: Some(service_main) },
+            SERVICE_TABLE_ENTRYW { lpServiceName: null_mut(), lpServiceProc: None },
+        ];
+
+        if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() == Some(ERROR_FAILED_SERVICE_CONTROLLER_CONNECT as i32) {
+                return serve(args, config.clone(), mode);
+            }
+            return Err(e.into());
+        }
+
+        return Ok(());
+    }
+
+    //CommandLineToArgvW rules: quote arguments with spaces, escape quotes and the backslashes before them
+    pub(super) fn quote(arg: &str) -> String {
+        if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+            return arg.to_string();
+        }
+        let mut out = String::from("\"");
+        let mut backslashes = 0;
+        for c in arg.chars() {
+            match c {
+                '\\' => backslashes += 1,
+                '"' => {
+                    out.push_str(&"\\".repeat(backslashes * 2 + 1));
+                    backslashes = 0;
+                }
+                _ => {
+                    out.push_str(&"\\".repeat(backslashes));
+                    backslashes = 0;
+                }
+            }
+            if c != '\\' {
+                out.push(c);
+            }
+        }
+        out.push_str(&"\\".repeat(backslashes * 2));
+        out.push('"');
+        return out;
+    }
+
+    fn last_error(what: &str) -> Box<dyn Error> {
+        return format!("{}: {}", what, std::io::Error::last_os_error()).into();
+    }
+
+    unsafe fn open_manager(access: DWORD) -> Result<SC_HANDLE, Box<dyn Error>> {
+        let scm = OpenSCManagerW(null_mut(), null_mut(), access);
+        if scm.is_null() {
+            return Err(last_error("Cannot open the service control manager (run as administrator)"));
+        }
+        return Ok(scm);
+    }
+
+    pub(super) fn install(name: &str, args: &[String]) -> Result<(), Box<dyn Error>> {
+        let exe = std::env::current_exe()?;
+        let mut command = format!("{} service run", quote(&exe.to_string_lossy()));
+        for arg in args {
+            command.push(' ');
+            command.push_str(&quote(arg));
+        }
+
+        unsafe {
+            let scm = open_manager(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE)?;
+            let display = to_wide(&format!("uptime {}", args[0]));
+            //LocalSystem, started at boot
+            let service = CreateServiceW(
+                scm,
+                to_wide(name).as_ptr(),
+                display.as_ptr(),
+                SERVICE_ALL_ACCESS,
+                SERVICE_WIN32_OWN_PROCESS,
+                SERVICE_AUTO_START,
+                SERVICE_ERROR_NORMAL,
+                to_wide(&command).as_ptr(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+            );
+            if service.is_null() {
+                let e = last_error(&format!("Cannot create service {}", name));
+                CloseServiceHandle(scm);
+                return Err(e);
+            }
+
+            let mut text = to_wide("Samples system metrics in the background, see `uptime service` in the README");
+            let mut description = SERVICE_DESCRIPTIONW { lpDescription: text.as_mut_ptr() };
+            ChangeServiceConfig2W(service, SERVICE_CONFIG_DESCRIPTION, &mut description as *mut _ as LPVOID);
+
+            let started = StartServiceW(service, 0, null_mut()) != 0;
+            let e = last_error(&format!("Installed {} but it did not start", name));
+            CloseServiceHandle(service);
+            CloseServiceHandle(scm);
+            if !started {
+                return Err(e);
+            }
+        }
+
+        println!("Installed and started service {}: {}", name, command);
+        return Ok(());
+    }
+
+    pub(super) fn uninstall(name: &str) -> Result<(), Box<dyn Error>> {
+        unsafe {
+            let scm = open_manager(SC_MANAGER_CONNECT)?;
+            let service = OpenServiceW(scm, to_wide(name).as_ptr(), SERVICE_STOP | SERVICE_QUERY_STATUS | DELETE);
+            if service.is_null() {
+                let e = last_error(&format!("Cannot open service {}", name));
+                CloseServiceHandle(scm);
+                return Err(e);
+            }
+
+            //Stop first so the SCM can remove it now rather than at the next reboot
+            let mut status: SERVICE_STATUS = std::mem::zeroed();
+            if ControlService(service, SERVICE_CONTROL_STOP, &mut status) != 0 {
+                let deadline = Instant::now() + Duration::from_secs(30);
+                while QueryServiceStatus(service, &mut status) != 0 && status.dwCurrentState != SERVICE_STOPPED && Instant::now() < deadline {
+                    std::thread::sleep(POLL);
+                }
+            }
+
+            let deleted = DeleteService(service) != 0;
+            let e = last_error(&format!("Cannot delete service {}", name));
+            CloseServiceHandle(service);
+            CloseServiceHandle(scm);
+            if !deleted {
+                return Err(e);
+            }
+        }
+
+        println!("Removed service {}", name);
+        return Ok(());
+    }
+}
+
+//systemd: sd_notify readiness and watchdog, signals, and unit files for install/uninstall
+#[cfg(not(windows))]
+mod systemd {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use std::sync::atomic::AtomicU64;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const UNIT_DIR: &str = "/etc/systemd/system";
+
+    //Must be longer than one collection, which includes the load sample and network timeouts
+    const WATCHDOG_SECONDS: u32 = 120;
+
+    const SIGHUP: i32 = 1;
+    const SIGINT: i32 = 2;
+    const SIGTERM: i32 = 15;
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    //Only touches atomics, which is all a signal handler may safely do
+    extern "C" fn on_signal(signum: i32) {
+        if signum == SIGHUP {
+            RELOAD.store(true, Ordering::SeqCst);
+        } else {
+            STOP.store(true, Ordering::SeqCst);
+        }
+    }
+
+    pub(super) fn handle_signals() {
+        unsafe {
+            signal(SIGHUP, on_signal);
+            signal(SIGINT, on_signal);
+            signal(SIGTERM, on_signal);
+        }
+    }
+
+    //Send a state change to the service manager when started as Type=notify
+    pub(super) fn notify(state: &str) {
+        let path = match std::env::var_os("NOTIFY_SOCKET") {
+            Some(p) => p,
+            None => return,
+        };
+        //Abstract socket names (@...) are not reachable through std, systemd itself uses a path
+        if path.to_string_lossy().starts_with('@') {
+            return;
+        }
+        if let Ok(socket) = UnixDatagram::unbound() {
+            let _ = socket.send_to(state.as_bytes(), &path);
+        }
+    }
+
+    //Milliseconds since the epoch of the last WATCHDOG=1
+    static LAST_PING: AtomicU64 = AtomicU64::new(0);
+
+    //Ping at half of WatchdogSec, only while the loop is alive and waiting
+    pub(super) fn watchdog() {
+        let usec: u64 = match std::env::var("WATCHDOG_USEC").ok().and_then(|v| v.parse().ok()) {
+            Some(u) => u,
+            None => return,
+        };
+        if let Some(pid) = std::env::var("WATCHDOG_PID").ok().and_then(|p| p.parse::<u32>().ok()) {
+            if pid != std::process::id() {
+                return;
+            }
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        if now.saturating_sub(LAST_PING.load(Ordering::SeqCst)) >= usec / 2000 {
+            LAST_PING.store(now, Ordering::SeqCst);
+            notify("WATCHDOG=1");
+        }
+    }
+
+    //systemd command line quoting: double quotes around arguments with spaces, and % and $
+    //doubled so they are not taken as specifiers or variables
+    pub(super) fn quote(arg: &str) -> String {
+        let escaped = arg.replace('%', "%%").replace('$', "$$");
+        if !escaped.is_empty() && !escaped.contains([' ', '\t', '"', '\\', '\'']) {
+            return escaped;
+        }
+        return format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""));
+    }
+
+    fn systemctl(args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let status = Command::new("systemctl").args(args).status().map_err(|e| format!("systemctl: {}", e))?;
+        if !status.success() {
+            return Err(format!("systemctl {} failed ({})", args.join(" "), status).into());
+        }
+        return Ok(());
+    }
+
+    pub(super) fn install(name: &str, args: &[String]) -> Result<(), Box<dyn Error>> {
+        let exe = std::env::current_exe()?;
+        let mut command = format!("{} service run", quote(&exe.to_string_lossy()));
+        for arg in args {
+            command.push(' ');
+            command.push_str(&quote(arg));
+        }
+
+        let unit = format!(
+            "[Unit]\n\
+             Description=uptime {}\n\
+             Wants=network-online.target\n\
+             After=network-online.target\n\
+             \n\
+             [Service]\n\
+             Type=notify\n\
+             ExecStart={}\n\
+             ExecReload=/bin/kill -HUP $MAINPID\n\
+             WatchdogSec={}\n\
+             Restart=on-failure\n\
+             RuntimeDirectory=uptime\n\
+             RuntimeDirectoryPreserve=yes\n\
+             \n\
+             [Install]\n\
+             WantedBy=multi-user.target\n",
+            args[0], command, WATCHDOG_SECONDS
+        );
+
+        let path = PathBuf::from(UNIT_DIR).join(format!("{}.service", name));
+        std::fs::write(&path, unit).map_err(|e| format!("{}: {} (run as root)", path.display(), e))?;
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", name])?;
+
+        println!("Installed and started {}: {}", path.display(), command);
+        return Ok(());
+    }
+
+    pub(super) fn uninstall(name: &str) -> Result<(), Box<dyn Error>> {
+        let path = PathBuf::from(UNIT_DIR).join(format!("{}.service", name));
+        if !path.is_file() {
+            return Err(format!("{}: no such unit file", path.display()).into());
+        }
+
+        systemctl(&["disable", "--now", name])?;
+        std::fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        systemctl(&["daemon-reload"])?;
+
+        println!("Removed {}", path.display());
+        return Ok(());
+    }
+}
+
+//Entry point for `uptime service install|uninstall|run|status`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime service install <record|push|otlp|agent> [OPTIONS] [--name NAME]\n       \
+                 uptime service uninstall <NAME|record|push|otlp|agent>\n       \
+                 uptime service run <record|push|otlp|agent> [OPTIONS]\n       \
+                 uptime service status";
+    //The whole command line, flags included, to keep at install and to read again on reload
+    let raw: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(|a| a.as_str()) {
+        Some("install") => {
+            let (name, mode) = install_args(&raw).map_err(|e| format!("{}\n{}", e, usage))?;
+            let name = service_name(name.as_ref(), &mode[0]);
+            #[cfg(windows)]
+            return scm::install(&name, &mode);
+            #[cfg(not(windows))]
+            return systemd::install(&name, &mode);
+        }
+        Some("uninstall") if args.len() == 2 => {
+            let name = service_name(args.get(1), "");
+            #[cfg(windows)]
+            return scm::uninstall(&name);
+            #[cfg(not(windows))]
+            return systemd::uninstall(&name);
+        }
+        Some("run") if args.len() >= 2 => {
+            #[cfg(windows)]
+            return scm::dispatch(&raw, config, args[1..].to_vec());
+            #[cfg(not(windows))]
+            {
+                systemd::handle_signals();
+                return serve(&raw, config.clone(), args[1..].to_vec());
+            }
+        }
+        Some("status") if args.len() == 1 => ipc::status(config),
+        _ => Err(usage.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        return args.iter().map(|a| a.to_string()).collect();
+    }
+
+    #[test]
+    fn install_arguments() {
+        let (name, args) = install_args(&strings(&["service", "install", "record", "--interval", "60"])).unwrap();
+        assert_eq!((name, args), (None, strings(&["record", "--interval", "60"])));
+
+        //--name before or after the mode, and global flags before `service` are kept
+        let raw = strings(&["--config", "/etc/u.toml", "service", "install", "--name", "rec", "record", "--dir", "/var/u"]);
+        let (name, args) = install_args(&raw).unwrap();
+        assert_eq!(name.as_deref(), Some("rec"));
+        assert_eq!(args, strings(&["record", "--dir", "/var/u", "--config", "/etc/u.toml"]));
+        let (name, args) = install_args(&strings(&["service", "install", "push", "--once", "--name", "p1"])).unwrap();
+        assert_eq!((name.as_deref(), args), (Some("p1"), strings(&["push", "--once"])));
+
+        assert!(install_args(&strings(&["service", "install"])).is_err());
+        assert!(install_args(&strings(&["service", "install", "check"])).is_err());
+        assert!(install_args(&strings(&["service", "install", "--name", "x", "--interval", "5"])).is_err());
+        assert!(install_args(&strings(&["service", "install", "record", "--name"])).is_err());
+    }
+
+    #[test]
+    fn service_names() {
+        assert_eq!(service_name(None, "record"), "uptime-record");
+        assert_eq!(service_name(Some(&"collector".to_string()), "push"), "collector");
+        //A mode given to uninstall means the default name for it
+        assert_eq!(service_name(Some(&"agent".to_string()), ""), "uptime-agent");
+    }
+
+    #[test]
+    fn run_arguments() {
+        assert_eq!(mode_args(&strings(&["service", "run", "otlp", "--once"])), strings(&["otlp", "--once"]));
+        assert_eq!(mode_args(&strings(&["service", "run"])), Vec::<String>::new());
+        assert_eq!(mode_args(&strings(&["service", "status"])), Vec::<String>::new());
+    }
+
+    //Split a command line the way CommandLineToArgvW does, to check the quoting round trips
+    #[cfg(windows)]
+    fn argv(line: &str) -> Vec<String> {
+        let chars: Vec<char> = line.chars().collect();
+        let (mut args, mut arg, mut in_arg, mut quoted) = (vec![], String::new(), false, false);
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c == '\\' {
+                let start = i;
+                while i < chars.len() && chars[i] == '\\' {
+                    i += 1;
+                }
+                let n = i - start;
+                if chars.get(i) == Some(&'"') {
+                    arg.push_str(&"\\".repeat(n / 2));
+                    if !n.is_multiple_of(2) {
+                        arg.push('"');
+                        i += 1;
+                    }
+                } else {
+                    arg.push_str(&"\\".repeat(n));
+                }
+                in_arg = true;
+                continue;
+            }
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    in_arg = true;
+                }
+                ' ' | '\t' if !quoted => {
+                    if in_arg {
+                        args.push(std::mem::take(&mut arg));
+                        in_arg = false;
+                    }
+                }
+                c => {
+                    arg.push(c);
+                    in_arg = true;
+                }
+            }
+            i += 1;
+        }
+        if in_arg {
+            args.push(arg);
+        }
+        return args;
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_quoting() {
+        use super::scm::quote;
+
+        assert_eq!(quote("record"), "record");
+        assert_eq!(quote(r"C:\tools\uptime.exe"), r"C:\tools\uptime.exe");
+        assert_eq!(quote(""), r#""""#);
+        assert_eq!(quote(r"C:\Program Files\uptime\uptime.exe"), r#""C:\Program Files\uptime\uptime.exe""#);
+        //Trailing backslashes are doubled so they do not escape the closing quote
+        assert_eq!(quote(r"C:\My Data\"), r#""C:\My Data\\""#);
+        assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+        //Backslashes before a quote are doubled, plus one for the quote
+        assert_eq!(quote(r#"a\"b"#), r#""a\\\"b""#);
+
+        let args = [r"C:\My Data\", r#"a\\"b c"#, "", "tab\there", r"\\server\share\", r#"""#, "plain"];
+        let line = args.iter().map(|a| quote(a)).collect::<Vec<String>>().join(" ");
+        assert_eq!(argv(&line), strings(&args));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn systemd_quoting() {
+        use super::systemd::quote;
+
+        assert_eq!(quote("record"), "record");
+        assert_eq!(quote("/usr/local/bin/uptime"), "/usr/local/bin/uptime");
+        assert_eq!(quote(""), r#""""#);
+        //% starts a specifier and $ a variable, both are doubled
+        assert_eq!(quote("100%"), "100%%");
+        assert_eq!(quote("$HOME/data"), "$$HOME/data");
+        assert_eq!(quote("50% of $X"), r#""50%% of $$X""#);
+        assert_eq!(quote("/opt/my tools/uptime"), r#""/opt/my tools/uptime""#);
+        assert_eq!(quote(r#"a"b"#), r#""a\"b""#);
+        assert_eq!(quote(r"C:\x"), r#""C:\\x""#);
+        assert_eq!(quote("it's"), r#""it's""#);
+    }
+}
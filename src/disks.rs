@@ -9,6 +9,7 @@ use windows::Win32::Storage::FileSystem::CreateFileW;
 use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
 use windows::Win32::Storage::FileSystem::GetDriveTypeW;
 use windows::Win32::Storage::FileSystem::GetLogicalDrives;
+use windows::Win32::Storage::FileSystem::GetVolumeInformationW;
 use windows::Win32::Storage::FileSystem::FILE_ACCESS_FLAGS;
 use windows::Win32::Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES;
 use windows::Win32::Storage::FileSystem::FILE_SHARE_READ;
@@ -51,10 +52,32 @@ pub unsafe fn get_drive_size(handle: HANDLE) -> u64 {
     }
 }
 
+pub unsafe fn get_file_system(mount_point: &str) -> String {
+    let mut fs_name = [0u16; 32];
+    let result = GetVolumeInformationW(
+        mount_point,
+        PWSTR(std::ptr::null_mut()),
+        0,
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        std::ptr::null_mut(),
+        PWSTR(fs_name.as_mut_ptr()),
+        fs_name.len() as u32,
+    );
+
+    if BOOL::as_bool(result) {
+        let len = fs_name.iter().position(|&c| c == 0).unwrap_or(fs_name.len());
+        String::from_utf16_lossy(&fs_name[..len])
+    } else {
+        String::new()
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct Disks {
     pub drive: String,
     pub drive_type: DriveType,
+    pub file_system: String,
     pub total_bytes: u64,
     pub total_gigabytes: u64,
     pub available_bytes: u64,
@@ -140,6 +163,7 @@ impl Disks {
 
                     self.available_bytes = tmp;
                     self.available_gigabytes = self.available_bytes / (1024 * 1024 * 1024);
+                    self.file_system = get_file_system(&str_mount_point);
                     self.drive = str_mount_point;
                     self.drive_type = DriveType::get(drive_type);
 
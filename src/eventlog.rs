@@ -0,0 +1,120 @@
+use windows::Win32::Foundation::GetLastError;
+use windows::Win32::Foundation::ERROR_INSUFFICIENT_BUFFER;
+use windows::Win32::System::EventLog::CloseEventLog;
+use windows::Win32::System::EventLog::OpenEventLogW;
+use windows::Win32::System::EventLog::ReadEventLogW;
+use windows::Win32::System::EventLog::EVENTLOGRECORD;
+use windows::Win32::System::EventLog::EVENTLOG_SEQUENTIAL_READ;
+use windows::Win32::System::EventLog::READ_EVENT_LOG_READ_FLAGS;
+
+use std::error::Error;
+use std::mem::size_of;
+
+const EVENTLOG_FORWARDS_READ: u32 = 0x4;
+const EVENTLOG_BACKWARDS_READ: u32 = 0x8;
+
+#[derive(Default, Debug, Clone)]
+pub(crate) struct LogEvent {
+    pub(crate) id: u32,
+    //Unix timestamp in seconds
+    pub(crate) time: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Direction {
+    //Oldest record first, reads the whole log
+    Forwards,
+    //Newest record first, stops at the first record older than the given unix time
+    BackwardsUntil(i64),
+    //Newest record first, stops at the first wanted record
+    Latest,
+}
+
+unsafe fn source_name(buffer: &[u8], offset: usize) -> String {
+    //The source name follows the fixed part of the record
+    let name_ptr = buffer.as_ptr().add(offset + size_of::<EVENTLOGRECORD>()) as *const u16;
+    let mut name: Vec<u16> = vec![];
+    loop {
+        let c = std::ptr::read_unaligned(name_ptr.add(name.len()));
+        if c == 0 {
+            break;
+        }
+        name.push(c);
+    }
+    String::from_utf16_lossy(&name)
+}
+
+//Read the classic event log API records for the given log name, keeping only the
+//records where wanted(source, event id) returns true
+pub(crate) fn read_events<F>(log: &str, direction: Direction, wanted: F) -> Result<Vec<LogEvent>, Box<dyn Error>>
+where
+    F: Fn(&str, u32) -> bool,
+{
+    let mut events = vec![];
+
+    let flags = match direction {
+        Direction::Forwards => EVENTLOG_SEQUENTIAL_READ.0 | EVENTLOG_FORWARDS_READ,
+        Direction::BackwardsUntil(_) | Direction::Latest => EVENTLOG_SEQUENTIAL_READ.0 | EVENTLOG_BACKWARDS_READ,
+    };
+
+    unsafe {
+        let handle = OpenEventLogW(None, log);
+        if handle.0 == 0 {
+            return Err(format!("OpenEventLogW failed for {}", log).into());
+        }
+
+        let mut buffer: Vec<u8> = vec![0; 64 * 1024];
+
+        'read: loop {
+            let mut read = 0_u32;
+            let mut needed = 0_u32;
+
+            let ok = ReadEventLogW(
+                handle,
+                READ_EVENT_LOG_READ_FLAGS(flags),
+                0,
+                buffer.as_mut_ptr() as _,
+                buffer.len() as u32,
+                &mut read,
+                &mut needed,
+            );
+
+            if !ok.as_bool() {
+                if GetLastError() == ERROR_INSUFFICIENT_BUFFER {
+                    buffer.resize(needed as usize, 0);
+                    continue;
+                }
+                //ERROR_HANDLE_EOF once every record has been read
+                break;
+            }
+
+            let mut offset = 0_usize;
+            while offset < read as usize {
+                let record = std::ptr::read_unaligned(buffer.as_ptr().add(offset) as *const EVENTLOGRECORD);
+                let time = record.TimeGenerated as i64;
+
+                if let Direction::BackwardsUntil(since) = direction {
+                    if time < since {
+                        break 'read;
+                    }
+                }
+
+                //The top bits hold the severity and facility
+                let id = record.EventID & 0xFFFF;
+
+                if wanted(&source_name(&buffer, offset), id) {
+                    events.push(LogEvent { id, time });
+                    if direction == Direction::Latest {
+                        break 'read;
+                    }
+                }
+
+                offset += record.Length as usize;
+            }
+        }
+
+        CloseEventLog(handle);
+    }
+
+    return Ok(events);
+}
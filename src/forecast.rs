@@ -0,0 +1,334 @@
+use crate::check::parse_duration;
+use crate::config::Config;
+use crate::history::format_time;
+use crate::record;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+//Theil-Sen is O(n²) in points, so history is averaged down to at most this many first
+const MAX_POINTS: usize = 200;
+
+//A flat drive with a little noise can trend upwards by a few bytes a day, anything further
+//out than this is treated as not growing
+const MAX_DAYS: f64 = 36500.0;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Forecast {
+    //Drive letter as recorded, e.g. "C"
+    pub(crate) drive: String,
+    pub(crate) used: f64,
+    pub(crate) total: f64,
+    //Bytes per day, negative when usage is shrinking
+    pub(crate) growth: f64,
+    //When usage reaches the threshold, None if it is not growing (or not within MAX_DAYS)
+    pub(crate) full_at: Option<i64>,
+    pub(crate) r_squared: f64,
+    pub(crate) span_days: f64,
+    pub(crate) samples: usize,
+}
+
+impl Forecast {
+    //How far to trust full_at: a straight, long history is high, a short or noisy one low
+    pub(crate) fn confidence(&self) -> &'static str {
+        if self.samples < 10 || self.span_days < 1.0 {
+            "low"
+        } else if self.r_squared >= 0.9 && self.span_days >= 7.0 {
+            "high"
+        } else if self.r_squared >= 0.6 {
+            "medium"
+        } else {
+            "low"
+        }
+    }
+}
+
+pub(crate) fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+//Theil-Sen estimator: the median slope over all pairs of points, so a one-off cleanup or
+//a burst of temporary files does not swing the trend. Returns (slope, intercept)
+pub(crate) fn theil_sen(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let mut slopes = vec![];
+    for (i, (x1, y1)) in points.iter().enumerate() {
+        for (x2, y2) in &points[i + 1..] {
+            if x2 != x1 {
+                slopes.push((y2 - y1) / (x2 - x1));
+            }
+        }
+    }
+    if slopes.is_empty() {
+        return None;
+    }
+    let slope = median(&mut slopes);
+    let intercept = median(&mut points.iter().map(|(x, y)| y - slope * x).collect::<Vec<f64>>());
+    return Some((slope, intercept));
+}
+
+//Share of the variance the line explains, 1.0 for a perfect fit
+pub(crate) fn r_squared(points: &[(f64, f64)], slope: f64, intercept: f64) -> f64 {
+    let mean = points.iter().map(|(_, y)| y).sum::<f64>() / points.len().max(1) as f64;
+    let total: f64 = points.iter().map(|(_, y)| (y - mean).powi(2)).sum();
+    let residual: f64 = points.iter().map(|(x, y)| (y - (slope * x + intercept)).powi(2)).sum();
+    if total == 0.0 {
+        return if residual == 0.0 { 1.0 } else { 0.0 };
+    }
+    return (1.0 - residual / total).max(0.0);
+}
+
+//Average consecutive points into at most MAX_POINTS groups
+fn thin(points: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    if points.len() <= MAX_POINTS {
+        return points;
+    }
+    let size = points.len().div_ceil(MAX_POINTS);
+    return points
+        .chunks(size)
+        .map(|c| {
+            let n = c.len() as f64;
+            (c.iter().map(|(x, _)| x).sum::<f64>() / n, c.iter().map(|(_, y)| y).sum::<f64>() / n)
+        })
+        .collect();
+}
+
+//When usage reaches target at slope bytes per day from the last sample, which is already
+//the case when it is at or past the target
+fn full_at(last: (i64, f64), slope: f64, target: f64) -> Option<i64> {
+    if last.1 >= target {
+        return Some(last.0);
+    }
+    if slope > 0.0 && (target - last.1) / slope <= MAX_DAYS {
+        return Some(last.0 + ((target - last.1) / slope * 86400.0) as i64);
+    }
+    return None;
+}
+
+//Forecast every drive with recorded history since `from`, threshold is a percentage of the size
+pub(crate) fn forecast_disks(dir: &Path, from: i64, now: i64, threshold: f64) -> Result<Vec<Forecast>, Box<dyn Error>> {
+    let mut list = vec![];
+
+    for name in record::metric_names(dir)? {
+        let drive = match name.strip_prefix("disk_").and_then(|n| n.strip_suffix("_used")) {
+            Some(d) => d.to_string(),
+            None => continue,
+        };
+        let used = record::query(dir, &name, from, now)?;
+        let free: BTreeMap<i64, f64> = record::query(dir, &format!("disk_{}_free", drive), from, now)?.into_iter().collect();
+        let (first, last) = match (used.first(), used.last()) {
+            (Some(f), Some(l)) => (*f, *l),
+            _ => continue,
+        };
+
+        //x in days since the first sample keeps the slope in bytes per day
+        let points = thin(used.iter().map(|(t, v)| ((t - first.0) as f64 / 86400.0, *v)).collect());
+        let (slope, intercept) = theil_sen(&points).unwrap_or((0.0, last.1));
+        let total = last.1 + free.get(&last.0).copied().unwrap_or(0.0);
+
+        list.push(Forecast {
+            drive,
+            used: last.1,
+            total,
+            growth: slope,
+            full_at: full_at(last, slope, total * threshold / 100.0),
+            r_squared: r_squared(&points, slope, intercept),
+            span_days: (last.0 - first.0) as f64 / 86400.0,
+            samples: used.len(),
+        });
+    }
+
+    return Ok(list);
+}
+
+pub(crate) fn print_forecasts(list: &[Forecast], threshold: f64, now: i64, config: &Config) {
+    let unit = config.disk_unit;
+    for f in list {
+        let pct = if f.total > 0.0 { f.used * 100.0 / f.total } else { 0.0 };
+        let full = match f.full_at {
+            Some(t) if t <= now => "now".to_string(),
+            Some(t) => format!("{} (in {}d)", format_time(t), (t - now) / 86400),
+            None => "never (not growing)".to_string(),
+        };
+        println!(
+            "Disk: {}: | Used: {} {u} of {} {u} ({:.1}%) | Growth: {} {u}/day | Full ({}%): {} | Confidence: {} (R² {:.2}, {:.1} days, {} samples)",
+            f.drive,
+            unit.format_bytes(f.used as u64),
+            unit.format_bytes(f.total as u64),
+            pct,
+            if f.growth < 0.0 { format!("-{}", unit.format_bytes(-f.growth as u64)) } else { unit.format_bytes(f.growth as u64) },
+            threshold,
+            full,
+            f.confidence(),
+            f.r_squared,
+            f.span_days,
+            f.samples,
+            u = unit.label()
+        );
+    }
+}
+
+//Entry point for `uptime forecast [--threshold PCT] [--history DURATION] [--dir PATH]`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime forecast [--threshold PCT] [--history DURATION] [--dir PATH]";
+    let mut threshold = config.forecast_threshold;
+    let mut history = config.forecast_history;
+    let mut dir: PathBuf = config.record_dir.clone();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match (arg.as_str(), iter.next()) {
+            ("--threshold", Some(v)) => {
+                threshold = v.trim_end_matches('%').parse().ok().filter(|t| *t > 0.0 && *t <= 100.0).ok_or(usage)?
+            }
+            ("--history", Some(v)) => history = parse_duration(v)?,
+            ("--dir", Some(v)) => dir = PathBuf::from(v),
+            _ => return Err(usage.into()),
+        }
+    }
+
+    let now = record::now();
+    let list = forecast_disks(&dir, now - history as i64, now, threshold)?;
+    if list.is_empty() {
+        return Err(format!("No disk history in {}, run `uptime record` first", dir.display()).into());
+    }
+    print_forecasts(&list, threshold, now, config);
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86400;
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+    fn line(x: f64) -> (f64, f64) {
+        return (x, 2.0 * x + 5.0);
+    }
+
+    #[test]
+    fn exact_line() {
+        let points: Vec<(f64, f64)> = (0..10).map(|x| line(x as f64)).collect();
+        assert_eq!(theil_sen(&points), Some((2.0, 5.0)));
+        assert_eq!(r_squared(&points, 2.0, 5.0), 1.0);
+
+        //No pair with distinct x, no slope
+        assert_eq!(theil_sen(&[(1.0, 1.0)]), None);
+        assert_eq!(theil_sen(&[(1.0, 1.0), (1.0, 2.0)]), None);
+    }
+
+    #[test]
+    fn outlier_is_ignored() {
+        let mut points: Vec<(f64, f64)> = (0..10).map(|x| line(x as f64)).collect();
+        points[6].1 = 1000.0;
+        assert_eq!(theil_sen(&points), Some((2.0, 5.0)));
+        //The line ignores it but the fit does not
+        assert!(r_squared(&points, 2.0, 5.0) < 0.5);
+
+        //A flat series is explained perfectly by a flat line and not at all by any other
+        let flat = [(0.0, 3.0), (1.0, 3.0), (2.0, 3.0)];
+        assert_eq!(theil_sen(&flat), Some((0.0, 3.0)));
+        assert_eq!(r_squared(&flat, 0.0, 3.0), 1.0);
+        assert_eq!(r_squared(&flat, 1.0, 3.0), 0.0);
+    }
+
+    #[test]
+    fn medians() {
+        assert_eq!(median(&mut [3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&mut [4.0, 1.0, 3.0, 2.0]), 2.5);
+    }
+
+    #[test]
+    fn thinning() {
+        let points: Vec<(f64, f64)> = (0..MAX_POINTS).map(|x| (x as f64, x as f64)).collect();
+        assert_eq!(thin(points.clone()), points);
+
+        let points: Vec<(f64, f64)> = (0..1000).map(|x| (x as f64, 10.0 * x as f64)).collect();
+        let thinned = thin(points);
+        assert_eq!(thinned.len(), MAX_POINTS);
+        assert_eq!((thinned[0], thinned[199]), ((2.0, 20.0), (997.0, 9970.0)));
+
+        //One point over the limit halves the series rather than dropping a point
+        let thinned = thin((0..=MAX_POINTS).map(|x| (x as f64, 0.0)).collect());
+        assert_eq!((thinned.len(), thinned[100].0), (101, 200.0));
+    }
+
+    #[test]
+    fn time_to_full() {
+        let last = (1_700_000_000, 50.0);
+        assert_eq!(full_at(last, 10.0, 90.0), Some(last.0 + 4 * DAY));
+        //Flat or shrinking usage never fills the drive
+        assert_eq!(full_at(last, 0.0, 90.0), None);
+        assert_eq!(full_at(last, -5.0, 90.0), None);
+        //Nor does growth so slow it would take more than MAX_DAYS
+        assert_eq!(full_at(last, 40.0 / (MAX_DAYS + 1.0), 90.0), None);
+        //Already past the threshold, whatever the trend
+        assert_eq!(full_at((last.0, 95.0), -5.0, 90.0), Some(last.0));
+        assert_eq!(full_at((last.0, 90.0), 0.0, 90.0), Some(last.0));
+    }
+
+    #[test]
+    fn confidence_levels() {
+        let f = |r_squared: f64, span_days: f64, samples: usize| Forecast {
+            drive: "C".to_string(),
+            used: 0.0,
+            total: 0.0,
+            growth: 0.0,
+            full_at: None,
+            r_squared,
+            span_days,
+            samples,
+        };
+        assert_eq!(f(0.95, 10.0, 100).confidence(), "high");
+        assert_eq!(f(0.95, 3.0, 100).confidence(), "medium");
+        assert_eq!(f(0.7, 10.0, 100).confidence(), "medium");
+        assert_eq!(f(0.3, 10.0, 100).confidence(), "low");
+        assert_eq!(f(1.0, 10.0, 5).confidence(), "low");
+        assert_eq!(f(1.0, 0.5, 100).confidence(), "low");
+    }
+
+    #[test]
+    fn drives_from_the_store() {
+        let dir = std::env::temp_dir().join(format!("uptime-forecast-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        //C: grows a GiB a day on a 100 GiB drive, D: shrinks
+        let start = 1_700_000_000;
+        let text: String = (0..10)
+            .map(|d| {
+                let (c, d_used) = ((10 + d) as f64 * GIB, (50 - d) as f64 * GIB);
+                format!(
+                    "{} disk_C_used={} disk_C_free={} disk_D_used={} disk_D_free={} load=1\n",
+                    start + d * DAY,
+                    c,
+                    100.0 * GIB - c,
+                    d_used,
+                    100.0 * GIB - d_used
+                )
+            })
+            .collect();
+        std::fs::write(dir.join("raw.log"), text).unwrap();
+
+        let list = forecast_disks(&dir, start, start + 9 * DAY, 90.0);
+        let _ = std::fs::remove_dir_all(&dir);
+        let list = list.unwrap();
+        assert_eq!(list.len(), 2);
+
+        let c = &list[0];
+        assert_eq!((c.drive.as_str(), c.used, c.total, c.growth), ("C", 19.0 * GIB, 100.0 * GIB, GIB));
+        assert_eq!(c.full_at, Some(start + 9 * DAY + 71 * DAY));
+        assert_eq!((c.r_squared, c.span_days, c.samples, c.confidence()), (1.0, 9.0, 10, "high"));
+
+        let d = &list[1];
+        assert_eq!((d.drive.as_str(), d.growth, d.full_at), ("D", -GIB, None));
+    }
+}
@@ -0,0 +1,341 @@
+use crate::utmp;
+
+use chrono::{DateTime, Local, SecondsFormat, TimeZone, Utc};
+
+use std::error::Error;
+use std::path::Path;
+
+//Event log source and ids written by the Event Log service itself
+const EVENTLOG_SOURCE: &str = "EventLog";
+const EVENT_LOG_STARTED: u32 = 6005;
+const EVENT_LOG_STOPPED: u32 = 6006;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BootEvent {
+    Boot(i64),
+    Shutdown(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ShutdownKind {
+    Clean,
+    Unclean,
+    Running,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BootRecord {
+    pub(crate) boot: i64,
+    pub(crate) shutdown: Option<i64>,
+    pub(crate) kind: ShutdownKind,
+}
+
+impl BootEvent {
+    fn time(&self) -> i64 {
+        match self {
+            BootEvent::Boot(t) | BootEvent::Shutdown(t) => *t,
+        }
+    }
+}
+
+impl BootRecord {
+    //Seconds the system was up, None when the shutdown time was never recorded
+    pub(crate) fn duration(&self, now: i64) -> Option<i64> {
+        match (self.kind, self.shutdown) {
+            (ShutdownKind::Running, _) => Some(now - self.boot),
+            (_, Some(end)) => Some(end - self.boot),
+            _ => None,
+        }
+    }
+}
+
+//Pair boots with the shutdown that followed them. A boot with no shutdown
+//before the next boot means the machine crashed or lost power
+pub(crate) fn build_history(mut events: Vec<BootEvent>) -> Vec<BootRecord> {
+    let mut records: Vec<BootRecord> = vec![];
+    events.sort_by_key(|e| e.time());
+
+    for event in events {
+        match event {
+            BootEvent::Boot(t) => {
+                if let Some(last) = records.last_mut() {
+                    if last.kind == ShutdownKind::Running {
+                        last.kind = ShutdownKind::Unclean;
+                    }
+                }
+                records.push(BootRecord {
+                    boot: t,
+                    shutdown: None,
+                    kind: ShutdownKind::Running,
+                });
+            }
+            BootEvent::Shutdown(t) => {
+                if let Some(last) = records.last_mut() {
+                    if last.kind == ShutdownKind::Running {
+                        last.shutdown = Some(t);
+                        last.kind = ShutdownKind::Clean;
+                    }
+                }
+            }
+        }
+    }
+
+    return records;
+}
+
+fn extract<'a>(text: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let from = text.find(start)? + start.len();
+    let len = text[from..].find(end)?;
+    Some(&text[from..from + len])
+}
+
+//Parse the output of
+//wevtutil qe System /q:"*[System[Provider[@Name='EventLog'] and (EventID=6005 or EventID=6006)]]" /f:xml
+pub(crate) fn parse_event_xml(text: &str) -> Result<Vec<BootEvent>, Box<dyn Error>> {
+    let mut events = vec![];
+
+    for chunk in text.split("<Event ").skip(1) {
+        let provider = extract(chunk, "<Provider Name='", "'").unwrap_or("");
+        if provider != EVENTLOG_SOURCE {
+            continue;
+        }
+
+        let id = extract(chunk, "<EventID", "</EventID>")
+            .and_then(|s| s.rsplit('>').next())
+            .and_then(|s| s.trim().parse::<u32>().ok());
+        let time = extract(chunk, "<TimeCreated SystemTime='", "'")
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+            .map(|dt| dt.timestamp());
+
+        match (id, time) {
+            (Some(EVENT_LOG_STARTED), Some(t)) => events.push(BootEvent::Boot(t)),
+            (Some(EVENT_LOG_STOPPED), Some(t)) => events.push(BootEvent::Shutdown(t)),
+            (Some(_), Some(_)) => {}
+            _ => return Err("Malformed event in XML export".into()),
+        }
+    }
+
+    return Ok(events);
+}
+
+//reboot records mark a boot and the shutdown run level record marks a clean shutdown
+pub(crate) fn parse_wtmp(records: &[utmp::UtmpRecord]) -> Vec<BootEvent> {
+    return records
+        .iter()
+        .filter_map(|r| match r.kind {
+            utmp::BOOT_TIME => Some(BootEvent::Boot(r.time)),
+            utmp::RUN_LVL if r.user == "shutdown" => Some(BootEvent::Shutdown(r.time)),
+            _ => None,
+        })
+        .collect();
+}
+
+pub(crate) fn read_file(path: &Path) -> Result<Vec<BootEvent>, Box<dyn Error>> {
+    let bytes = match std::fs::read(path) {
+        Ok(b) => b,
+        Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+    };
+
+    //XML exports are text, anything else is treated as a binary wtmp file
+    let text = String::from_utf8_lossy(&bytes);
+    if text.trim_start().starts_with('<') {
+        parse_event_xml(&text)
+    } else {
+        Ok(parse_wtmp(&utmp::parse(&bytes)))
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn read_system_log() -> Result<Vec<BootEvent>, Box<dyn Error>> {
+    use crate::eventlog::{read_events, Direction};
+
+    let events = read_events("System", Direction::Forwards, |source, id| {
+        source == EVENTLOG_SOURCE && (id == EVENT_LOG_STARTED || id == EVENT_LOG_STOPPED)
+    })?;
+
+    return Ok(events
+        .iter()
+        .map(|e| match e.id {
+            EVENT_LOG_STARTED => BootEvent::Boot(e.time),
+            _ => BootEvent::Shutdown(e.time),
+        })
+        .collect());
+}
+
+#[cfg(not(windows))]
+pub(crate) fn read_system_log() -> Result<Vec<BootEvent>, Box<dyn Error>> {
+    read_file(Path::new("/var/log/wtmp"))
+}
+
+//FILETIME (100ns intervals since 1601) to unix seconds
+#[cfg(windows)]
+pub(crate) fn filetime_to_unix(ft: i64) -> i64 {
+    ft / 10_000_000 - 11_644_473_600
+}
+
+//Times out of chrono's range fall back to the raw unix seconds
+pub(crate) fn format_time(t: i64) -> String {
+    match Local.timestamp_opt(t, 0).earliest() {
+        Some(dt) => dt.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => t.to_string(),
+    }
+}
+
+pub(crate) fn format_time_utc(t: i64) -> String {
+    match Utc.timestamp_opt(t, 0).single() {
+        Some(dt) => dt.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        None => t.to_string(),
+    }
+}
+
+//RFC 3339 with the local UTC offset, for times that other hosts read back
+pub(crate) fn format_time_rfc3339(t: i64) -> String {
+    match Local.timestamp_opt(t, 0).earliest() {
+        Some(dt) => dt.to_rfc3339_opts(SecondsFormat::Secs, false),
+        None => t.to_string(),
+    }
+}
+
+pub(crate) fn format_duration(secs: i64) -> String {
+    let secs = secs.max(0);
+    format!(
+        "{}d {:02}h {:02}m {:02}s",
+        secs / 86400,
+        (secs / 3600) % 24,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+//Entry point for `uptime history [--file <wtmp or wevtutil xml export>] [--limit N]`
+pub(crate) fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut file: Option<String> = None;
+    let mut limit: Option<usize> = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--file" => file = iter.next().cloned(),
+            "--limit" => limit = iter.next().and_then(|l| l.parse().ok()),
+            _ => return Err(format!("Unexpected argument '{}'\nusage: uptime history [--file PATH] [--limit N]", arg).into()),
+        }
+    }
+
+    let events = match file {
+        Some(path) => read_file(Path::new(&path))?,
+        None => read_system_log()?,
+    };
+
+    let now = Utc::now().timestamp();
+    let records = build_history(events);
+
+    println!("{:<21}{:<21}{:<18}Status", "Boot", "Shutdown", "Duration");
+    for r in records.iter().rev().take(limit.unwrap_or(usize::MAX)) {
+        let shutdown = r.shutdown.map(format_time).unwrap_or_else(|| "-".to_string());
+        let duration = r.duration(now).map(format_duration).unwrap_or_else(|| "-".to_string());
+        let status = match r.kind {
+            ShutdownKind::Clean => "clean",
+            ShutdownKind::Unclean => "unclean",
+            ShutdownKind::Running => "running",
+        };
+        println!("{:<21}{:<21}{:<18}{}", format_time(r.boot), shutdown, duration, status);
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOT: i64 = 1_700_000_000;
+
+    fn event_xml(id: u32, time: &str) -> String {
+        format!(
+            "<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'><System>\
+             <Provider Name='EventLog'/><EventID Qualifiers='32768'>{}</EventID>\
+             <TimeCreated SystemTime='{}'/></System></Event>",
+            id, time
+        )
+    }
+
+    fn wtmp_record(kind: i16, user: &str, time: i64) -> Vec<u8> {
+        let mut buf = vec![0u8; utmp::UTMP_RECORD_SIZE];
+        buf[0..2].copy_from_slice(&kind.to_le_bytes());
+        buf[44..44 + user.len()].copy_from_slice(user.as_bytes());
+        buf[340..344].copy_from_slice(&(time as i32).to_le_bytes());
+        return buf;
+    }
+
+    #[test]
+    fn boot_and_shutdown_from_event_xml() {
+        let xml = format!(
+            "<Events>{}{}</Events>",
+            event_xml(6005, "2023-11-14T22:13:20.0000000Z"),
+            event_xml(6006, "2023-11-14T23:13:20.5000000Z")
+        );
+        let events = parse_event_xml(&xml).unwrap();
+        assert_eq!(events, vec![BootEvent::Boot(BOOT), BootEvent::Shutdown(BOOT + 3600)]);
+
+        let history = build_history(events);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].kind, ShutdownKind::Clean);
+        assert_eq!(history[0].shutdown, Some(BOOT + 3600));
+        assert_eq!(history[0].duration(0), Some(3600));
+    }
+
+    #[test]
+    fn boot_without_shutdown_is_unclean() {
+        let xml = format!(
+            "{}{}",
+            event_xml(6005, "2023-11-14T22:13:20Z"),
+            event_xml(6005, "2023-11-15T22:13:20Z")
+        );
+        let history = build_history(parse_event_xml(&xml).unwrap());
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].kind, ShutdownKind::Unclean);
+        assert_eq!(history[0].shutdown, None);
+        assert_eq!(history[0].duration(BOOT + 100_000), None);
+        assert_eq!(history[1].kind, ShutdownKind::Running);
+        assert_eq!(history[1].duration(BOOT + 86_400 + 60), Some(60));
+    }
+
+    #[test]
+    fn other_events_are_skipped_and_malformed_ones_rejected() {
+        let other = event_xml(6013, "2023-11-14T22:13:20Z");
+        assert_eq!(parse_event_xml(&other).unwrap(), vec![]);
+        assert!(parse_event_xml(&event_xml(6005, "not a time")).is_err());
+    }
+
+    #[test]
+    fn wtmp_boot_and_shutdown() {
+        let mut bytes = wtmp_record(utmp::BOOT_TIME, "reboot", BOOT);
+        bytes.extend(wtmp_record(utmp::USER_PROCESS, "alice", BOOT + 10));
+        bytes.extend(wtmp_record(utmp::RUN_LVL, "shutdown", BOOT + 3600));
+        let events = parse_wtmp(&utmp::parse(&bytes));
+        assert_eq!(events, vec![BootEvent::Boot(BOOT), BootEvent::Shutdown(BOOT + 3600)]);
+        assert_eq!(build_history(events)[0].kind, ShutdownKind::Clean);
+    }
+
+    #[test]
+    fn utc_time_format() {
+        assert_eq!(format_time_utc(BOOT), "2023-11-14 22:13:20 UTC");
+        assert_eq!(format_time_utc(i64::MAX), i64::MAX.to_string());
+    }
+
+    #[test]
+    fn rfc3339_time_keeps_the_offset() {
+        let text = format_time_rfc3339(BOOT);
+        assert_eq!(DateTime::parse_from_rfc3339(&text).unwrap().timestamp(), BOOT);
+        assert_eq!(text.len(), "2023-11-14T22:13:20+00:00".len());
+    }
+
+    #[test]
+    fn torn_wtmp_record_is_ignored() {
+        let mut bytes = wtmp_record(utmp::BOOT_TIME, "reboot", BOOT);
+        //A record cut short by a crash while it was being written
+        bytes.extend(&wtmp_record(utmp::BOOT_TIME, "reboot", BOOT + 60)[..100]);
+        let events = parse_wtmp(&utmp::parse(&bytes));
+        assert_eq!(events, vec![BootEvent::Boot(BOOT)]);
+    }
+}
@@ -0,0 +1,424 @@
+use crate::config::Config;
+use crate::history::format_time;
+
+use chrono::Local;
+use colored::*;
+
+#[derive(Default, Debug, Clone)]
+pub struct Host {
+    pub hostname: String,
+    pub fqdn: String,
+    //DNS/Active Directory domain, or the workgroup of a Windows machine not joined to one
+    pub domain: String,
+    pub os_name: String,
+    pub edition: String,
+    pub version: String,
+    pub build: String,
+    pub kernel: String,
+    pub arch: String,
+    //Unix timestamps in seconds
+    pub install_date: Option<i64>,
+    pub last_patched: Option<i64>,
+    //Hypervisor and container layers we are running under, outermost first
+    pub virtualization: Vec<String>,
+    pub timezone: String,
+}
+
+//Hypervisor vendor from CPUID leaf 0x40000000, only valid when the hypervisor present bit is set
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[allow(unused_unsafe)]
+pub(crate) fn cpuid_hypervisor() -> Option<&'static str> {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::__cpuid;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::__cpuid;
+
+    let features = unsafe { __cpuid(1) };
+    if features.ecx & (1 << 31) == 0 {
+        return None;
+    }
+
+    let leaf = unsafe { __cpuid(0x4000_0000) };
+    let mut vendor = [0u8; 12];
+    vendor[..4].copy_from_slice(&leaf.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&leaf.ecx.to_le_bytes());
+    vendor[8..].copy_from_slice(&leaf.edx.to_le_bytes());
+
+    match &vendor {
+        b"Microsoft Hv" => {
+            //With virtualisation based security the host itself runs as the Hyper-V root partition,
+            //which holds the CreatePartitions privilege
+            let privileges = unsafe { __cpuid(0x4000_0003) };
+            if privileges.ebx & 1 != 0 {
+                None
+            } else {
+                Some("Hyper-V")
+            }
+        }
+        b"KVMKVMKVM\0\0\0" => Some("KVM"),
+        b"VMwareVMware" => Some("VMware"),
+        b"VBoxVBoxVBox" => Some("VirtualBox"),
+        b"XenVMMXenVMM" => Some("Xen"),
+        b"TCGTCGTCGTCG" => Some("QEMU"),
+        b" lrpepyh  vr" => Some("Parallels"),
+        b"bhyve bhyve " => Some("bhyve"),
+        _ => Some("unknown hypervisor"),
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub(crate) fn cpuid_hypervisor() -> Option<&'static str> {
+    None
+}
+
+fn utc_offset() -> String {
+    Local::now().format("%:z").to_string()
+}
+
+#[cfg(windows)]
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+#[cfg(windows)]
+pub(crate) fn from_wide(wide: &[u16]) -> String {
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..len])
+}
+
+//REG_SZ or REG_DWORD value under HKEY_LOCAL_MACHINE, as raw bytes
+#[cfg(windows)]
+unsafe fn reg_value(key: &str, value: &str, flags: u32) -> Option<Vec<u8>> {
+    use winapi::um::winreg::{RegGetValueW, HKEY_LOCAL_MACHINE};
+
+    let (key, value) = (to_wide(key), to_wide(value));
+    let mut buffer: Vec<u8> = vec![0; 512];
+    let mut size = buffer.len() as u32;
+    let status = RegGetValueW(
+        HKEY_LOCAL_MACHINE,
+        key.as_ptr(),
+        value.as_ptr(),
+        flags,
+        std::ptr::null_mut(),
+        buffer.as_mut_ptr() as _,
+        &mut size,
+    );
+    if status != 0 {
+        return None;
+    }
+    buffer.truncate(size as usize);
+    return Some(buffer);
+}
+
+#[cfg(windows)]
+unsafe fn reg_string(key: &str, value: &str) -> Option<String> {
+    use winapi::um::winreg::RRF_RT_REG_SZ;
+
+    let bytes = reg_value(key, value, RRF_RT_REG_SZ)?;
+    let wide: Vec<u16> = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    Some(from_wide(&wide))
+}
+
+#[cfg(windows)]
+unsafe fn reg_dword(key: &str, value: &str) -> Option<u32> {
+    use winapi::um::winreg::RRF_RT_REG_DWORD;
+
+    let bytes = reg_value(key, value, RRF_RT_REG_DWORD)?;
+    Some(u32::from_le_bytes([*bytes.first()?, *bytes.get(1)?, *bytes.get(2)?, *bytes.get(3)?]))
+}
+
+#[cfg(windows)]
+unsafe fn computer_name(format: winapi::um::sysinfoapi::COMPUTER_NAME_FORMAT) -> String {
+    use winapi::um::sysinfoapi::GetComputerNameExW;
+
+    let mut buffer = [0_u16; 256];
+    let mut size = buffer.len() as u32;
+    if GetComputerNameExW(format, buffer.as_mut_ptr(), &mut size) == 0 {
+        return String::new();
+    }
+    from_wide(&buffer[..size as usize])
+}
+
+#[cfg(windows)]
+unsafe fn workgroup() -> String {
+    use winapi::um::lmapibuf::NetApiBufferFree;
+    use winapi::um::lmjoin::{NetGetJoinInformation, NetSetupWorkgroupName};
+
+    let mut name = std::ptr::null_mut();
+    let mut status = 0;
+    if NetGetJoinInformation(std::ptr::null(), &mut name, &mut status) != 0 {
+        return String::new();
+    }
+
+    let mut len = 0;
+    while *name.add(len) != 0 {
+        len += 1;
+    }
+    let result = if status == NetSetupWorkgroupName {
+        String::from_utf16_lossy(std::slice::from_raw_parts(name, len))
+    } else {
+        String::new()
+    };
+    NetApiBufferFree(name as _);
+
+    return result;
+}
+
+//Most recent "Installation Successful" event from the Windows Update client
+#[cfg(windows)]
+fn last_update_install() -> Option<i64> {
+    use crate::eventlog::{read_events, Direction};
+
+    read_events("System", Direction::Latest, |source, id| {
+        source == "Microsoft-Windows-WindowsUpdateClient" && id == 19
+    })
+    .ok()?
+    .first()
+    .map(|e| e.time)
+}
+
+#[cfg(windows)]
+impl Host {
+    pub fn info() -> Host {
+        use ntapi::ntrtl::RtlGetVersion;
+        use winapi::um::sysinfoapi::{
+            ComputerNameDnsDomain, ComputerNameDnsFullyQualified, ComputerNameDnsHostname, GetNativeSystemInfo, SYSTEM_INFO,
+        };
+        use winapi::um::timezoneapi::{GetDynamicTimeZoneInformation, DYNAMIC_TIME_ZONE_INFORMATION};
+        use winapi::um::winnt::{
+            OSVERSIONINFOW, PROCESSOR_ARCHITECTURE_AMD64, PROCESSOR_ARCHITECTURE_ARM, PROCESSOR_ARCHITECTURE_ARM64,
+            PROCESSOR_ARCHITECTURE_INTEL,
+        };
+
+        const CURRENT_VERSION: &str = "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion";
+
+        let mut host = Host::default();
+
+        unsafe {
+            host.hostname = computer_name(ComputerNameDnsHostname);
+            host.fqdn = computer_name(ComputerNameDnsFullyQualified);
+            host.domain = computer_name(ComputerNameDnsDomain);
+            if host.domain.is_empty() {
+                host.domain = workgroup();
+            }
+
+            host.os_name = reg_string(CURRENT_VERSION, "ProductName").unwrap_or_default();
+            //Windows 11 still reports "Windows 10" in ProductName, the build number tells them apart
+            let build = reg_string(CURRENT_VERSION, "CurrentBuild").unwrap_or_default();
+            if build.parse::<u32>().unwrap_or(0) >= 22000 {
+                host.os_name = host.os_name.replacen("Windows 10", "Windows 11", 1);
+            }
+            host.edition = reg_string(CURRENT_VERSION, "EditionID").unwrap_or_default();
+            host.version = reg_string(CURRENT_VERSION, "DisplayVersion")
+                .or_else(|| reg_string(CURRENT_VERSION, "ReleaseId"))
+                .unwrap_or_default();
+            host.build = match reg_dword(CURRENT_VERSION, "UBR") {
+                Some(ubr) => format!("{}.{}", build, ubr),
+                None => build,
+            };
+            host.install_date = reg_dword(CURRENT_VERSION, "InstallDate").map(|d| d as i64);
+            host.last_patched = last_update_install();
+
+            let mut version: OSVERSIONINFOW = std::mem::zeroed();
+            version.dwOSVersionInfoSize = std::mem::size_of::<OSVERSIONINFOW>() as u32;
+            if RtlGetVersion(&mut version) >= 0 {
+                host.kernel = format!("{}.{}.{}", version.dwMajorVersion, version.dwMinorVersion, version.dwBuildNumber);
+            }
+
+            let mut system: SYSTEM_INFO = std::mem::zeroed();
+            GetNativeSystemInfo(&mut system);
+            host.arch = match system.u.s().wProcessorArchitecture {
+                PROCESSOR_ARCHITECTURE_AMD64 => "x86_64",
+                PROCESSOR_ARCHITECTURE_ARM64 => "aarch64",
+                PROCESSOR_ARCHITECTURE_INTEL => "x86",
+                PROCESSOR_ARCHITECTURE_ARM => "arm",
+                _ => "unknown",
+            }
+            .to_string();
+
+            let mut tz: DYNAMIC_TIME_ZONE_INFORMATION = std::mem::zeroed();
+            GetDynamicTimeZoneInformation(&mut tz);
+            host.timezone = format!("{} ({})", from_wide(&tz.TimeZoneKeyName), utc_offset());
+
+            if let Some(hypervisor) = cpuid_hypervisor() {
+                host.virtualization.push(hypervisor.to_string());
+            }
+            //Process and Hyper-V isolated Windows containers set ContainerType
+            if reg_dword("SYSTEM\\CurrentControlSet\\Control", "ContainerType").is_some() {
+                host.virtualization.push("Windows container".to_string());
+            }
+        }
+
+        return host;
+    }
+}
+
+//NAME="Ubuntu" style lines from /etc/os-release, quotes optional
+#[cfg(not(windows))]
+pub(crate) fn os_release_value(text: &str, key: &str) -> Option<String> {
+    text.lines().find_map(|l| {
+        let value = l.strip_prefix(key)?.strip_prefix('=')?;
+        Some(value.trim().trim_matches('"').trim_matches('\'').to_string())
+    })
+}
+
+//Container runtime from the marker files they create, or from the cgroup of pid 1
+#[cfg(not(windows))]
+pub(crate) fn container() -> Option<&'static str> {
+    use std::path::Path;
+
+    if Path::new("/.dockerenv").exists() {
+        return Some("Docker");
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some("Podman");
+    }
+
+    let cgroup = std::fs::read_to_string("/proc/1/cgroup").unwrap_or_default();
+    if cgroup.contains("kubepods") {
+        Some("Kubernetes")
+    } else if cgroup.contains("/docker") {
+        Some("Docker")
+    } else if cgroup.contains("/lxc") {
+        Some("LXC")
+    } else {
+        None
+    }
+}
+
+//DMI vendor strings, for platforms without CPUID
+#[cfg(not(windows))]
+fn dmi_hypervisor() -> Option<&'static str> {
+    let vendor = std::fs::read_to_string("/sys/class/dmi/id/sys_vendor").unwrap_or_default();
+    let product = std::fs::read_to_string("/sys/class/dmi/id/product_name").unwrap_or_default();
+
+    match vendor.trim() {
+        "QEMU" => Some("KVM"),
+        "VMware, Inc." => Some("VMware"),
+        "innotek GmbH" | "Oracle Corporation" if product.contains("VirtualBox") => Some("VirtualBox"),
+        "Microsoft Corporation" if product.contains("Virtual Machine") => Some("Hyper-V"),
+        "Xen" => Some("Xen"),
+        "Amazon EC2" => Some("Amazon EC2"),
+        _ => None,
+    }
+}
+
+//Newest modification time of the package manager databases, updated on every install or upgrade
+#[cfg(not(windows))]
+fn package_db_modified() -> Option<i64> {
+    let paths = [
+        "/var/lib/dpkg/status",
+        "/var/lib/rpm/rpmdb.sqlite",
+        "/var/lib/rpm/Packages",
+        "/var/lib/pacman/local",
+        "/lib/apk/db/installed",
+    ];
+
+    paths
+        .iter()
+        .filter_map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+        .filter_map(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .max()
+}
+
+#[cfg(not(windows))]
+fn timezone_name() -> String {
+    if let Ok(tz) = std::env::var("TZ") {
+        if !tz.is_empty() {
+            return tz.trim_start_matches(':').to_string();
+        }
+    }
+    if let Ok(tz) = std::fs::read_to_string("/etc/timezone") {
+        return tz.trim().to_string();
+    }
+    //Usually a link to /usr/share/zoneinfo/Area/City
+    std::fs::read_link("/etc/localtime")
+        .ok()
+        .and_then(|p| p.to_str().and_then(|s| s.split_once("zoneinfo/")).map(|(_, tz)| tz.to_string()))
+        .unwrap_or_default()
+}
+
+#[cfg(not(windows))]
+impl Host {
+    pub fn info() -> Host {
+        let read = |path: &str| std::fs::read_to_string(path).map(|s| s.trim().to_string()).unwrap_or_default();
+        let mut host = Host::default();
+
+        host.hostname = read("/proc/sys/kernel/hostname");
+        //The FQDN comes from the resolver, which `hostname -f` asks for us
+        host.fqdn = std::process::Command::new("hostname")
+            .arg("-f")
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+            .filter(|f| !f.is_empty())
+            .unwrap_or_else(|| host.hostname.clone());
+        host.domain = host.fqdn.split_once('.').map(|(_, d)| d.to_string()).unwrap_or_default();
+
+        let os_release = std::fs::read_to_string("/etc/os-release")
+            .or_else(|_| std::fs::read_to_string("/usr/lib/os-release"))
+            .unwrap_or_default();
+        host.os_name = os_release_value(&os_release, "PRETTY_NAME")
+            .or_else(|| os_release_value(&os_release, "NAME"))
+            .unwrap_or_else(|| "Linux".to_string());
+        host.edition = os_release_value(&os_release, "VARIANT").unwrap_or_default();
+        host.version = os_release_value(&os_release, "VERSION_ID").unwrap_or_default();
+        host.build = os_release_value(&os_release, "BUILD_ID").unwrap_or_default();
+        host.kernel = read("/proc/sys/kernel/osrelease");
+        host.arch = std::env::consts::ARCH.to_string();
+
+        //Birth time of the root file system, filesystems without one report 0
+        host.install_date = std::fs::metadata("/")
+            .and_then(|m| m.created())
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .filter(|&t| t > 0);
+        host.last_patched = package_db_modified();
+
+        host.timezone = format!("{} ({})", timezone_name(), utc_offset());
+
+        if let Some(hypervisor) = cpuid_hypervisor().or_else(dmi_hypervisor) {
+            host.virtualization.push(hypervisor.to_string());
+        }
+        if host.kernel.to_lowercase().contains("microsoft") {
+            host.virtualization.push("WSL".to_string());
+        }
+        if let Some(runtime) = container() {
+            host.virtualization.push(runtime.to_string());
+        }
+
+        return host;
+    }
+}
+
+pub(crate) fn print_host(host: &Host, config: &Config) {
+    let c = config.theme.highlight;
+    let or_dash = |s: &str| if s.is_empty() { "-".to_string() } else { s.to_string() };
+    let date = |t: Option<i64>| t.map(format_time).unwrap_or_else(|| "-".to_string());
+
+    println!(
+        "Host: {} | FQDN: {} | Domain: {}",
+        host.hostname.color(c).bold(),
+        or_dash(&host.fqdn),
+        or_dash(&host.domain)
+    );
+    println!(
+        "OS: {} | Edition: {} | Version: {} | Build: {} | Kernel: {} | Arch: {}",
+        host.os_name.color(c).bold(),
+        or_dash(&host.edition),
+        or_dash(&host.version),
+        or_dash(&host.build),
+        or_dash(&host.kernel),
+        host.arch
+    );
+    println!(
+        "Installed: {} | Last Patched: {} | Virtualization: {} | Time Zone: {}",
+        date(host.install_date),
+        date(host.last_patched),
+        if host.virtualization.is_empty() { "none".to_string() } else { host.virtualization.join(", ") },
+        or_dash(&host.timezone)
+    );
+}
@@ -0,0 +1,440 @@
+use crate::config::Config;
+use crate::system::{Load, SystemInfo};
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//The daemon's latest load as f64 bits: cpu, load, load1, load5, load15, then the sample time in
+//ms since the epoch. The time is stored last and read first, 0 until the first sample
+static LATEST: [AtomicU64; 6] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+//Set once this process owns the endpoint, so the socket file is only removed by its owner
+static SERVING: AtomicBool = AtomicBool::new(false);
+
+//How long either end of a socket waits on the other, pipes have no such timeout
+#[cfg(not(windows))]
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+//How often a daemon that found the endpoint taken tries again, in case the owner went away
+const RETRY: Duration = Duration::from_secs(60);
+
+fn now_ms() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+}
+
+//Exponentially damped average over a period, the same weighting as the Unix load averages
+fn damp(average: f64, load: f64, elapsed: f64, period: f64) -> f64 {
+    let factor = (-elapsed / period).exp();
+    return average * factor + load * (1.0 - factor);
+}
+
+//Sample continuously so the averages cover the daemon's whole run, not two queries
+//load_interval seconds apart. The first sample seeds them with the one-shot estimate
+fn sampler(cpu_interval: u32, load_interval: u32) {
+    let mut averages: Option<(f64, f64, f64)> = None;
+    let mut last = std::time::Instant::now();
+
+    loop {
+        let mut l: Load = SystemInfo::new();
+        let l = *l.sample(cpu_interval, load_interval);
+        let elapsed = last.elapsed().as_secs_f64();
+        last = std::time::Instant::now();
+
+        let (a1, a5, a15) = match averages {
+            None => (l.avg_1_load, l.avg_5_load, l.avg_15_load),
+            Some((a1, a5, a15)) => (
+                damp(a1, l.current_load, elapsed, 60.0),
+                damp(a5, l.current_load, elapsed, 300.0),
+                damp(a15, l.current_load, elapsed, 900.0),
+            ),
+        };
+        averages = Some((a1, a5, a15));
+
+        for (slot, value) in [l.processor_activity, l.current_load, a1, a5, a15].iter().enumerate() {
+            LATEST[slot].store(value.to_bits(), Ordering::SeqCst);
+        }
+        LATEST[5].store(now_ms(), Ordering::SeqCst);
+    }
+}
+
+//The daemon's load if it has sampled recently, with the age of the sample in ms. A sampler
+//that stopped making progress is treated as absent
+fn latest(config: &Config) -> Option<(Load, u64)> {
+    let time = LATEST[5].load(Ordering::SeqCst);
+    if time == 0 {
+        return None;
+    }
+    let age = now_ms().saturating_sub(time);
+    let limit = (config.cpu_interval.max(config.load_interval) as u64 * 2 + 5) * 1000;
+    if age > limit {
+        return None;
+    }
+
+    let value = |slot: usize| f64::from_bits(LATEST[slot].load(Ordering::SeqCst));
+    let load = Load {
+        processor_activity: value(0),
+        current_load: value(1),
+        avg_1_load: value(2),
+        avg_5_load: value(3),
+        avg_15_load: value(4),
+    };
+    return Some((load, age));
+}
+
+//One request line in, one response line out: "ok ..." or "error ..."
+fn respond(request: &str, config: &Config) -> String {
+    match request.trim() {
+        "ping" => format!("ok uptime {}", env!("CARGO_PKG_VERSION")),
+        "load" => match latest(config) {
+            Some((l, age)) => format!(
+                "ok cpu={} load={} load1={} load5={} load15={} age={}",
+                l.processor_activity, l.current_load, l.avg_1_load, l.avg_5_load, l.avg_15_load, age
+            ),
+            None => "error no recent sample".to_string(),
+        },
+        other => format!("error unknown request {:?}", other),
+    }
+}
+
+fn handle<S: Read + Write>(stream: S, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.by_ref().take(256).read_line(&mut request)?;
+    let stream = reader.get_mut();
+    writeln!(stream, "{}", respond(&request, config))?;
+    stream.flush()?;
+    return Ok(());
+}
+
+//Start the sampler and the query endpoint in the background for a daemon. The endpoint stays
+//as configured at start, a reload does not move it
+pub(crate) fn start(config: &Config) {
+    if !config.ipc_enabled {
+        return;
+    }
+    let (cpu_interval, load_interval) = (config.cpu_interval, config.load_interval);
+    std::thread::spawn(move || sampler(cpu_interval, load_interval));
+
+    let config = config.clone();
+    std::thread::spawn(move || loop {
+        match server::listen(&config) {
+            //Another daemon answers queries already, check again later in case it stops
+            Ok(false) => std::thread::sleep(RETRY),
+            Ok(true) => return,
+            Err(e) => {
+                eprintln!("Query endpoint {} unavailable: {}", config.ipc_path, e);
+                return;
+            }
+        }
+    });
+}
+
+//Called on shutdown so clients do not find a stale socket
+pub(crate) fn stop(config: &Config) {
+    if SERVING.load(Ordering::SeqCst) {
+        server::remove(&config.ipc_path);
+    }
+}
+
+//Ask the daemon for its load, None when no daemon answers or it has no recent sample
+fn query_load(path: &str) -> Option<Load> {
+    return parse_load(&client::request(path, "load").ok()?);
+}
+
+fn parse_load(response: &str) -> Option<Load> {
+    let fields = response.strip_prefix("ok ")?;
+
+    let mut l = Load::default();
+    for field in fields.split_whitespace() {
+        let (key, value) = field.split_once('=')?;
+        let value: f64 = value.parse().ok()?;
+        match key {
+            "cpu" => l.processor_activity = value,
+            "load" => l.current_load = value,
+            "load1" => l.avg_1_load = value,
+            "load5" => l.avg_5_load = value,
+            "load15" => l.avg_15_load = value,
+            _ => {}
+        }
+    }
+    return Some(l);
+}
+
+//Load from the sampler in this process, then a running daemon, then a direct sample which
+//takes the full cpu and load intervals
+pub(crate) fn load(config: &Config) -> Load {
+    if let Some((l, _)) = latest(config) {
+        return l;
+    }
+    if config.ipc_enabled {
+        if let Some(l) = query_load(&config.ipc_path) {
+            return l;
+        }
+    }
+    let mut l: Load = SystemInfo::new();
+    return *l.sample(config.cpu_interval, config.load_interval);
+}
+
+#[cfg(windows)]
+mod client {
+    use super::*;
+    use std::fs::OpenOptions;
+
+    //A busy or missing pipe fails the open straight away, the reply is from memory so it is quick
+    pub(super) fn request(path: &str, request: &str) -> Result<String, Box<dyn Error>> {
+        let mut pipe = OpenOptions::new().read(true).write(true).open(path)?;
+        writeln!(pipe, "{}", request)?;
+        let mut response = String::new();
+        BufReader::new(pipe).read_line(&mut response)?;
+        return Ok(response.trim_end().to_string());
+    }
+}
+
+#[cfg(not(windows))]
+mod client {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    pub(super) fn request(path: &str, request: &str) -> Result<String, Box<dyn Error>> {
+        let mut stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        writeln!(stream, "{}", request)?;
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        return Ok(response.trim_end().to_string());
+    }
+}
+
+//Named pipe server. Each connection gets one request and one response, then the next instance
+//of the pipe is created for the next client
+#[cfg(windows)]
+mod server {
+    use super::*;
+    use crate::daemon::to_wide;
+    use std::fs::File;
+    use std::os::windows::io::FromRawHandle;
+    use std::ptr::null_mut;
+    use winapi::shared::sddl::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1};
+    use winapi::shared::winerror::{ERROR_ACCESS_DENIED, ERROR_PIPE_CONNECTED};
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::minwinbase::SECURITY_ATTRIBUTES;
+    use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW};
+    use winapi::um::winbase::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, LocalFree, PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    //The default pipe DACL only gives other users read access, which is not enough to send a
+    //request. Allow read/write for authenticated users and full control for SYSTEM and admins
+    const SDDL: &str = "D:(A;;GRGW;;;AU)(A;;GA;;;SY)(A;;GA;;;BA)";
+
+    //Returns Ok(false) when another process already owns the pipe name
+    pub(super) fn listen(config: &Config) -> Result<bool, Box<dyn Error>> {
+        let name = to_wide(&config.ipc_path);
+        let sddl = to_wide(SDDL);
+        unsafe {
+            let mut descriptor = null_mut();
+            if ConvertStringSecurityDescriptorToSecurityDescriptorW(sddl.as_ptr(), SDDL_REVISION_1 as u32, &mut descriptor, null_mut()) == 0 {
+                return Err(format!("security descriptor failed ({})", GetLastError()).into());
+            }
+            let mut attributes = SECURITY_ATTRIBUTES {
+                nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+                lpSecurityDescriptor: descriptor,
+                bInheritHandle: 0,
+            };
+
+            let mut first = true;
+            loop {
+                //The first instance claims the name, so a second daemon fails instead of sharing it
+                let open_mode = PIPE_ACCESS_DUPLEX | if first { FILE_FLAG_FIRST_PIPE_INSTANCE } else { 0 };
+                let pipe = CreateNamedPipeW(
+                    name.as_ptr(),
+                    open_mode,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    4096,
+                    4096,
+                    0,
+                    &mut attributes,
+                );
+                if pipe == INVALID_HANDLE_VALUE {
+                    let error = GetLastError();
+                    LocalFree(descriptor);
+                    if first && error == ERROR_ACCESS_DENIED {
+                        return Ok(false);
+                    }
+                    return Err(format!("CreateNamedPipe failed ({})", error).into());
+                }
+                if first {
+                    SERVING.store(true, Ordering::SeqCst);
+                    first = false;
+                }
+
+                //A client can connect between create and connect, which is reported as an error
+                if ConnectNamedPipe(pipe, null_mut()) == 0 && GetLastError() != ERROR_PIPE_CONNECTED {
+                    CloseHandle(pipe);
+                    continue;
+                }
+                //Pipe reads have no timeout, so a client that never sends a request only holds up
+                //its own thread. Closing the file disconnects it and frees this instance
+                let file = File::from_raw_handle(pipe as _);
+                let config = config.clone();
+                std::thread::spawn(move || {
+                    let _ = handle(file, &config);
+                });
+            }
+        }
+    }
+
+    //Pipes go away with the process
+    pub(super) fn remove(_path: &str) {}
+}
+
+#[cfg(not(windows))]
+mod server {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+
+    //Returns Ok(false) when another process is answering on the socket. A socket file nobody
+    //answers on is left from a daemon that did not shut down cleanly and is replaced
+    pub(super) fn listen(config: &Config) -> Result<bool, Box<dyn Error>> {
+        let path = Path::new(&config.ipc_path);
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Ok(false);
+            }
+            std::fs::remove_file(path)?;
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        //Queries are read only, so any local user may ask
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))?;
+        SERVING.store(true, Ordering::SeqCst);
+
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(TIMEOUT));
+            let _ = stream.set_write_timeout(Some(TIMEOUT));
+            let _ = handle(stream, config);
+        }
+        return Ok(true);
+    }
+
+    pub(super) fn remove(path: &str) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+//`uptime service status`: whether a daemon answers queries and what it reports
+pub(crate) fn status(config: &Config) -> Result<(), Box<dyn Error>> {
+    let version = client::request(&config.ipc_path, "ping").map_err(|e| format!("No daemon at {}: {}", config.ipc_path, e))?;
+    println!("Daemon at {}: {}", config.ipc_path, version.trim_start_matches("ok "));
+    println!("{}", client::request(&config.ipc_path, "load")?);
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_latest(values: [f64; 5], time: u64) {
+        for (slot, value) in values.iter().enumerate() {
+            LATEST[slot].store(value.to_bits(), Ordering::SeqCst);
+        }
+        LATEST[5].store(time, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn damping() {
+        assert_eq!(damp(2.0, 2.0, 30.0, 60.0), 2.0);
+        assert_eq!(damp(1.0, 5.0, 0.0, 60.0), 1.0);
+        //After one period the average has moved 1 - 1/e of the way
+        assert!((damp(0.0, 1.0, 60.0, 60.0) - (1.0 - (-1.0f64).exp())).abs() < 1e-12);
+        assert!(damp(0.0, 1.0, 60.0, 900.0) < damp(0.0, 1.0, 60.0, 300.0));
+    }
+
+    #[test]
+    fn requests() {
+        let config = Config::default();
+        assert_eq!(respond("ping\n", &config), format!("ok uptime {}", env!("CARGO_PKG_VERSION")));
+        assert_eq!(respond("reboot", &config), "error unknown request \"reboot\"");
+
+        let load = parse_load("ok cpu=12.5 load=1 load1=0.82 load5=0.71 load15=0.64 age=840").unwrap();
+        assert_eq!(
+            (load.processor_activity, load.current_load, load.avg_1_load, load.avg_5_load, load.avg_15_load),
+            (12.5, 1.0, 0.82, 0.71, 0.64)
+        );
+        assert!(parse_load("error no recent sample").is_none());
+        assert!(parse_load("ok cpu=high").is_none());
+    }
+
+    //The only test that sets the in-process sample, as tests share it
+    #[test]
+    fn latest_sample() {
+        let config = Config::default();
+        set_latest([12.5, 1.0, 0.82, 0.71, 0.64], now_ms() - 840);
+        let response = respond("load", &config);
+        assert!(response.starts_with("ok cpu=12.5 load=1 load1=0.82 load5=0.71 load15=0.64 age="), "{}", response);
+        let (served, age) = latest(&config).unwrap();
+        assert!((840..5000).contains(&age));
+        assert_eq!(parse_load(&response).unwrap().avg_15_load, 0.64);
+        assert_eq!(served.avg_5_load, 0.71);
+        //A recent sample is used without sampling again
+        assert_eq!(load(&Config { ipc_enabled: false, ..Config::default() }).avg_1_load, 0.82);
+
+        //A sampler that stopped is ignored
+        let limit = (config.cpu_interval.max(config.load_interval) as u64 * 2 + 5) * 1000;
+        set_latest([12.5, 1.0, 0.82, 0.71, 0.64], now_ms() - limit - 1000);
+        assert!(latest(&config).is_none());
+        assert_eq!(respond("load", &config), "error no recent sample");
+    }
+
+    //A daemon on a socket in the temp directory that gives each connection the next canned reply
+    #[cfg(not(windows))]
+    #[test]
+    fn query_over_a_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("uptime-ipc-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let replies = ["ok cpu=3.5 load=0.5 load1=0.25 load5=0.2 load15=0.1 age=100", "error no recent sample"];
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for reply in replies {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                requests.push(request);
+                writeln!(reader.get_mut(), "{}", reply).unwrap();
+            }
+            requests
+        });
+
+        let path_str = path.to_str().unwrap();
+        let l = query_load(path_str).unwrap();
+        assert_eq!((l.processor_activity, l.current_load, l.avg_15_load), (3.5, 0.5, 0.1));
+        assert!(query_load(path_str).is_none());
+        assert_eq!(server.join().unwrap(), vec!["load\n".to_string(), "load\n".to_string()]);
+
+        //Nobody listening any more
+        let _ = std::fs::remove_file(&path);
+        assert!(query_load(path_str).is_none());
+    }
+}
@@ -0,0 +1,343 @@
+use std::error::Error;
+use std::fmt;
+
+//Just enough JSON for the snapshots this tool writes and reads back from other hosts
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    //Keys stay in the order they were written
+    Object(Vec<(String, Value)>),
+}
+
+//Nesting deeper than this is not something we write, so it is refused rather than recursed into
+const MAX_DEPTH: usize = 32;
+
+impl Value {
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+//Quoted and escaped. Shared with the WMI output so both write strings the same way
+pub(crate) fn write_string<W: fmt::Write>(f: &mut W, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    return write!(f, "\"");
+}
+
+pub(crate) fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    let _ = write_string(&mut out, s);
+    return out;
+}
+
+//Compact, on one line, so a snapshot per line can be streamed and read back line by line
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            //JSON has no NaN or infinity
+            Value::Number(n) if !n.is_finite() => write!(f, "null"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, msg: &str) -> Result<T, Box<dyn Error>> {
+        return Err(format!("Invalid JSON at byte {}: {}", self.pos, msg).into());
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && matches!(self.bytes[self.pos], b' ' | b'\t' | b'\r' | b'\n') {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str, value: Value) -> Result<Value, Box<dyn Error>> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            return Ok(value);
+        }
+        return self.error("unexpected character");
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, Box<dyn Error>> {
+        if depth > MAX_DEPTH {
+            return self.error("nested too deeply");
+        }
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            None => self.error("unexpected end"),
+            Some(b'n') => self.expect("null", Value::Null),
+            Some(b't') => self.expect("true", Value::Bool(true)),
+            Some(b'f') => self.expect("false", Value::Bool(false)),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                self.skip_whitespace();
+                if self.bytes.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(items));
+                        }
+                        _ => return self.error("expected ',' or ']'"),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = vec![];
+                self.skip_whitespace();
+                if self.bytes.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.bytes.get(self.pos) != Some(&b'"') {
+                        return self.error("expected a key");
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if self.bytes.get(self.pos) != Some(&b':') {
+                        return self.error("expected ':'");
+                    }
+                    self.pos += 1;
+                    fields.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(fields));
+                        }
+                        _ => return self.error("expected ',' or '}'"),
+                    }
+                }
+            }
+            Some(_) => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, Box<dyn Error>> {
+        let start = self.pos;
+        while self.pos < self.bytes.len() && matches!(self.bytes[self.pos], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap_or("");
+        match text.parse::<f64>() {
+            Ok(n) if !text.is_empty() => Ok(Value::Number(n)),
+            _ => {
+                self.pos = start;
+                self.error("unexpected character")
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Box<dyn Error>> {
+        let hex = self.bytes.get(self.pos..self.pos + 4).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.and_then(|h| u32::from_str_radix(h, 16).ok()) {
+            Some(n) => {
+                self.pos += 4;
+                Ok(n)
+            }
+            None => self.error("invalid \\u escape"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        //Skip the opening quote
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while self.pos < self.bytes.len() && self.bytes[self.pos] != b'"' && self.bytes[self.pos] != b'\\' {
+                self.pos += 1;
+            }
+            //The input is a &str and quotes and backslashes are ASCII, so this slice is whole characters
+            out.push_str(std::str::from_utf8(&self.bytes[start..self.pos]).unwrap_or(""));
+
+            match self.bytes.get(self.pos) {
+                None => return self.error("unterminated string"),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                _ => {}
+            }
+            self.pos += 1;
+            let escape = match self.bytes.get(self.pos) {
+                Some(e) => *e,
+                None => return self.error("unterminated string"),
+            };
+            self.pos += 1;
+            match escape {
+                b'"' => out.push('"'),
+                b'\\' => out.push('\\'),
+                b'/' => out.push('/'),
+                b'b' => out.push('\u{8}'),
+                b'f' => out.push('\u{c}'),
+                b'n' => out.push('\n'),
+                b'r' => out.push('\r'),
+                b't' => out.push('\t'),
+                b'u' => {
+                    let mut code = self.hex4()?;
+                    //A high surrogate needs its low half from the next escape
+                    if (0xD800..0xDC00).contains(&code) && self.bytes[self.pos..].starts_with(b"\\u") {
+                        let start = self.pos;
+                        self.pos += 2;
+                        let low = self.hex4()?;
+                        if (0xDC00..0xE000).contains(&low) {
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                        } else {
+                            //Not a pair, the lone high half is replaced and the next escape read on its own
+                            self.pos = start;
+                        }
+                    }
+                    out.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                }
+                _ => return self.error("invalid escape"),
+            }
+        }
+    }
+}
+
+pub(crate) fn parse(text: &str) -> Result<Value, Box<dyn Error>> {
+    let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos != parser.bytes.len() {
+        return parser.error("trailing characters");
+    }
+    return Ok(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let value = Value::Object(vec![
+            ("host".to_string(), Value::String("web \"1\"\\\n\t\u{1}é".to_string())),
+            ("uptime".to_string(), Value::Number(3600.5)),
+            ("up".to_string(), Value::Bool(true)),
+            ("swap".to_string(), Value::Null),
+            ("disks".to_string(), Value::Array(vec![Value::Object(vec![("drive".to_string(), Value::String("C:".to_string()))])])),
+        ]);
+        let text = value.to_string();
+        assert_eq!(
+            text,
+            r#"{"host":"web \"1\"\\\n\t\u0001é","uptime":3600.5,"up":true,"swap":null,"disks":[{"drive":"C:"}]}"#
+        );
+        assert_eq!(parse(&text).unwrap(), value);
+        assert_eq!(quote("a\"b"), r#""a\"b""#);
+
+        //Non-finite numbers are written as null
+        assert_eq!(Value::Array(vec![Value::Number(f64::NAN)]).to_string(), "[null]");
+        assert_eq!(parse(" [ 1 , -2.5e3 , {} , [] ] ").unwrap().to_string(), "[1,-2500,{},[]]");
+    }
+
+    #[test]
+    fn escapes_and_surrogates() {
+        let s = |text: &str| parse(text).unwrap().as_str().unwrap().to_string();
+        assert_eq!(s(r#""\/\b\fé""#), "/\u{8}\u{c}é");
+        assert_eq!(s(r#""\ud83d\ude00""#), "😀");
+        //A high half without its low half becomes a replacement character, the next escape is kept
+        assert_eq!(s(r#""\ud83d\u0041""#), "\u{FFFD}A");
+        assert_eq!(s(r#""\ud83dx""#), "\u{FFFD}x");
+        assert_eq!(s(r#""\ude00""#), "\u{FFFD}");
+        assert!(parse(r#""\u12""#).is_err());
+        assert!(parse(r#""\q""#).is_err());
+        assert!(parse(r#""open"#).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(parse("").is_err());
+        assert!(parse("{\"a\" 1}").is_err());
+        assert!(parse("[1,]").is_err());
+        assert!(parse("{} x").is_err());
+        assert!(parse("nul").is_err());
+
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH + 1)).is_ok());
+        assert!(parse(&nested(MAX_DEPTH + 2)).unwrap_err().to_string().contains("nested too deeply"));
+    }
+}
@@ -0,0 +1,307 @@
+//Resource limits applied to this process by a container runtime or service manager:
+//cgroup v1/v2 on Linux and Job Objects on Windows (which Windows containers are built on).
+//Host wide APIs such as GlobalMemoryStatusEx and GetSystemInfo do not see these.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LimitSource {
+    #[cfg(not(windows))]
+    CgroupV1,
+    #[cfg(not(windows))]
+    CgroupV2,
+    JobObject,
+}
+
+impl LimitSource {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            #[cfg(not(windows))]
+            LimitSource::CgroupV1 => "cgroup v1",
+            #[cfg(not(windows))]
+            LimitSource::CgroupV2 => "cgroup v2",
+            LimitSource::JobObject => "job object",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Limits {
+    pub(crate) source: Option<LimitSource>,
+    //Bytes
+    pub(crate) memory_limit: Option<u64>,
+    pub(crate) memory_used: Option<u64>,
+    //CPU time allowed per second of wall time, in logical processors (1.5 = one and a half)
+    pub(crate) cpu_quota: Option<f64>,
+    //Logical processors the process may be scheduled on
+    pub(crate) cpu_set: Option<u32>,
+}
+
+impl Limits {
+    //Processors' worth of CPU actually available, never more than the host has
+    pub(crate) fn effective_cpus(&self, host_cpus: u32) -> f64 {
+        let mut cpus = host_cpus as f64;
+        if let Some(set) = self.cpu_set {
+            cpus = cpus.min(set as f64);
+        }
+        if let Some(quota) = self.cpu_quota {
+            cpus = cpus.min(quota);
+        }
+        return cpus;
+    }
+}
+
+//cpu.max is "max 100000" or "<quota> <period>" in microseconds
+#[cfg(not(windows))]
+pub(crate) fn parse_cpu_max(text: &str) -> Option<f64> {
+    let mut parts = text.split_whitespace();
+    let quota = parts.next()?.parse::<f64>().ok()?;
+    let period = parts.next().unwrap_or("100000").parse::<f64>().ok()?;
+    if period <= 0.0 || quota <= 0.0 {
+        return None;
+    }
+    Some(quota / period)
+}
+
+//"0-3,6,8-9" style CPU lists
+#[cfg(not(windows))]
+pub(crate) fn parse_cpu_list(text: &str) -> Option<u32> {
+    let mut count = 0;
+    for range in text.trim().split(',').filter(|r| !r.is_empty()) {
+        count += match range.split_once('-') {
+            Some((a, b)) => b.parse::<u32>().ok()?.checked_sub(a.parse::<u32>().ok()?)? + 1,
+            None => {
+                range.parse::<u32>().ok()?;
+                1
+            }
+        };
+    }
+    if count == 0 {
+        None
+    } else {
+        Some(count)
+    }
+}
+
+//"max" in cgroup v2, a page rounded i64::MAX in cgroup v1
+#[cfg(not(windows))]
+pub(crate) fn parse_memory_limit(text: &str) -> Option<u64> {
+    match text.trim().parse::<u64>() {
+        Ok(bytes) if bytes < 1 << 62 => Some(bytes),
+        _ => None,
+    }
+}
+
+#[cfg(not(windows))]
+fn read(path: &std::path::Path) -> Option<String> {
+    std::fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+//The cgroup directory and each of its parents up to the mount point, a limit anywhere
+//up the tree applies. Inside a container with its own cgroup namespace the path is "/"
+#[cfg(not(windows))]
+fn cgroup_dirs(mount: &std::path::Path, path: &str) -> Vec<std::path::PathBuf> {
+    let mut dirs = vec![];
+    let mut current = std::path::PathBuf::from(path.trim_start_matches('/'));
+    loop {
+        let dir = mount.join(&current);
+        if dir.is_dir() {
+            dirs.push(dir);
+        }
+        if !current.pop() {
+            break;
+        }
+    }
+    return dirs;
+}
+
+fn min_option<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b < a { b } else { a }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+#[cfg(not(windows))]
+pub(crate) fn detect() -> Limits {
+    use std::path::Path;
+
+    let mut limits = Limits::default();
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+    let root = Path::new("/sys/fs/cgroup");
+
+    //cgroup v2 has a single "0::/path" line and cgroup.controllers at the root
+    if root.join("cgroup.controllers").exists() {
+        let path = cgroup.lines().find_map(|l| l.strip_prefix("0::")).unwrap_or("/");
+        let dirs = cgroup_dirs(root, path);
+
+        for dir in &dirs {
+            limits.memory_limit = min_option(limits.memory_limit, read(&dir.join("memory.max")).and_then(|m| parse_memory_limit(&m)));
+            limits.cpu_quota = min_option(limits.cpu_quota, read(&dir.join("cpu.max")).and_then(|c| parse_cpu_max(&c)));
+        }
+        if let Some(dir) = dirs.first() {
+            limits.memory_used = read(&dir.join("memory.current")).and_then(|m| m.parse().ok());
+            limits.cpu_set = read(&dir.join("cpuset.cpus.effective")).and_then(|c| parse_cpu_list(&c));
+        }
+        limits.source = Some(LimitSource::CgroupV2);
+    } else {
+        //"4:memory:/docker/abc" or "3:cpu,cpuacct:/user.slice", one mount per controller
+        let path_for = |controller: &str| {
+            cgroup.lines().find_map(|l| {
+                let mut parts = l.splitn(3, ':');
+                let (_, controllers, path) = (parts.next()?, parts.next()?, parts.next()?);
+                controllers.split(',').any(|c| c == controller).then(|| path.to_string())
+            })
+        };
+
+        if let Some(path) = path_for("memory") {
+            let dirs = cgroup_dirs(&root.join("memory"), &path);
+            for dir in &dirs {
+                limits.memory_limit =
+                    min_option(limits.memory_limit, read(&dir.join("memory.limit_in_bytes")).and_then(|m| parse_memory_limit(&m)));
+            }
+            limits.memory_used = dirs.first().and_then(|d| read(&d.join("memory.usage_in_bytes"))).and_then(|m| m.parse().ok());
+        }
+        if let Some(path) = path_for("cpu") {
+            for dir in cgroup_dirs(&root.join("cpu"), &path) {
+                let quota = read(&dir.join("cpu.cfs_quota_us")).unwrap_or_default();
+                let period = read(&dir.join("cpu.cfs_period_us")).unwrap_or_default();
+                limits.cpu_quota = min_option(limits.cpu_quota, parse_cpu_max(&format!("{} {}", quota, period)));
+            }
+        }
+        if let Some(path) = path_for("cpuset") {
+            limits.cpu_set = cgroup_dirs(&root.join("cpuset"), &path).first().and_then(|d| {
+                read(&d.join("cpuset.effective_cpus"))
+                    .or_else(|| read(&d.join("cpuset.cpus")))
+                    .and_then(|c| parse_cpu_list(&c))
+            });
+        }
+        if !cgroup.is_empty() {
+            limits.source = Some(LimitSource::CgroupV1);
+        }
+    }
+
+    return limits;
+}
+
+//Limits of the job object this process belongs to, if any. Nested jobs report the
+//combined, most restrictive limits through the NULL job handle
+#[cfg(windows)]
+pub(crate) fn detect() -> Limits {
+    use winapi::shared::minwindef::FALSE;
+    use winapi::um::jobapi::IsProcessInJob;
+    use winapi::um::jobapi2::QueryInformationJobObject;
+    use winapi::um::processthreadsapi::GetCurrentProcess;
+    use winapi::um::winnt::{
+        JobObjectCpuRateControlInformation, JobObjectExtendedLimitInformation, JOBOBJECT_CPU_RATE_CONTROL_INFORMATION,
+        JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_CPU_RATE_CONTROL_ENABLE, JOB_OBJECT_CPU_RATE_CONTROL_WEIGHT_BASED, JOB_OBJECT_LIMIT_AFFINITY, JOB_OBJECT_LIMIT_JOB_MEMORY,
+        JOB_OBJECT_LIMIT_PROCESS_MEMORY,
+    };
+
+    //Not in winapi: MinRate/MaxRate form of the CPU rate union
+    const JOB_OBJECT_CPU_RATE_CONTROL_MIN_MAX_RATE: u32 = 0x10;
+    //JobObjectMemoryUsageInformation, Windows 10 and later
+    const JOB_OBJECT_MEMORY_USAGE_INFORMATION: u32 = 28;
+    #[repr(C)]
+    struct MemoryUsage {
+        job_memory: u64,
+        _peak_job_memory_used: u64,
+    }
+
+    let mut limits = Limits::default();
+
+    unsafe {
+        let mut in_job = FALSE;
+        if IsProcessInJob(GetCurrentProcess(), std::ptr::null_mut(), &mut in_job) == 0 || in_job == FALSE {
+            return limits;
+        }
+        limits.source = Some(LimitSource::JobObject);
+
+        let mut ext: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+        if QueryInformationJobObject(
+            std::ptr::null_mut(),
+            JobObjectExtendedLimitInformation,
+            &mut ext as *mut _ as _,
+            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            std::ptr::null_mut(),
+        ) != 0
+        {
+            let flags = ext.BasicLimitInformation.LimitFlags;
+            if flags & JOB_OBJECT_LIMIT_JOB_MEMORY != 0 {
+                limits.memory_limit = Some(ext.JobMemoryLimit as u64);
+            }
+            if flags & JOB_OBJECT_LIMIT_PROCESS_MEMORY != 0 {
+                limits.memory_limit = min_option(limits.memory_limit, Some(ext.ProcessMemoryLimit as u64));
+            }
+            if flags & JOB_OBJECT_LIMIT_AFFINITY != 0 {
+                limits.cpu_set = Some(ext.BasicLimitInformation.Affinity.count_ones());
+            }
+        }
+
+        let mut usage: MemoryUsage = std::mem::zeroed();
+        if QueryInformationJobObject(
+            std::ptr::null_mut(),
+            JOB_OBJECT_MEMORY_USAGE_INFORMATION,
+            &mut usage as *mut _ as _,
+            std::mem::size_of::<MemoryUsage>() as u32,
+            std::ptr::null_mut(),
+        ) != 0
+        {
+            limits.memory_used = Some(usage.job_memory);
+        }
+
+        //Rates are in 1/100ths of a percent of every processor on the machine
+        let mut rate: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = std::mem::zeroed();
+        if QueryInformationJobObject(
+            std::ptr::null_mut(),
+            JobObjectCpuRateControlInformation,
+            &mut rate as *mut _ as _,
+            std::mem::size_of::<JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>() as u32,
+            std::ptr::null_mut(),
+        ) != 0
+            && rate.ControlFlags & JOB_OBJECT_CPU_RATE_CONTROL_ENABLE != 0
+            && rate.ControlFlags & JOB_OBJECT_CPU_RATE_CONTROL_WEIGHT_BASED == 0
+        {
+            let cap = if rate.ControlFlags & JOB_OBJECT_CPU_RATE_CONTROL_MIN_MAX_RATE != 0 {
+                rate.u.s().MaxRate as u32
+            } else {
+                *rate.u.CpuRate()
+            };
+            let mut system: winapi::um::sysinfoapi::SYSTEM_INFO = std::mem::zeroed();
+            winapi::um::sysinfoapi::GetSystemInfo(&mut system);
+            if cap > 0 {
+                limits.cpu_quota = Some(cap as f64 / 10_000.0 * system.dwNumberOfProcessors as f64);
+            }
+        }
+    }
+
+    return limits;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effective_cpus_takes_the_tightest_limit() {
+        let limits = Limits {
+            cpu_quota: Some(1.5),
+            cpu_set: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(limits.effective_cpus(8), 1.5);
+        assert_eq!(limits.effective_cpus(1), 1.0);
+        assert_eq!(Limits::default().effective_cpus(8), 8.0);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn cgroup_files() {
+        assert_eq!(parse_cpu_max("max 100000"), None);
+        assert_eq!(parse_cpu_max("150000 100000"), Some(1.5));
+        assert_eq!(parse_cpu_list("0-3,6,8-9"), Some(7));
+        assert_eq!(parse_cpu_list("3-1"), None);
+        assert_eq!(parse_memory_limit("536870912\n"), Some(536_870_912));
+        assert_eq!(parse_memory_limit("max"), None);
+        assert_eq!(parse_memory_limit("9223372036854771712"), None);
+    }
+}
@@ -2,49 +2,364 @@
 #[macro_use]
 mod macros;
 mod disks;
+mod check;
+mod config;
+mod cpu;
+mod daemon;
+mod eventlog;
+mod forecast;
+mod history;
+mod host;
+mod ipc;
+mod json;
+mod limits;
+mod net;
+mod otlp;
+mod power;
+mod pressure;
+mod procs;
+mod push;
+mod record;
+mod remote;
+mod report;
+mod services;
+mod sockets;
+mod table;
+mod users;
+mod utmp;
+mod wmi;
 
 use disks::Disks;
+use host::Host;
+use users::Session;
 mod system;
-use system::{Memory, SystemInfo, Uptime, Load, Processor};
+use system::{Memory, SystemInfo, Uptime, Processor};
+use config::{Config, Format, Section};
 use colored::*;
 
-fn main() {
-    //Make sure ansi support works in terminal
-    let _ = ansi_term::enable_ansi_support();
-    
-    //Initialise all our traits
+fn print_host(config: &Config) {
+    host::print_host(&Host::info(), config);
+}
+
+fn print_uptime(config: &Config) {
     let mut ut: Uptime = SystemInfo::new();
-    let u = ut.info();
+    let u = ut.info().resumes();
+    let c = config.theme.highlight;
+    let users = unsafe { Session::new().info() };
 
     println!(
-        "Uptime: {} Days {} Hours {} Minutes {} Seconds",
-        u.uptime_days.to_string().blue().bold(), u.uptime_hours.to_string().blue().bold(), u.uptime_minutes.to_string().blue().bold(), u.uptime_seconds.to_string().blue().bold()
+        "Uptime: {} Days {} Hours {} Minutes {} Seconds | Users: {}",
+        u.uptime_days.to_string().color(c).bold(), u.uptime_hours.to_string().color(c).bold(), u.uptime_minutes.to_string().color(c).bold(), u.uptime_seconds.to_string().color(c).bold(), users.len()
     );
+    println!(
+        "Boot Time: {} | {}",
+        history::format_time(u.boot_time).color(c).bold(), history::format_time_utc(u.boot_time)
+    );
+    println!(
+        "Active: {} | Suspended: {} | Since Resume: {} | Resumes: {}",
+        history::format_duration((u.active_ms / 1000) as i64).color(c).bold(), history::format_duration((u.suspended_ms / 1000) as i64), history::format_duration((u.since_resume_ms / 1000) as i64).color(c).bold(), u.resume_count
+    );
+}
 
-    let mut pr: Processor = SystemInfo::new();
-    let p = pr.info();
-
+fn print_memory(config: &Config) {
     let mut mi: Memory = SystemInfo::new();
     let m = mi.info();
-    println!("Installed RAM: {} MB | Available RAM: {} MB | Total Page : {} MB | Available Page: {} MB | Used Page: {} MB",
-    m.installed_ram.to_string().green().bold(),m.available_memory.to_string().red().bold(),m.page_file_size.to_string().green().bold(),m.available_page.to_string().red().bold(),m.used_page);
+    let (unit, total, available) = (config.memory_unit, config.theme.total, config.theme.available);
+    //Memory reports megabytes
+    let mb = |v: u64| unit.format_bytes(v * 1024 * 1024);
+
+    println!("Installed RAM: {} {u} | Available RAM: {} {u} | Total Page : {} {u} | Available Page: {} {u} | Used Page: {} {u}",
+    mb(m.installed_ram).color(total).bold(),mb(m.available_memory).color(available).bold(),mb(m.page_file_size).color(total).bold(),mb(m.available_page).color(available).bold(),mb(m.used_page), u = unit.label());
+    if let Some(limit) = m.limit_memory.filter(|l| *l < m.installed_ram) {
+        let used = m.limit_used.unwrap_or(0);
+        println!("Memory Limit ({}): {} {u} | Used: {} {u} ({:.1}%)",
+        m.limit_source.unwrap_or("container"), mb(limit).color(total).bold(), mb(used).color(available).bold(), used as f64 / limit.max(1) as f64 * 100.0, u = unit.label());
+    }
+}
 
+fn print_disks(config: &Config) {
     let mut d = Disks::new();
+    let unit = config.disk_unit;
     unsafe {
-        for x in d.info() {
+        for x in d.info().iter().filter(|x| config.include_disk(x)) {
             println!(
-                "Drive: {} | Type: {:?} | Size: {} GB | Free: {} GB",
-                x.drive, x.drive_type, x.total_gigabytes.to_string().green().bold(), x.available_gigabytes.to_string().red().bold()
+                "Drive: {} | Type: {:?} | FS: {} | Size: {} {u} | Free: {} {u}",
+                x.drive, x.drive_type, x.file_system, unit.format_bytes(x.total_bytes).color(config.theme.total).bold(), unit.format_bytes(x.available_bytes).color(config.theme.available).bold(), u = unit.label()
             );
         }
     }
-    
-    let mut l: Load = SystemInfo::new();
-    let ul = l.info();
-    print!(
+}
+
+fn print_load(config: &Config) {
+    let mut pr: Processor = SystemInfo::new();
+    let p = pr.info();
+
+    let ul = ipc::load(config);
+    println!(
         "Load Average: Current: {:.2} 1 min: {:.2} 5 min: {:.2} 15 min: {:.2} | CPU Usage: {:.2}% | CPU: {} Mhz | CPU Cores: {} ",
-        ul.current_load.to_string().green().bold(), ul.avg_1_load,ul.avg_5_load,ul.avg_15_load, ul.processor_activity.round().to_string().red().bold(), p.current_mhz, p.number_processors,
+        ul.current_load.to_string().color(config.theme.total).bold(), ul.avg_1_load,ul.avg_5_load,ul.avg_15_load, ul.processor_activity.round().to_string().color(config.theme.available).bold(), p.current_mhz, p.number_processors,
     );
-    
-   
+    //Under a CPU quota or affinity mask the load that matters is per CPU actually available
+    if p.effective_processors > 0.0 && p.effective_processors < p.number_processors as f64 {
+        println!(
+            "Effective CPUs: {} | Load per CPU: Current: {:.2} 1 min: {:.2} 5 min: {:.2} 15 min: {:.2}",
+            format!("{:.2}", p.effective_processors).color(config.theme.total).bold(), ul.current_load / p.effective_processors, ul.avg_1_load / p.effective_processors, ul.avg_5_load / p.effective_processors, ul.avg_15_load / p.effective_processors,
+        );
+    }
+}
+
+fn print_users(_config: &Config) {
+    let sessions = unsafe { Session::new().info() };
+    users::print_sessions(&sessions);
+}
+
+fn print_procs(config: &Config) {
+    let processes = procs::top(procs::sample(config.cpu_interval), config.procs_sort, config.procs_top, &config.procs_filter);
+    procs::print_processes(&processes, config.memory_unit);
+}
+
+fn print_net(config: &Config) {
+    let interfaces = net::filter(net::sample(config.cpu_interval), &config.net_filter);
+    net::print_interfaces(&interfaces, config.memory_unit);
+}
+
+fn print_sockets(config: &Config) {
+    let summary = sockets::summarise(sockets::snapshot(), config.sockets_top);
+    sockets::print_summary(&summary);
+}
+
+fn print_services(config: &Config) {
+    let list: Vec<services::Service> = services::snapshot().into_iter().filter(|s| services::included(s, config)).collect();
+    services::print_services(&list, services::boot_time(), false);
+}
+
+fn print_cpu(config: &Config) {
+    match cpu::sample(config.cpu_interval) {
+        Ok(times) => cpu::print_cpu_times(&times),
+        Err(e) => println!("CPU Time: unavailable ({})", e),
+    }
+}
+
+fn print_power(config: &Config) {
+    power::print_power(&power::Power::info(), config);
+}
+
+fn print_pressure(config: &Config) {
+    match pressure::sample(config.cpu_interval) {
+        Ok(list) => pressure::print_pressure(&list),
+        Err(e) => println!("Pressure: unavailable ({})", e),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let (config, rest) = match config::load(&args) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            //Plugin mode reports configuration problems as UNKNOWN
+            if args.iter().any(|a| a == "check") {
+                println!("UPTIME UNKNOWN - {}", e);
+                std::process::exit(3);
+            }
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match rest.first().map(|a| a.as_str()) {
+        //Nagios/Icinga style plugin mode, prints a single status line and exits 0/1/2/3
+        Some("check") => std::process::exit(check::run(&rest[1..], &config)),
+        Some("history") => {
+            if let Err(e) = history::run(&rest[1..]) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("users") => {
+            if let Err(e) = users::run(&rest[1..]) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("procs") => {
+            if let Err(e) = procs::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("proc") => {
+            if let Err(e) = procs::run_detail(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("net") => {
+            if let Err(e) = net::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("sockets") => {
+            if let Err(e) = sockets::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("cpu") => {
+            if let Err(e) = cpu::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("power") => {
+            if let Err(e) = power::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("pressure") => {
+            if let Err(e) = pressure::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("services") => {
+            if let Err(e) = services::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("record") => {
+            if let Err(e) = record::run_record(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("query") => {
+            if let Err(e) = record::run_query(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("forecast") => {
+            if let Err(e) = forecast::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("report") => {
+            if let Err(e) = report::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("otlp") => {
+            if let Err(e) = otlp::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("push") => {
+            if let Err(e) = push::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("agent") => {
+            if let Err(e) = remote::run_agent(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("service") => {
+            if let Err(e) = daemon::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("wmi") => {
+            if let Err(e) = wmi::run(&rest[1..], &config) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(other) => {
+            eprintln!("Unknown argument '{}'", other);
+            std::process::exit(1);
+        }
+        None => {}
+    }
+
+    //Other hosts, through their agents or over ssh, shown together
+    if !config.hosts.is_empty() {
+        if let Err(e) = remote::run(&config) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    //Delimited rows for spreadsheets and log files, no colours or labels
+    if config.format != Format::Text {
+        if let Err(e) = table::run(&config) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if !config.theme.enabled {
+        colored::control::set_override(false);
+    }
+
+    //Make sure ansi support works in terminal
+    let _ = ansi_term::enable_ansi_support();
+
+    loop {
+        for section in &config.sections {
+            match section {
+                Section::Host => print_host(&config),
+                Section::Uptime => print_uptime(&config),
+                Section::Memory => print_memory(&config),
+                Section::Disks => print_disks(&config),
+                Section::Load => print_load(&config),
+                Section::Users => print_users(&config),
+                Section::Procs => print_procs(&config),
+                Section::Net => print_net(&config),
+                Section::Sockets => print_sockets(&config),
+                Section::Services => print_services(&config),
+                Section::Pressure => print_pressure(&config),
+                Section::Cpu => print_cpu(&config),
+                Section::Power => print_power(&config),
+            }
+        }
+
+        match config.watch {
+            Some(seconds) => {
+                println!();
+                std::thread::sleep(std::time::Duration::from_secs(seconds as u64));
+            }
+            None => break,
+        }
+    }
 }
@@ -0,0 +1,401 @@
+use crate::config::{Config, Unit};
+use crate::procs::format_rate;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Default, Debug, Clone)]
+pub struct Interface {
+    //Alias on Windows (Ethernet, Wi-Fi), kernel name on Linux (eth0, wlan0)
+    pub name: String,
+    pub kind: InterfaceKind,
+    pub state: String,
+    pub mac: String,
+    pub ipv4: Vec<IpAddr>,
+    pub ipv6: Vec<IpAddr>,
+    //Bits per second, 0 when the driver does not report it
+    pub speed: u64,
+    pub mtu: u32,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_drops: u64,
+    pub tx_drops: u64,
+    //Bytes per second between the two samples
+    pub rx_rate: f64,
+    pub tx_rate: f64,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum InterfaceKind {
+    #[default]
+    Other,
+    Ethernet,
+    Wireless,
+    Loopback,
+    Tunnel,
+}
+
+impl InterfaceKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InterfaceKind::Other => "other",
+            InterfaceKind::Ethernet => "ethernet",
+            InterfaceKind::Wireless => "wireless",
+            InterfaceKind::Loopback => "loopback",
+            InterfaceKind::Tunnel => "tunnel",
+        }
+    }
+}
+
+fn format_mac(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(":")
+}
+
+#[cfg(windows)]
+unsafe fn unicast_addresses() -> HashMap<u32, Vec<IpAddr>> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use winapi::shared::netioapi::{FreeMibTable, GetUnicastIpAddressTable, PMIB_UNICASTIPADDRESS_TABLE};
+    use winapi::shared::ws2def::{AF_INET, AF_INET6, AF_UNSPEC};
+
+    let mut addresses: HashMap<u32, Vec<IpAddr>> = HashMap::new();
+    let mut table: PMIB_UNICASTIPADDRESS_TABLE = std::ptr::null_mut();
+    if GetUnicastIpAddressTable(AF_UNSPEC as u16, &mut table) != 0 {
+        return addresses;
+    }
+
+    let rows = std::slice::from_raw_parts((*table).Table.as_ptr(), (*table).NumEntries as usize);
+    for row in rows {
+        let addr = match *row.Address.si_family() as i32 {
+            AF_INET => {
+                let b = row.Address.Ipv4().sin_addr.S_un.S_un_b();
+                IpAddr::V4(Ipv4Addr::new(b.s_b1, b.s_b2, b.s_b3, b.s_b4))
+            }
+            AF_INET6 => IpAddr::V6(Ipv6Addr::from(*row.Address.Ipv6().sin6_addr.u.Byte())),
+            _ => continue,
+        };
+        addresses.entry(row.InterfaceIndex).or_default().push(addr);
+    }
+
+    FreeMibTable(table as _);
+
+    return addresses;
+}
+
+//Every interface with its cumulative counters at this instant
+#[cfg(windows)]
+pub(crate) fn snapshot() -> Vec<Interface> {
+    use winapi::shared::ifdef::IfOperStatusUp;
+    use winapi::shared::ipifcons::{IF_TYPE_ETHERNET_CSMACD, IF_TYPE_IEEE80211, IF_TYPE_SOFTWARE_LOOPBACK, IF_TYPE_TUNNEL};
+    use winapi::shared::netioapi::{FreeMibTable, GetIfTable2, PMIB_IF_TABLE2};
+
+    let mut interfaces: Vec<Interface> = vec![];
+
+    unsafe {
+        let mut table: PMIB_IF_TABLE2 = std::ptr::null_mut();
+        if GetIfTable2(&mut table) != 0 {
+            return interfaces;
+        }
+        let mut addresses = unicast_addresses();
+
+        let rows = std::slice::from_raw_parts((*table).Table.as_ptr(), (*table).NumEntries as usize);
+        for row in rows {
+            //Skip the filter driver layers (QoS, WFP, ...) stacked on each adapter
+            if row.InterfaceAndOperStatusFlags.FilterInterface() != 0 {
+                continue;
+            }
+
+            let addrs = addresses.remove(&row.InterfaceIndex).unwrap_or_default();
+            let alias_len = row.Alias.iter().position(|&c| c == 0).unwrap_or(row.Alias.len());
+
+            interfaces.push(Interface {
+                name: String::from_utf16_lossy(&row.Alias[..alias_len]),
+                kind: match row.Type {
+                    IF_TYPE_ETHERNET_CSMACD => InterfaceKind::Ethernet,
+                    IF_TYPE_IEEE80211 => InterfaceKind::Wireless,
+                    IF_TYPE_SOFTWARE_LOOPBACK => InterfaceKind::Loopback,
+                    IF_TYPE_TUNNEL => InterfaceKind::Tunnel,
+                    _ => InterfaceKind::Other,
+                },
+                state: if row.OperStatus == IfOperStatusUp { "up" } else { "down" }.to_string(),
+                mac: format_mac(&row.PhysicalAddress[..(row.PhysicalAddressLength as usize).min(row.PhysicalAddress.len())]),
+                ipv4: addrs.iter().filter(|a| a.is_ipv4()).cloned().collect(),
+                ipv6: addrs.iter().filter(|a| a.is_ipv6()).cloned().collect(),
+                speed: row.ReceiveLinkSpeed.max(row.TransmitLinkSpeed),
+                mtu: row.Mtu,
+                rx_bytes: row.InOctets,
+                tx_bytes: row.OutOctets,
+                rx_packets: row.InUcastPkts + row.InNUcastPkts,
+                tx_packets: row.OutUcastPkts + row.OutNUcastPkts,
+                rx_errors: row.InErrors,
+                tx_errors: row.OutErrors,
+                rx_drops: row.InDiscards,
+                tx_drops: row.OutDiscards,
+                ..Default::default()
+            });
+        }
+
+        FreeMibTable(table as _);
+    }
+
+    return interfaces;
+}
+
+//Counters from /proc/net/dev, two header lines then "name: rx fields... tx fields..."
+#[cfg(not(windows))]
+pub(crate) fn parse_net_dev(text: &str) -> Vec<Interface> {
+    let mut interfaces: Vec<Interface> = vec![];
+
+    for line in text.lines().skip(2) {
+        let (name, counters) = match line.split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+        let fields: Vec<u64> = counters.split_whitespace().map(|f| f.parse().unwrap_or(0)).collect();
+        if fields.len() < 16 {
+            continue;
+        }
+
+        interfaces.push(Interface {
+            name: name.trim().to_string(),
+            rx_bytes: fields[0],
+            rx_packets: fields[1],
+            rx_errors: fields[2],
+            rx_drops: fields[3],
+            tx_bytes: fields[8],
+            tx_packets: fields[9],
+            tx_errors: fields[10],
+            tx_drops: fields[11],
+            ..Default::default()
+        });
+    }
+
+    return interfaces;
+}
+
+//ARPHRD_* values from /sys/class/net/<name>/type, wireless devices are plain ARPHRD_ETHER
+#[cfg(not(windows))]
+fn kind_from_sys(dir: &std::path::Path) -> InterfaceKind {
+    if dir.join("wireless").exists() {
+        return InterfaceKind::Wireless;
+    }
+    match std::fs::read_to_string(dir.join("type")).ok().and_then(|t| t.trim().parse::<u32>().ok()) {
+        Some(1) => InterfaceKind::Ethernet,
+        Some(772) => InterfaceKind::Loopback,
+        Some(768) | Some(769) | Some(776) | Some(778) | Some(823) | Some(65534) => InterfaceKind::Tunnel,
+        _ => InterfaceKind::Other,
+    }
+}
+
+//Leading fields of struct ifaddrs, only ever read through the pointers getifaddrs returns
+#[cfg(not(windows))]
+#[repr(C)]
+struct IfAddrs {
+    ifa_next: *mut IfAddrs,
+    ifa_name: *const std::os::raw::c_char,
+    _ifa_flags: std::os::raw::c_uint,
+    //sockaddr, starting with the address family
+    ifa_addr: *const u16,
+}
+
+#[cfg(not(windows))]
+extern "C" {
+    fn getifaddrs(ifap: *mut *mut IfAddrs) -> std::os::raw::c_int;
+    fn freeifaddrs(ifa: *mut IfAddrs);
+}
+
+//Interface addresses from getifaddrs, which glibc fetches over an RTM_GETADDR netlink request
+#[cfg(not(windows))]
+unsafe fn interface_addresses() -> HashMap<String, Vec<IpAddr>> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const AF_INET: u16 = 2;
+    const AF_INET6: u16 = 10;
+
+    let mut addresses: HashMap<String, Vec<IpAddr>> = HashMap::new();
+    let mut list: *mut IfAddrs = std::ptr::null_mut();
+    if getifaddrs(&mut list) != 0 {
+        return addresses;
+    }
+
+    let mut entry = list;
+    while !entry.is_null() {
+        let ifa = &*entry;
+        entry = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+
+        //sockaddr_in keeps the address at byte 4, sockaddr_in6 at byte 8
+        let sockaddr = ifa.ifa_addr as *const u8;
+        let addr = match *ifa.ifa_addr {
+            AF_INET => {
+                let mut octets = [0u8; 4];
+                std::ptr::copy_nonoverlapping(sockaddr.add(4), octets.as_mut_ptr(), 4);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            AF_INET6 => {
+                let mut octets = [0u8; 16];
+                std::ptr::copy_nonoverlapping(sockaddr.add(8), octets.as_mut_ptr(), 16);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => continue,
+        };
+
+        let name = std::ffi::CStr::from_ptr(ifa.ifa_name).to_string_lossy().into_owned();
+        addresses.entry(name).or_default().push(addr);
+    }
+
+    freeifaddrs(list);
+
+    return addresses;
+}
+
+#[cfg(not(windows))]
+pub(crate) fn snapshot() -> Vec<Interface> {
+    let mut interfaces = match std::fs::read_to_string("/proc/net/dev") {
+        Ok(text) => parse_net_dev(&text),
+        Err(_) => return vec![],
+    };
+    let mut addresses = unsafe { interface_addresses() };
+
+    for i in interfaces.iter_mut() {
+        let dir = std::path::Path::new("/sys/class/net").join(&i.name);
+        let read = |file: &str| std::fs::read_to_string(dir.join(file)).map(|s| s.trim().to_string()).unwrap_or_default();
+
+        i.kind = kind_from_sys(&dir);
+        i.state = read("operstate");
+        i.mac = read("address");
+        i.mtu = read("mtu").parse().unwrap_or(0);
+        //Mb/s, reading it fails or gives -1 while the link is down
+        i.speed = read("speed").parse::<u64>().map(|s| s * 1_000_000).unwrap_or(0);
+
+        let addrs = addresses.remove(&i.name).unwrap_or_default();
+        i.ipv4 = addrs.iter().filter(|a| a.is_ipv4()).cloned().collect();
+        i.ipv6 = addrs.iter().filter(|a| a.is_ipv6()).cloned().collect();
+    }
+
+    return interfaces;
+}
+
+//Take two snapshots interval seconds apart and work out the byte rates in between
+pub(crate) fn sample(interval: u32) -> Vec<Interface> {
+    let first: HashMap<String, Interface> = snapshot().into_iter().map(|i| (i.name.clone(), i)).collect();
+    let start = Instant::now();
+
+    std::thread::sleep(Duration::from_secs(interval as u64));
+
+    let mut second = snapshot();
+    let elapsed_ms = start.elapsed().as_millis().max(1) as f64;
+
+    for i in second.iter_mut() {
+        if let Some(old) = first.get(&i.name) {
+            i.rx_rate = i.rx_bytes.saturating_sub(old.rx_bytes) as f64 * 1000.0 / elapsed_ms;
+            i.tx_rate = i.tx_bytes.saturating_sub(old.tx_bytes) as f64 * 1000.0 / elapsed_ms;
+        }
+    }
+
+    return second;
+}
+
+//Filter matches an interface type (ethernet, wireless, loopback, tunnel, other)
+//or a case insensitive part of the interface name
+pub(crate) fn filter(mut interfaces: Vec<Interface>, filter: &str) -> Vec<Interface> {
+    let filter = filter.to_lowercase();
+    interfaces.retain(|i| filter.is_empty() || i.kind.name() == filter || i.name.to_lowercase().contains(&filter));
+    return interfaces;
+}
+
+fn format_list(addrs: &[IpAddr]) -> String {
+    if addrs.is_empty() {
+        return "-".to_string();
+    }
+    addrs.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(", ")
+}
+
+fn format_speed(bits_per_sec: u64) -> String {
+    match bits_per_sec {
+        0 => "-".to_string(),
+        s if s >= 1_000_000_000 => format!("{} Gb/s", s as f64 / 1_000_000_000.0),
+        s => format!("{} Mb/s", s / 1_000_000),
+    }
+}
+
+pub(crate) fn print_interfaces(interfaces: &[Interface], unit: Unit) {
+    for i in interfaces {
+        println!(
+            "Interface: {} | Type: {} | State: {} | MAC: {} | Speed: {} | MTU: {}",
+            i.name,
+            i.kind.name(),
+            i.state,
+            if i.mac.is_empty() { "-" } else { &i.mac },
+            format_speed(i.speed),
+            i.mtu
+        );
+        println!("  IPv4: {} | IPv6: {}", format_list(&i.ipv4), format_list(&i.ipv6));
+        println!(
+            "  RX: {} {u} {} packets {} errors {} drops {} | TX: {} {u} {} packets {} errors {} drops {}",
+            unit.format_bytes(i.rx_bytes),
+            i.rx_packets,
+            i.rx_errors,
+            i.rx_drops,
+            format_rate(i.rx_rate),
+            unit.format_bytes(i.tx_bytes),
+            i.tx_packets,
+            i.tx_errors,
+            i.tx_drops,
+            format_rate(i.tx_rate),
+            u = unit.label()
+        );
+    }
+}
+
+//Entry point for `uptime net [--filter NAME|TYPE]`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let name = match args {
+        [] => config.net_filter.clone(),
+        [flag, value] if flag == "--filter" => value.clone(),
+        _ => return Err("usage: uptime net [--filter NAME|ethernet|wireless|loopback|tunnel|other]".into()),
+    };
+
+    let interfaces = filter(sample(config.cpu_interval), &name);
+    print_interfaces(&interfaces, config.memory_unit);
+
+    return Ok(());
+}
+
+//The /proc/net/dev parser only exists off Windows
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    const NET_DEV: &str = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 2776770   11307    0    0    0     0          0         0  2776770   11307    0    0    0     0       0          0
+  eth0:123 456 7 8 9 10 11 12 789 1011 13 14 15 16 17 18
+ wlan0: 1 2 3
+";
+
+    fn counters(i: &Interface) -> [u64; 8] {
+        return [i.rx_bytes, i.rx_packets, i.rx_errors, i.rx_drops, i.tx_bytes, i.tx_packets, i.tx_errors, i.tx_drops];
+    }
+
+    #[test]
+    fn net_dev() {
+        let interfaces = parse_net_dev(NET_DEV);
+        //The two header lines and the short wlan0 row are skipped
+        assert_eq!(interfaces.iter().map(|i| i.name.as_str()).collect::<Vec<&str>>(), ["lo", "eth0"]);
+        assert_eq!(counters(&interfaces[0]), [2776770, 11307, 0, 0, 2776770, 11307, 0, 0]);
+        //Receive is bytes, packets, errs, drop then four more columns before transmit starts
+        assert_eq!(counters(&interfaces[1]), [123, 456, 7, 8, 789, 1011, 13, 14]);
+        assert_eq!((interfaces[1].rx_rate, interfaces[1].speed), (0.0, 0));
+
+        assert!(parse_net_dev("").is_empty());
+        assert!(parse_net_dev("eth0: 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16\n").is_empty());
+    }
+}
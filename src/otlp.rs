@@ -0,0 +1,865 @@
+use crate::config::Config;
+use crate::daemon;
+use crate::disks::Disks;
+use crate::host::Host;
+use crate::ipc;
+use crate::system::{Memory, Processor, SystemInfo, Uptime};
+
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const GRPC_PATH: &str = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+
+//HTTP/2 frame types and flags used by the gRPC client
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_RST_STREAM: u8 = 0x3;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_PING: u8 = 0x6;
+const FRAME_GOAWAY: u8 = 0x7;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+
+//Limits every HTTP/2 peer accepts before its SETTINGS arrive
+const MAX_FRAME: usize = 16384;
+const INITIAL_WINDOW: usize = 65535;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Protocol {
+    //OTLP/HTTP with a protobuf body, port 4318
+    Http,
+    //OTLP/gRPC over cleartext HTTP/2, port 4317
+    Grpc,
+}
+
+impl Protocol {
+    pub(crate) fn parse(s: &str) -> Option<Protocol> {
+        match s.to_lowercase().as_str() {
+            "http" | "http/protobuf" => Some(Protocol::Http),
+            "grpc" => Some(Protocol::Grpc),
+            _ => None,
+        }
+    }
+
+    fn default_endpoint(&self) -> &'static str {
+        match self {
+            Protocol::Http => "http://localhost:4318",
+            Protocol::Grpc => "http://localhost:4317",
+        }
+    }
+}
+
+//http://host:port[/path]. Only cleartext is supported, TLS is left to a local collector
+#[derive(Debug, Clone)]
+struct Endpoint {
+    authority: String,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(s: &str, protocol: Protocol) -> Result<Endpoint, Box<dyn Error>> {
+        let rest = match s.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some(("https", _)) => return Err(format!("{}: https is not supported, export to a local collector over http", s).into()),
+            Some((scheme, _)) => return Err(format!("Unsupported scheme '{}' in '{}'", scheme, s).into()),
+            None => s,
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(format!("Missing host in OTLP endpoint '{}'", s).into());
+        }
+
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            let port = if protocol == Protocol::Grpc { 4317 } else { 4318 };
+            format!("{}:{}", authority, port)
+        };
+        //The HTTP endpoint is the base URL, the signal path is appended as the SDKs do
+        let path = match protocol {
+            Protocol::Grpc => GRPC_PATH.to_string(),
+            Protocol::Http if path.ends_with("/v1/metrics") => path.to_string(),
+            Protocol::Http => format!("{}/v1/metrics", path.trim_end_matches('/')),
+        };
+
+        return Ok(Endpoint { authority, path });
+    }
+
+    fn connect(&self) -> Result<TcpStream, Box<dyn Error>> {
+        let addr = self
+            .authority
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("{}: no addresses", self.authority))?;
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        return Ok(stream);
+    }
+}
+
+//Protobuf writer for the handful of wire types the OTLP messages use
+#[derive(Default)]
+struct Proto(Vec<u8>);
+
+impl Proto {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    fn key(&mut self, field: u32, wire: u8) {
+        self.varint(((field as u64) << 3) | wire as u64);
+    }
+
+    fn uint(&mut self, field: u32, v: u64) {
+        self.key(field, 0);
+        self.varint(v);
+    }
+
+    fn fixed64(&mut self, field: u32, v: u64) {
+        self.key(field, 1);
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn double(&mut self, field: u32, v: f64) {
+        self.fixed64(field, v.to_bits());
+    }
+
+    fn bytes(&mut self, field: u32, v: &[u8]) {
+        self.key(field, 2);
+        self.varint(v.len() as u64);
+        self.0.extend_from_slice(v);
+    }
+
+    fn string(&mut self, field: u32, v: &str) {
+        self.bytes(field, v.as_bytes());
+    }
+
+    fn message(&mut self, field: u32, v: Proto) {
+        self.bytes(field, &v.0);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Int(i64),
+    Double(f64),
+}
+
+//Gauges are sampled values, UpDownCounters are cumulative non-monotonic sums such as bytes used
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Gauge,
+    UpDownCounter,
+}
+
+#[derive(Debug, Clone)]
+struct Metric {
+    name: &'static str,
+    description: &'static str,
+    unit: &'static str,
+    kind: Kind,
+    points: Vec<(Vec<(&'static str, String)>, Number)>,
+}
+
+impl Metric {
+    fn new(name: &'static str, description: &'static str, unit: &'static str, kind: Kind) -> Metric {
+        return Metric { name, description, unit, kind, points: vec![] };
+    }
+
+    fn point(mut self, attributes: Vec<(&'static str, String)>, value: Number) -> Metric {
+        self.points.push((attributes, value));
+        return self;
+    }
+}
+
+//The system.* metrics from the OpenTelemetry semantic conventions that we have data for
+fn collect(config: &Config) -> Vec<Metric> {
+    let state = |key: &'static str, value: &str| vec![(key, value.to_string())];
+    let mut metrics = vec![];
+
+    let mut u: Uptime = SystemInfo::new();
+    let u = u.info();
+    metrics.push(
+        Metric::new("system.uptime", "The time the system has been running", "s", Kind::Gauge)
+            .point(vec![], Number::Double(u.uptime_ms as f64 / 1000.0)),
+    );
+
+    let l = ipc::load(config);
+    let mut p: Processor = SystemInfo::new();
+    let p = p.info();
+    metrics.push(
+        Metric::new("system.cpu.utilization", "Share of CPU time in use across all processors", "1", Kind::Gauge)
+            .point(vec![], Number::Double(l.processor_activity / 100.0)),
+    );
+    metrics.push(
+        Metric::new("system.cpu.logical.count", "Number of logical processors", "{cpu}", Kind::UpDownCounter)
+            .point(vec![], Number::Int(p.number_processors as i64)),
+    );
+    metrics.push(
+        Metric::new("system.cpu.frequency", "Current processor frequency", "Hz", Kind::Gauge)
+            .point(vec![], Number::Int(p.current_mhz as i64 * 1_000_000)),
+    );
+    //Load averages use the hostmetrics receiver names, the conventions do not define them
+    for (name, value) in [
+        ("system.cpu.load_average.1m", l.avg_1_load),
+        ("system.cpu.load_average.5m", l.avg_5_load),
+        ("system.cpu.load_average.15m", l.avg_15_load),
+    ] {
+        metrics.push(Metric::new(name, "Average run queue length", "{thread}", Kind::Gauge).point(vec![], Number::Double(value)));
+    }
+
+    let mut m: Memory = SystemInfo::new();
+    let m = m.info();
+    //Memory reports megabytes
+    let bytes = |v: u64| (v * 1024 * 1024) as i64;
+    let used = m.installed_ram.saturating_sub(m.available_memory);
+    metrics.push(
+        Metric::new("system.memory.usage", "Memory in use and free", "By", Kind::UpDownCounter)
+            .point(state("system.memory.state", "used"), Number::Int(bytes(used)))
+            .point(state("system.memory.state", "free"), Number::Int(bytes(m.available_memory))),
+    );
+    metrics.push(
+        Metric::new("system.memory.limit", "Total memory available to the system", "By", Kind::UpDownCounter)
+            .point(vec![], Number::Int(bytes(m.limit_memory.unwrap_or(m.installed_ram).min(m.installed_ram)))),
+    );
+    let installed = m.installed_ram.max(1) as f64;
+    metrics.push(
+        Metric::new("system.memory.utilization", "Share of memory in use and free", "1", Kind::Gauge)
+            .point(state("system.memory.state", "used"), Number::Double(used as f64 / installed))
+            .point(state("system.memory.state", "free"), Number::Double(m.available_memory as f64 / installed)),
+    );
+    metrics.push(
+        Metric::new("system.paging.usage", "Page file in use and free", "By", Kind::UpDownCounter)
+            .point(state("system.paging.state", "used"), Number::Int(bytes(m.used_page)))
+            .point(state("system.paging.state", "free"), Number::Int(bytes(m.available_page))),
+    );
+
+    let mut usage = Metric::new("system.filesystem.usage", "Filesystem space in use and free", "By", Kind::UpDownCounter);
+    let mut utilization = Metric::new("system.filesystem.utilization", "Share of filesystem space in use", "1", Kind::Gauge);
+    let mut d = Disks::new();
+    for disk in unsafe { d.info() }.iter().filter(|d| config.include_disk(d)) {
+        let attributes = |state: Option<&str>| {
+            let mut a = vec![
+                ("system.device", disk.drive.trim_end_matches('\\').to_string()),
+                ("system.filesystem.mountpoint", disk.drive.clone()),
+                ("system.filesystem.type", disk.file_system.clone()),
+            ];
+            if let Some(s) = state {
+                a.push(("system.filesystem.state", s.to_string()));
+            }
+            a
+        };
+        let used = disk.total_bytes.saturating_sub(disk.available_bytes);
+        usage = usage
+            .point(attributes(Some("used")), Number::Int(used as i64))
+            .point(attributes(Some("free")), Number::Int(disk.available_bytes as i64));
+        utilization = utilization.point(attributes(None), Number::Double(used as f64 / disk.total_bytes.max(1) as f64));
+    }
+    metrics.push(usage);
+    metrics.push(utilization);
+
+    return metrics;
+}
+
+//Resource attributes describing this host, names from the host.* and os.* conventions
+fn resource(host: &Host) -> Vec<(&'static str, String)> {
+    let arch = match host.arch.as_str() {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "arm" => "arm32",
+        other => other,
+    };
+    let description = format!("{} {}", host.os_name, host.version);
+
+    return vec![
+        ("service.name", "uptime".to_string()),
+        ("host.name", host.hostname.clone()),
+        ("host.arch", arch.to_string()),
+        ("os.type", if cfg!(windows) { "windows" } else { "linux" }.to_string()),
+        ("os.name", host.os_name.clone()),
+        ("os.description", description.trim().to_string()),
+        ("os.version", host.kernel.clone()),
+    ];
+}
+
+fn key_values(message: &mut Proto, field: u32, attributes: &[(&'static str, String)]) {
+    for (key, value) in attributes {
+        let mut any = Proto::default();
+        any.string(1, value);
+        let mut kv = Proto::default();
+        kv.string(1, key);
+        kv.message(2, any);
+        message.message(field, kv);
+    }
+}
+
+//ExportMetricsServiceRequest with one ResourceMetrics and one ScopeMetrics.
+//start_ns is when we started exporting, the start of the cumulative sums
+fn encode(resource_attributes: &[(&'static str, String)], metrics: &[Metric], start_ns: u64, now_ns: u64) -> Vec<u8> {
+    let mut resource = Proto::default();
+    key_values(&mut resource, 1, resource_attributes);
+
+    let mut scope = Proto::default();
+    let mut name = Proto::default();
+    name.string(1, "uptime");
+    name.string(2, env!("CARGO_PKG_VERSION"));
+    scope.message(1, name);
+
+    for metric in metrics {
+        let mut data = Proto::default();
+        for (attributes, value) in &metric.points {
+            let mut point = Proto::default();
+            point.fixed64(2, start_ns);
+            point.fixed64(3, now_ns);
+            match value {
+                Number::Double(v) => point.double(4, *v),
+                //as_int is sfixed64
+                Number::Int(v) => point.fixed64(6, *v as u64),
+            }
+            key_values(&mut point, 7, attributes);
+            data.message(1, point);
+        }
+
+        let mut m = Proto::default();
+        m.string(1, metric.name);
+        m.string(2, metric.description);
+        m.string(3, metric.unit);
+        match metric.kind {
+            Kind::Gauge => m.message(5, data),
+            Kind::UpDownCounter => {
+                //Cumulative temporality, not monotonic
+                data.uint(2, 2);
+                m.message(7, data);
+            }
+        }
+        scope.message(2, m);
+    }
+
+    let mut resource_metrics = Proto::default();
+    resource_metrics.message(1, resource);
+    resource_metrics.message(2, scope);
+
+    let mut request = Proto::default();
+    request.message(1, resource_metrics);
+    return request.0;
+}
+
+fn send_http(endpoint: &Endpoint, body: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut stream = endpoint.connect()?;
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: uptime/{}\r\nContent-Type: application/x-protobuf\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        endpoint.path,
+        endpoint.authority,
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response)?;
+    let status_line = String::from_utf8_lossy(&response).lines().next().unwrap_or_default().to_string();
+    let status: u32 = status_line.split_whitespace().nth(1).and_then(|s| s.parse().ok()).unwrap_or(0);
+    if !(200..300).contains(&status) {
+        return Err(format!("OTLP export to {} failed: {}", endpoint.authority, status_line).into());
+    }
+
+    return Ok(());
+}
+
+//HPACK integer with an n bit prefix (RFC 7541 5.1)
+fn hpack_int(out: &mut Vec<u8>, prefix_bits: u32, flags: u8, mut v: usize) {
+    let max = (1usize << prefix_bits) - 1;
+    if v < max {
+        out.push(flags | v as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    v -= max;
+    while v >= 0x80 {
+        out.push((v % 0x80) as u8 | 0x80);
+        v /= 0x80;
+    }
+    out.push(v as u8);
+}
+
+//Literal header field without indexing. The name is a static table index, or 0 for a new name.
+//Strings are sent without Huffman coding, which every decoder accepts
+fn hpack_literal(out: &mut Vec<u8>, index: usize, name: &str, value: &str) {
+    hpack_int(out, 4, 0, index);
+    if index == 0 {
+        hpack_int(out, 7, 0, name.len());
+        out.extend_from_slice(name.as_bytes());
+    }
+    hpack_int(out, 7, 0, value.len());
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&stream.to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+//(type, flags, stream id, payload)
+type Frame = (u8, u8, u32, Vec<u8>);
+
+fn read_frame(stream: &mut TcpStream) -> Result<Frame, Box<dyn Error>> {
+    let mut header = [0u8; 9];
+    stream.read_exact(&mut header)?;
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload)?;
+    return Ok((header[3], header[4], id, payload));
+}
+
+//A single unary gRPC call over cleartext HTTP/2 with prior knowledge, just enough of the
+//protocol for Export. Decoding the grpc-status trailer needs a full HPACK decoder, so success is
+//judged by the response message: a failed call ends with trailers and no DATA
+fn send_grpc(endpoint: &Endpoint, body: &[u8]) -> Result<(), Box<dyn Error>> {
+    //gRPC message: compressed flag and big endian length before the protobuf
+    let mut message = vec![0u8];
+    message.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    message.extend_from_slice(body);
+    if message.len() > INITIAL_WINDOW {
+        return Err(format!("OTLP export of {} bytes is too large for a single gRPC request", message.len()).into());
+    }
+
+    let mut headers = vec![];
+    //Indexed :method POST and :scheme http from the static table
+    headers.push(0x83);
+    headers.push(0x86);
+    hpack_literal(&mut headers, 4, ":path", &endpoint.path);
+    hpack_literal(&mut headers, 1, ":authority", &endpoint.authority);
+    hpack_literal(&mut headers, 31, "content-type", "application/grpc");
+    hpack_literal(&mut headers, 0, "te", "trailers");
+    hpack_literal(&mut headers, 58, "user-agent", &format!("uptime/{}", env!("CARGO_PKG_VERSION")));
+
+    let mut out = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+    frame(&mut out, FRAME_SETTINGS, 0, 0, &[]);
+    frame(&mut out, FRAME_HEADERS, FLAG_END_HEADERS, 1, &headers);
+    let chunks: Vec<&[u8]> = message.chunks(MAX_FRAME).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let flags = if i == chunks.len() - 1 { FLAG_END_STREAM } else { 0 };
+        frame(&mut out, FRAME_DATA, flags, 1, chunk);
+    }
+
+    let mut stream = endpoint.connect()?;
+    stream.write_all(&out)?;
+
+    let mut received = 0;
+    loop {
+        let (kind, flags, id, mut payload) = read_frame(&mut stream)?;
+        match kind {
+            FRAME_SETTINGS if flags & FLAG_ACK == 0 => {
+                let mut ack = vec![];
+                frame(&mut ack, FRAME_SETTINGS, FLAG_ACK, 0, &[]);
+                stream.write_all(&ack)?;
+            }
+            FRAME_PING if flags & FLAG_ACK == 0 => {
+                let mut ack = vec![];
+                frame(&mut ack, FRAME_PING, FLAG_ACK, 0, &payload);
+                stream.write_all(&ack)?;
+            }
+            FRAME_GOAWAY if payload.len() >= 8 => {
+                let code = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]);
+                if code != 0 || received == 0 {
+                    return Err(format!("OTLP export to {} failed: connection closed (HTTP/2 error {})", endpoint.authority, code).into());
+                }
+            }
+            FRAME_RST_STREAM if id == 1 => {
+                let code = payload.get(..4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])).unwrap_or(0);
+                return Err(format!("OTLP export to {} failed: stream reset (HTTP/2 error {})", endpoint.authority, code).into());
+            }
+            FRAME_DATA if id == 1 => {
+                if flags & FLAG_PADDED != 0 && !payload.is_empty() {
+                    let pad = payload[0] as usize;
+                    payload = payload[1..payload.len().saturating_sub(pad).max(1)].to_vec();
+                }
+                received += payload.len();
+            }
+            _ => {}
+        }
+
+        if id == 1 && flags & FLAG_END_STREAM != 0 && (kind == FRAME_DATA || kind == FRAME_HEADERS) {
+            break;
+        }
+    }
+
+    //An ExportMetricsServiceResponse is at least the 5 byte message prefix
+    if received < 5 {
+        return Err(format!("OTLP export to {} was rejected, see the collector log for the gRPC status", endpoint.authority).into());
+    }
+
+    return Ok(());
+}
+
+fn now_ns() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+
+//Entry point for `uptime otlp [--endpoint URL] [--protocol http|grpc] [--interval SECONDS] [--once]`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime otlp [--endpoint http://HOST:PORT] [--protocol http|grpc] [--interval SECONDS] [--once]";
+    let mut endpoint = config.otlp_endpoint.clone();
+    let mut protocol = config.otlp_protocol;
+    let mut interval = config.otlp_interval;
+    let mut once = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--once" => once = true,
+            "--endpoint" => endpoint = Some(iter.next().ok_or(usage)?.clone()),
+            "--protocol" => protocol = iter.next().and_then(|p| Protocol::parse(p)).ok_or(usage)?,
+            "--interval" => interval = iter.next().and_then(|v| v.parse().ok()).filter(|v| *v > 0).ok_or(usage)?,
+            _ => return Err(usage.into()),
+        }
+    }
+
+    let endpoint = Endpoint::parse(endpoint.as_deref().unwrap_or(protocol.default_endpoint()), protocol)?;
+    let attributes = resource(&Host::info());
+    let start = now_ns();
+
+    loop {
+        let body = encode(&attributes, &collect(config), start, now_ns());
+        let result = match protocol {
+            Protocol::Http => send_http(&endpoint, &body),
+            Protocol::Grpc => send_grpc(&endpoint, &body),
+        };
+
+        match result {
+            Ok(()) => {}
+            Err(e) if once => return Err(e),
+            //Gauges are current values, so a failed export is not retried, the next one replaces it
+            Err(e) => eprintln!("{}", e),
+        }
+
+        if once || !daemon::wait(Duration::from_secs(interval as u64)) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    //(field number, wire type, payload) for each field of a message, varints as 8 little endian bytes
+    fn decode(buf: &[u8]) -> Vec<(u32, u8, Vec<u8>)> {
+        let varint = |pos: &mut usize| {
+            let mut v = 0u64;
+            let mut shift = 0;
+            loop {
+                let b = buf[*pos];
+                *pos += 1;
+                v |= ((b & 0x7f) as u64) << shift;
+                if b < 0x80 {
+                    return v;
+                }
+                shift += 7;
+            }
+        };
+
+        let mut fields = vec![];
+        let mut pos = 0;
+        while pos < buf.len() {
+            let key = varint(&mut pos);
+            let wire = (key & 7) as u8;
+            let value = match wire {
+                0 => varint(&mut pos).to_le_bytes().to_vec(),
+                1 => {
+                    pos += 8;
+                    buf[pos - 8..pos].to_vec()
+                }
+                2 => {
+                    let len = varint(&mut pos) as usize;
+                    pos += len;
+                    buf[pos - len..pos].to_vec()
+                }
+                _ => panic!("unexpected wire type {}", wire),
+            };
+            fields.push(((key >> 3) as u32, wire, value));
+        }
+        return fields;
+    }
+
+    //Payloads of every occurrence of a field, checking its wire type
+    fn field(message: &[u8], number: u32, wire: u8) -> Vec<Vec<u8>> {
+        let fields = decode(message);
+        assert!(fields.iter().filter(|f| f.0 == number).all(|f| f.1 == wire), "field {} wire type", number);
+        return fields.into_iter().filter(|f| f.0 == number).map(|f| f.2).collect();
+    }
+
+    fn u64_at(bytes: &[u8]) -> u64 {
+        return u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+
+    fn text(bytes: &[u8]) -> &str {
+        return std::str::from_utf8(bytes).unwrap();
+    }
+
+    #[test]
+    fn protobuf_varint_and_fixed64() {
+        let encoded = |v: u64| {
+            let mut p = Proto::default();
+            p.varint(v);
+            p.0
+        };
+        assert_eq!(encoded(0), vec![0x00]);
+        assert_eq!(encoded(127), vec![0x7f]);
+        assert_eq!(encoded(128), vec![0x80, 0x01]);
+        assert_eq!(encoded(300), vec![0xac, 0x02]);
+        assert_eq!(encoded(u64::MAX), vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+
+        let mut p = Proto::default();
+        p.uint(2, 2);
+        p.fixed64(3, 0x0102);
+        p.double(4, 1.0);
+        p.string(1, "ab");
+        let mut expected = vec![0x10, 0x02, 0x19, 0x02, 0x01, 0, 0, 0, 0, 0, 0, 0x21];
+        expected.extend_from_slice(&1.0f64.to_le_bytes());
+        expected.extend_from_slice(&[0x0a, 0x02, b'a', b'b']);
+        assert_eq!(p.0, expected);
+    }
+
+    #[test]
+    fn hpack_integers_rfc7541_c1() {
+        let encoded = |prefix: u32, v: usize| {
+            let mut out = vec![];
+            hpack_int(&mut out, prefix, 0, v);
+            out
+        };
+        //C.1.1, C.1.2 and C.1.3
+        assert_eq!(encoded(5, 10), vec![0x0a]);
+        assert_eq!(encoded(5, 1337), vec![0x1f, 0x9a, 0x0a]);
+        assert_eq!(encoded(8, 42), vec![0x2a]);
+        //A value equal to the prefix maximum needs a zero continuation byte
+        assert_eq!(encoded(4, 15), vec![0x0f, 0x00]);
+
+        let mut out = vec![];
+        hpack_literal(&mut out, 0, "te", "trailers");
+        assert_eq!(out, b"\x00\x02te\x08trailers".to_vec());
+    }
+
+    #[test]
+    fn export_request_field_numbers() {
+        let metrics = vec![
+            Metric::new("system.memory.usage", "Memory in use", "By", Kind::UpDownCounter)
+                .point(vec![("system.memory.state", "used".to_string())], Number::Int(1024)),
+            Metric::new("system.cpu.load_average.1m", "Load", "{thread}", Kind::Gauge).point(vec![], Number::Double(0.5)),
+        ];
+        let body = encode(&[("host.name", "web1".to_string())], &metrics, 100, 200);
+
+        let resource_metrics = field(&body, 1, 2);
+        assert_eq!(resource_metrics.len(), 1);
+
+        //Resource.attributes is KeyValue { key = 1, value = AnyValue { string_value = 1 } }
+        let resource = &field(&resource_metrics[0], 1, 2)[0];
+        let attribute = &field(resource, 1, 2)[0];
+        assert_eq!(text(&field(attribute, 1, 2)[0]), "host.name");
+        assert_eq!(text(&field(&field(attribute, 2, 2)[0], 1, 2)[0]), "web1");
+
+        let scope_metrics = &field(&resource_metrics[0], 2, 2)[0];
+        assert_eq!(text(&field(&field(scope_metrics, 1, 2)[0], 1, 2)[0]), "uptime");
+        let list = field(scope_metrics, 2, 2);
+        assert_eq!(list.len(), 2);
+
+        //Sum is field 7 with cumulative temporality and the point value in as_int (6)
+        let usage = &list[0];
+        assert_eq!(text(&field(usage, 1, 2)[0]), "system.memory.usage");
+        assert_eq!(text(&field(usage, 3, 2)[0]), "By");
+        let sum = &field(usage, 7, 2)[0];
+        assert_eq!(u64_at(&field(sum, 2, 0)[0]), 2);
+        assert!(field(sum, 3, 0).is_empty());
+        let point = &field(sum, 1, 2)[0];
+        assert_eq!(u64_at(&field(point, 2, 1)[0]), 100);
+        assert_eq!(u64_at(&field(point, 3, 1)[0]), 200);
+        assert_eq!(u64_at(&field(point, 6, 1)[0]), 1024);
+        assert_eq!(text(&field(&field(point, 7, 2)[0], 1, 2)[0]), "system.memory.state");
+
+        //Gauge is field 5 and as_double is field 4
+        let load = &list[1];
+        let point = &field(&field(load, 5, 2)[0], 1, 2)[0];
+        assert_eq!(f64::from_bits(u64_at(&field(point, 4, 1)[0])), 0.5);
+    }
+
+    #[test]
+    fn endpoints() {
+        let e = Endpoint::parse("http://collector", Protocol::Http).unwrap();
+        assert_eq!((e.authority.as_str(), e.path.as_str()), ("collector:4318", "/v1/metrics"));
+        let e = Endpoint::parse("collector:9000/otlp/", Protocol::Http).unwrap();
+        assert_eq!((e.authority.as_str(), e.path.as_str()), ("collector:9000", "/otlp/v1/metrics"));
+        let e = Endpoint::parse("http://collector", Protocol::Grpc).unwrap();
+        assert_eq!((e.authority.as_str(), e.path.as_str()), ("collector:4317", GRPC_PATH));
+        assert!(Endpoint::parse("https://collector", Protocol::Http).is_err());
+    }
+
+    //A receiver that answers one request with the given status line, handing back what it was sent
+    fn stub_receiver(status: &'static str) -> (String, std::thread::JoinHandle<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0u8; 4096];
+            let (head, length) = loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&request[..end]).to_string();
+                    let length: usize = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .and_then(|l| l.parse().ok())
+                        .unwrap();
+                    request.drain(..end + 4);
+                    break (head, length);
+                }
+            };
+            while request.len() < length {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(format!("{}\r\nContent-Length: 0\r\n\r\n", status).as_bytes()).unwrap();
+            (head, request)
+        });
+        return (address, handle);
+    }
+
+    #[test]
+    fn http_export_to_a_stub_receiver() {
+        let metrics = vec![Metric::new("system.uptime", "Uptime", "s", Kind::Gauge).point(vec![], Number::Double(60.0))];
+        let body = encode(&[("service.name", "uptime".to_string())], &metrics, 1, 2);
+
+        let (address, receiver) = stub_receiver("HTTP/1.1 200 OK");
+        let endpoint = Endpoint::parse(&format!("http://{}", address), Protocol::Http).unwrap();
+        send_http(&endpoint, &body).unwrap();
+
+        let (head, received) = receiver.join().unwrap();
+        assert!(head.starts_with("POST /v1/metrics HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/x-protobuf"));
+        assert_eq!(received, body);
+        let scope_metrics = &field(&field(&received, 1, 2)[0], 2, 2)[0];
+        assert_eq!(text(&field(&field(scope_metrics, 2, 2)[0], 1, 2)[0]), "system.uptime");
+
+        let (address, receiver) = stub_receiver("HTTP/1.1 400 Bad Request");
+        let endpoint = Endpoint::parse(&format!("http://{}", address), Protocol::Http).unwrap();
+        let err = send_http(&endpoint, &body).unwrap_err();
+        assert!(err.to_string().ends_with("HTTP/1.1 400 Bad Request"));
+        receiver.join().unwrap();
+    }
+
+    //(header block, message) of a gRPC request
+    type Call = (Vec<u8>, Vec<u8>);
+
+    //A gRPC receiver that sends its SETTINGS, reads one Export call and answers with the given
+    //frames, handing back what it was sent
+    fn grpc_receiver(reply: Vec<u8>) -> (String, std::thread::JoinHandle<Call>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut preface = [0u8; 24];
+            stream.read_exact(&mut preface).unwrap();
+            assert_eq!(&preface, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+            let mut settings = vec![];
+            frame(&mut settings, FRAME_SETTINGS, 0, 0, &[]);
+            stream.write_all(&settings).unwrap();
+
+            let (mut headers, mut message, mut acked, mut ended) = (vec![], vec![], false, false);
+            while !(acked && ended) {
+                let (kind, flags, id, payload) = read_frame(&mut stream).unwrap();
+                match kind {
+                    FRAME_SETTINGS => acked |= flags & FLAG_ACK != 0,
+                    FRAME_HEADERS => {
+                        assert_eq!((id, flags), (1, FLAG_END_HEADERS));
+                        headers = payload;
+                    }
+                    FRAME_DATA => {
+                        assert_eq!(id, 1);
+                        message.extend_from_slice(&payload);
+                        ended = flags & FLAG_END_STREAM != 0;
+                    }
+                    _ => {}
+                }
+            }
+            stream.write_all(&reply).unwrap();
+            //Wait for the client to hang up, closing first could reset frames it has not read yet
+            let _ = stream.read_to_end(&mut vec![]);
+            (headers, message)
+        });
+        return (address, handle);
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        return haystack.windows(needle.len()).any(|w| w == needle);
+    }
+
+    #[test]
+    fn grpc_export_to_a_stub_receiver() {
+        let metrics = vec![Metric::new("system.uptime", "Uptime", "s", Kind::Gauge).point(vec![], Number::Double(60.0))];
+        let body = encode(&[("service.name", "uptime".to_string())], &metrics, 1, 2);
+
+        //:status 200 from the static table, a ping to answer, an empty ExportMetricsServiceResponse
+        //and grpc-status 0 in the trailers
+        let mut reply = vec![];
+        frame(&mut reply, FRAME_HEADERS, FLAG_END_HEADERS, 1, &[0x88]);
+        frame(&mut reply, FRAME_PING, 0, 0, &[7; 8]);
+        frame(&mut reply, FRAME_DATA, 0, 1, &[0, 0, 0, 0, 0]);
+        let mut trailers = vec![];
+        hpack_literal(&mut trailers, 0, "grpc-status", "0");
+        frame(&mut reply, FRAME_HEADERS, FLAG_END_HEADERS | FLAG_END_STREAM, 1, &trailers);
+
+        let (address, receiver) = grpc_receiver(reply);
+        let endpoint = Endpoint::parse(&format!("http://{}", address), Protocol::Grpc).unwrap();
+        send_grpc(&endpoint, &body).unwrap();
+
+        let (headers, message) = receiver.join().unwrap();
+        assert_eq!(&headers[..2], &[0x83, 0x86]);
+        assert!(contains(&headers, GRPC_PATH.as_bytes()));
+        assert!(contains(&headers, b"application/grpc"));
+        assert!(contains(&headers, address.as_bytes()));
+        //Uncompressed flag and big endian length ahead of the protobuf
+        assert_eq!(message[0], 0);
+        assert_eq!(u32::from_be_bytes([message[1], message[2], message[3], message[4]]) as usize, body.len());
+        assert_eq!(&message[5..], &body[..]);
+    }
+
+    #[test]
+    fn grpc_trailers_only_is_an_error() {
+        let body = encode(&[], &[], 1, 2);
+
+        //A failed call is a single HEADERS frame with the status and grpc-status, no DATA
+        let mut trailers = vec![0x88];
+        hpack_literal(&mut trailers, 0, "grpc-status", "3");
+        hpack_literal(&mut trailers, 0, "grpc-message", "bad metric");
+        let mut reply = vec![];
+        frame(&mut reply, FRAME_HEADERS, FLAG_END_HEADERS | FLAG_END_STREAM, 1, &trailers);
+
+        let (address, receiver) = grpc_receiver(reply);
+        let endpoint = Endpoint::parse(&format!("http://{}", address), Protocol::Grpc).unwrap();
+        let err = send_grpc(&endpoint, &body).unwrap_err();
+        assert!(err.to_string().contains("was rejected"), "{}", err);
+        receiver.join().unwrap();
+    }
+}
@@ -0,0 +1,606 @@
+use crate::config::Config;
+use crate::history::format_duration;
+
+use colored::*;
+use std::error::Error;
+
+#[derive(Default, Debug, Clone)]
+pub(crate) struct Battery {
+    pub(crate) name: String,
+    pub(crate) state: String,
+    pub(crate) charge_pct: Option<f64>,
+    //mWh, None when the battery only reports relative capacity
+    pub(crate) design_capacity: Option<u64>,
+    pub(crate) full_capacity: Option<u64>,
+    pub(crate) cycle_count: Option<u32>,
+}
+
+impl Battery {
+    //Capacity lost since new, as a percentage of the design capacity
+    pub(crate) fn wear(&self) -> Option<f64> {
+        match (self.design_capacity, self.full_capacity) {
+            (Some(design), Some(full)) if design > 0 => Some((100.0 - full as f64 * 100.0 / design as f64).max(0.0)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub(crate) struct Power {
+    //None when the firmware doesn't say (desktops without ACPI battery support)
+    pub(crate) on_ac: Option<bool>,
+    pub(crate) charge_pct: Option<f64>,
+    //Seconds of battery left, only while discharging
+    pub(crate) time_remaining: Option<u64>,
+    pub(crate) batteries: Vec<Battery>,
+    //Windows power plan, or the Linux platform profile and cpufreq governor
+    pub(crate) plan: String,
+    //(zone, degrees Celsius)
+    pub(crate) thermal: Vec<(String, f64)>,
+}
+
+#[cfg(windows)]
+mod ioctl {
+    pub(super) const IOCTL_BATTERY_QUERY_TAG: u32 = 0x294040;
+    pub(super) const IOCTL_BATTERY_QUERY_INFORMATION: u32 = 0x294044;
+    pub(super) const IOCTL_BATTERY_QUERY_STATUS: u32 = 0x29404C;
+
+    //BATTERY_QUERY_INFORMATION_LEVEL
+    pub(super) const BATTERY_INFORMATION: u32 = 0;
+    pub(super) const BATTERY_DEVICE_NAME: u32 = 4;
+
+    pub(super) const BATTERY_CAPACITY_RELATIVE: u32 = 0x40000000;
+    pub(super) const BATTERY_UNKNOWN_CAPACITY: u32 = 0xFFFFFFFF;
+    pub(super) const BATTERY_POWER_ON_LINE: u32 = 0x1;
+    pub(super) const BATTERY_DISCHARGING: u32 = 0x2;
+    pub(super) const BATTERY_CHARGING: u32 = 0x4;
+
+    //batclass.h is not in winapi
+    #[repr(C)]
+    pub(super) struct BatteryQueryInformation {
+        pub(super) battery_tag: u32,
+        pub(super) information_level: u32,
+        pub(super) at_rate: i32,
+    }
+
+    #[repr(C)]
+    pub(super) struct BatteryInformation {
+        pub(super) capabilities: u32,
+        pub(super) _technology: u8,
+        pub(super) _reserved: [u8; 3],
+        pub(super) _chemistry: [u8; 4],
+        pub(super) designed_capacity: u32,
+        pub(super) full_charged_capacity: u32,
+        pub(super) _default_alert1: u32,
+        pub(super) _default_alert2: u32,
+        pub(super) _critical_bias: u32,
+        pub(super) cycle_count: u32,
+    }
+
+    #[repr(C)]
+    pub(super) struct BatteryWaitStatus {
+        pub(super) battery_tag: u32,
+        pub(super) timeout: u32,
+        pub(super) power_state: u32,
+        pub(super) low_capacity: u32,
+        pub(super) high_capacity: u32,
+    }
+
+    #[repr(C)]
+    pub(super) struct BatteryStatus {
+        pub(super) power_state: u32,
+        pub(super) capacity: u32,
+        pub(super) _voltage: u32,
+        pub(super) _rate: i32,
+    }
+}
+
+#[cfg(windows)]
+unsafe fn battery_ioctl<I, O>(handle: winapi::um::winnt::HANDLE, code: u32, input: &I, output: &mut O) -> bool {
+    let mut returned = 0_u32;
+    winapi::um::ioapiset::DeviceIoControl(
+        handle,
+        code,
+        input as *const I as _,
+        std::mem::size_of::<I>() as u32,
+        output as *mut O as _,
+        std::mem::size_of::<O>() as u32,
+        &mut returned,
+        std::ptr::null_mut(),
+    ) != 0
+}
+
+#[cfg(windows)]
+unsafe fn query_battery(handle: winapi::um::winnt::HANDLE) -> Option<Battery> {
+    use ioctl::*;
+
+    let mut tag = 0_u32;
+    if !battery_ioctl(handle, IOCTL_BATTERY_QUERY_TAG, &0_u32, &mut tag) || tag == 0 {
+        return None;
+    }
+
+    let mut battery = Battery::default();
+    let query = |level: u32| BatteryQueryInformation { battery_tag: tag, information_level: level, at_rate: 0 };
+
+    let mut name = [0_u16; 128];
+    if battery_ioctl(handle, IOCTL_BATTERY_QUERY_INFORMATION, &query(BATTERY_DEVICE_NAME), &mut name) {
+        battery.name = crate::host::from_wide(&name);
+    }
+
+    let mut info: BatteryInformation = std::mem::zeroed();
+    let relative = if battery_ioctl(handle, IOCTL_BATTERY_QUERY_INFORMATION, &query(BATTERY_INFORMATION), &mut info) {
+        let relative = info.capabilities & BATTERY_CAPACITY_RELATIVE != 0;
+        if !relative {
+            battery.design_capacity = Some(info.designed_capacity as u64).filter(|c| *c > 0);
+            battery.full_capacity = Some(info.full_charged_capacity as u64).filter(|c| *c > 0);
+        }
+        battery.cycle_count = Some(info.cycle_count).filter(|c| *c > 0);
+        relative
+    } else {
+        true
+    };
+
+    let wait = BatteryWaitStatus { battery_tag: tag, timeout: 0, power_state: 0, low_capacity: 0, high_capacity: 0 };
+    let mut status: BatteryStatus = std::mem::zeroed();
+    if battery_ioctl(handle, IOCTL_BATTERY_QUERY_STATUS, &wait, &mut status) {
+        battery.state = if status.power_state & BATTERY_CHARGING != 0 {
+            "Charging"
+        } else if status.power_state & BATTERY_DISCHARGING != 0 {
+            "Discharging"
+        } else if status.power_state & BATTERY_POWER_ON_LINE != 0 {
+            "Not charging"
+        } else {
+            "Unknown"
+        }
+        .to_string();
+
+        if status.capacity != BATTERY_UNKNOWN_CAPACITY {
+            battery.charge_pct = if relative {
+                Some(status.capacity as f64)
+            } else {
+                Some(info.full_charged_capacity).filter(|f| *f > 0).map(|f| status.capacity as f64 * 100.0 / f as f64)
+            };
+        }
+    }
+
+    return Some(battery);
+}
+
+//Every battery the battery class driver exposes, including UPSes on HID
+#[cfg(windows)]
+unsafe fn batteries() -> Vec<Battery> {
+    use std::mem::{size_of, zeroed};
+    use winapi::shared::devguid::GUID_DEVCLASS_BATTERY;
+    use winapi::um::fileapi::{CreateFileW, OPEN_EXISTING};
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::setupapi::{
+        SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInterfaces, SetupDiGetClassDevsW, SetupDiGetDeviceInterfaceDetailW,
+        DIGCF_DEVICEINTERFACE, DIGCF_PRESENT, SP_DEVICE_INTERFACE_DATA, SP_DEVICE_INTERFACE_DETAIL_DATA_W,
+    };
+    use winapi::um::winnt::{FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, GENERIC_WRITE};
+
+    let mut list = vec![];
+    let devices = SetupDiGetClassDevsW(&GUID_DEVCLASS_BATTERY, std::ptr::null(), std::ptr::null_mut(), DIGCF_PRESENT | DIGCF_DEVICEINTERFACE);
+    if devices == INVALID_HANDLE_VALUE {
+        return list;
+    }
+
+    let mut index = 0;
+    loop {
+        let mut iface: SP_DEVICE_INTERFACE_DATA = zeroed();
+        iface.cbSize = size_of::<SP_DEVICE_INTERFACE_DATA>() as u32;
+        if SetupDiEnumDeviceInterfaces(devices, std::ptr::null_mut(), &GUID_DEVCLASS_BATTERY, index, &mut iface) == 0 {
+            break;
+        }
+        index += 1;
+
+        let mut needed = 0_u32;
+        SetupDiGetDeviceInterfaceDetailW(devices, &mut iface, std::ptr::null_mut(), 0, &mut needed, std::ptr::null_mut());
+        //cbSize is the size of the fixed part, the device path runs on past the end of the struct
+        let mut buffer = vec![0_u64; (needed as usize).div_ceil(8)];
+        let detail = buffer.as_mut_ptr() as *mut SP_DEVICE_INTERFACE_DETAIL_DATA_W;
+        (*detail).cbSize = size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() as u32;
+        if SetupDiGetDeviceInterfaceDetailW(devices, &mut iface, detail, needed, std::ptr::null_mut(), std::ptr::null_mut()) == 0 {
+            continue;
+        }
+
+        let handle = CreateFileW(
+            (*detail).DevicePath.as_ptr(),
+            GENERIC_READ | GENERIC_WRITE,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            std::ptr::null_mut(),
+            OPEN_EXISTING,
+            FILE_ATTRIBUTE_NORMAL,
+            std::ptr::null_mut(),
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            continue;
+        }
+        list.extend(query_battery(handle));
+        CloseHandle(handle);
+    }
+
+    SetupDiDestroyDeviceInfoList(devices);
+    return list;
+}
+
+#[cfg(windows)]
+unsafe fn power_plan() -> String {
+    use winapi::shared::guiddef::GUID;
+    use winapi::um::powersetting::PowerGetActiveScheme;
+    use winapi::um::powrprof::PowerReadFriendlyName;
+    use winapi::um::winbase::LocalFree;
+
+    let mut scheme: *mut GUID = std::ptr::null_mut();
+    if PowerGetActiveScheme(std::ptr::null_mut(), &mut scheme) != 0 {
+        return String::new();
+    }
+
+    //The name comes back as UTF-16 in a byte buffer
+    let mut name = [0_u16; 256];
+    let mut size = (name.len() * 2) as u32;
+    let result = PowerReadFriendlyName(
+        std::ptr::null_mut(),
+        scheme,
+        std::ptr::null(),
+        std::ptr::null(),
+        name.as_mut_ptr() as _,
+        &mut size,
+    );
+    LocalFree(scheme as _);
+
+    if result == 0 {
+        crate::host::from_wide(&name)
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(windows)]
+impl Power {
+    pub(crate) fn info() -> Power {
+        use winapi::um::winbase::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+        let mut power = Power::default();
+
+        unsafe {
+            let mut status: SYSTEM_POWER_STATUS = std::mem::zeroed();
+            if GetSystemPowerStatus(&mut status) != 0 {
+                //255 is unknown throughout, BatteryFlag 128 is no system battery
+                power.on_ac = match status.ACLineStatus {
+                    0 => Some(false),
+                    1 => Some(true),
+                    _ => None,
+                };
+                if status.BatteryFlag != 128 && status.BatteryLifePercent != 255 {
+                    power.charge_pct = Some(status.BatteryLifePercent as f64);
+                }
+                if status.BatteryLifeTime != u32::MAX && power.on_ac == Some(false) {
+                    power.time_remaining = Some(status.BatteryLifeTime as u64);
+                }
+            }
+
+            power.batteries = batteries();
+            power.plan = power_plan();
+            power.thermal = crate::system::get_thermal_zones().unwrap_or_default();
+        }
+
+        return power;
+    }
+}
+
+#[cfg(not(windows))]
+fn read_sys(dir: &std::path::Path, file: &str) -> Option<String> {
+    std::fs::read_to_string(dir.join(file)).ok().map(|s| s.trim().to_string())
+}
+
+#[cfg(not(windows))]
+fn read_sys_u64(dir: &std::path::Path, file: &str) -> Option<u64> {
+    read_sys(dir, file)?.parse().ok()
+}
+
+//A power_supply of type Battery. Energy files are in µWh and µW, charge files in µAh and µA
+//and need the design voltage (µV) to convert
+#[cfg(not(windows))]
+fn read_battery(dir: &std::path::Path) -> (Battery, Option<(f64, f64)>) {
+    let name = dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let model = read_sys(dir, "model_name").unwrap_or_default();
+    let mut battery = Battery {
+        name: if model.is_empty() { name } else { format!("{} ({})", name, model) },
+        state: read_sys(dir, "status").unwrap_or_else(|| "Unknown".to_string()),
+        charge_pct: read_sys_u64(dir, "capacity").map(|c| c as f64),
+        cycle_count: read_sys_u64(dir, "cycle_count").filter(|c| *c > 0).map(|c| c as u32),
+        ..Default::default()
+    };
+
+    let (now, full, design, rate) = match read_sys_u64(dir, "energy_full") {
+        Some(full) => (
+            read_sys_u64(dir, "energy_now"),
+            Some(full),
+            read_sys_u64(dir, "energy_full_design"),
+            read_sys_u64(dir, "power_now"),
+        ),
+        None => {
+            let to_energy = |uah: Option<u64>| uah.zip(read_sys_u64(dir, "voltage_min_design")).map(|(c, v)| c * v / 1_000_000);
+            (
+                to_energy(read_sys_u64(dir, "charge_now")),
+                to_energy(read_sys_u64(dir, "charge_full")),
+                to_energy(read_sys_u64(dir, "charge_full_design")),
+                to_energy(read_sys_u64(dir, "current_now")),
+            )
+        }
+    };
+    battery.full_capacity = full.map(|f| f / 1000).filter(|f| *f > 0);
+    battery.design_capacity = design.map(|d| d / 1000).filter(|d| *d > 0);
+    if battery.charge_pct.is_none() {
+        battery.charge_pct = now.zip(full).filter(|(_, f)| *f > 0).map(|(n, f)| n as f64 * 100.0 / f as f64);
+    }
+
+    //(energy now, discharge rate) in the same unit, for the time remaining
+    let drain = match (now, rate) {
+        (Some(now), Some(rate)) if battery.state == "Discharging" && rate > 0 => Some((now as f64, rate as f64)),
+        _ => None,
+    };
+    return (battery, drain);
+}
+
+#[cfg(not(windows))]
+fn power_plan() -> String {
+    let profile = std::fs::read_to_string("/sys/firmware/acpi/platform_profile").unwrap_or_default();
+    let governor = std::fs::read_to_string("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor").unwrap_or_default();
+    match (profile.trim(), governor.trim()) {
+        ("", "") => String::new(),
+        (p, "") => p.to_string(),
+        ("", g) => format!("governor {}", g),
+        (p, g) => format!("{} (governor {})", p, g),
+    }
+}
+
+#[cfg(not(windows))]
+fn thermal_zones() -> Vec<(String, f64)> {
+    let mut zones: Vec<(String, f64)> = vec![];
+    let mut dirs: Vec<std::path::PathBuf> = match std::fs::read_dir("/sys/class/thermal") {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.file_name().is_some_and(|n| n.to_string_lossy().starts_with("thermal_zone")))
+            .collect(),
+        Err(_) => return zones,
+    };
+    dirs.sort();
+
+    for dir in dirs {
+        //Millidegrees Celsius, reading fails while the zone is disabled
+        if let Some(temp) = read_sys(&dir, "temp").and_then(|t| t.parse::<i64>().ok()) {
+            let name = read_sys(&dir, "type").unwrap_or_else(|| dir.file_name().unwrap_or_default().to_string_lossy().to_string());
+            zones.push((name, temp as f64 / 1000.0));
+        }
+    }
+    return zones;
+}
+
+//Overall charge weighted by capacity when every battery reports it, and the time left with
+//every discharging battery draining together
+#[cfg(not(windows))]
+fn combine(batteries: &[Battery], drain: &[(f64, f64)]) -> (Option<f64>, Option<u64>) {
+    let weighted: Option<Vec<(f64, f64)>> = batteries.iter().map(|b| b.charge_pct.zip(b.full_capacity.map(|f| f as f64))).collect();
+    let charge_pct = match weighted {
+        Some(w) if !w.is_empty() => {
+            let total: f64 = w.iter().map(|(_, f)| f).sum();
+            Some(w.iter().map(|(pct, f)| pct * f).sum::<f64>() / total.max(1.0))
+        }
+        _ => batteries.first().and_then(|b| b.charge_pct),
+    };
+
+    let time_remaining = if drain.is_empty() {
+        None
+    } else {
+        let (now, rate) = drain.iter().fold((0.0, 0.0), |(n, r), (dn, dr)| (n + dn, r + dr));
+        Some((now / rate * 3600.0) as u64)
+    };
+    return (charge_pct, time_remaining);
+}
+
+#[cfg(not(windows))]
+impl Power {
+    pub(crate) fn info() -> Power {
+        let mut power = Power::default();
+        let mut drain: Vec<(f64, f64)> = vec![];
+        let mut entries: Vec<std::path::PathBuf> = match std::fs::read_dir("/sys/class/power_supply") {
+            Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+            Err(_) => vec![],
+        };
+        entries.sort();
+
+        for dir in entries {
+            match read_sys(&dir, "type").as_deref() {
+                Some("Mains") | Some("USB") => {
+                    if let Some(online) = read_sys_u64(&dir, "online") {
+                        power.on_ac = Some(power.on_ac.unwrap_or(false) || online == 1);
+                    }
+                }
+                //Peripheral batteries (mice, headsets) report scope Device
+                Some("Battery") if read_sys(&dir, "scope").as_deref() != Some("Device") => {
+                    let (battery, d) = read_battery(&dir);
+                    power.batteries.push(battery);
+                    drain.extend(d);
+                }
+                _ => {}
+            }
+        }
+
+        (power.charge_pct, power.time_remaining) = combine(&power.batteries, &drain);
+        if power.on_ac.is_none() && !power.batteries.is_empty() {
+            power.on_ac = Some(!power.batteries.iter().any(|b| b.state == "Discharging"));
+        }
+
+        power.plan = power_plan();
+        power.thermal = thermal_zones();
+        return power;
+    }
+}
+
+pub(crate) fn print_power(power: &Power, config: &Config) {
+    let c = config.theme.highlight;
+    let source = match power.on_ac {
+        Some(true) => "AC",
+        Some(false) => "Battery",
+        None => "Unknown",
+    };
+    let mut line = format!("Power: {}", source.color(c).bold());
+    match power.charge_pct {
+        Some(pct) => line.push_str(&format!(" | Charge: {:.0}%", pct)),
+        None => line.push_str(" | Battery: none"),
+    }
+    if let Some(secs) = power.time_remaining {
+        line.push_str(&format!(" | Remaining: {}", format_duration(secs as i64)));
+    }
+    line.push_str(&format!(" | Plan: {}", if power.plan.is_empty() { "-" } else { &power.plan }));
+    println!("{}", line);
+
+    let mwh = |v: Option<u64>| v.map(|v| format!("{} mWh", v)).unwrap_or_else(|| "-".to_string());
+    for b in &power.batteries {
+        println!(
+            "Battery: {} | State: {} | Charge: {} | Design: {} | Full: {} | Wear: {} | Cycles: {}",
+            if b.name.is_empty() { "-" } else { &b.name },
+            b.state,
+            b.charge_pct.map(|p| format!("{:.0}%", p)).unwrap_or_else(|| "-".to_string()),
+            mwh(b.design_capacity),
+            mwh(b.full_capacity),
+            b.wear().map(|w| format!("{:.1}%", w)).unwrap_or_else(|| "-".to_string()),
+            b.cycle_count.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string())
+        );
+    }
+
+    if !power.thermal.is_empty() {
+        let zones: Vec<String> = power.thermal.iter().map(|(name, t)| format!("{} {:.1}°C", name, t)).collect();
+        println!("Thermal: {}", zones.join(" | "));
+    }
+}
+
+//Entry point for `uptime power`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    if !args.is_empty() {
+        return Err("usage: uptime power".into());
+    }
+
+    print_power(&Power::info(), config);
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wear() {
+        let battery = |design, full| Battery { design_capacity: design, full_capacity: full, ..Default::default() };
+        assert_eq!(battery(Some(50_000), Some(40_000)).wear(), Some(20.0));
+        assert_eq!(battery(Some(50_000), Some(50_000)).wear(), Some(0.0));
+        //A fresh battery can hold a little more than its design capacity
+        assert_eq!(battery(Some(50_000), Some(52_000)).wear(), Some(0.0));
+        assert_eq!(battery(Some(0), Some(40_000)).wear(), None);
+        assert_eq!(battery(None, Some(40_000)).wear(), None);
+        assert_eq!(battery(Some(50_000), None).wear(), None);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn combined_charge_and_time() {
+        let battery = |pct: Option<f64>, full: Option<u64>| Battery { charge_pct: pct, full_capacity: full, ..Default::default() };
+
+        //A full 20 Wh battery and an empty 60 Wh one are a quarter charged together
+        let (pct, _) = combine(&[battery(Some(100.0), Some(20_000)), battery(Some(0.0), Some(60_000))], &[]);
+        assert_eq!(pct, Some(25.0));
+        //Without every capacity the first battery speaks for all of them
+        let (pct, _) = combine(&[battery(Some(80.0), None), battery(Some(10.0), Some(60_000))], &[]);
+        assert_eq!(pct, Some(80.0));
+        assert_eq!(combine(&[], &[]), (None, None));
+
+        //30 Wh left between two batteries drawing 10 W in all
+        let (_, time) = combine(&[battery(Some(50.0), Some(60_000))], &[(20_000_000.0, 6_000_000.0), (10_000_000.0, 4_000_000.0)]);
+        assert_eq!(time, Some(3 * 3600));
+    }
+
+    #[cfg(not(windows))]
+    struct Dir(std::path::PathBuf);
+
+    //A power_supply directory named after the battery, since read_battery takes its name from it
+    #[cfg(not(windows))]
+    impl Dir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Dir {
+            let path = std::env::temp_dir().join(format!("uptime-power-{}", std::process::id())).join(name);
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            for (file, text) in files {
+                std::fs::write(path.join(file), format!("{}\n", text)).unwrap();
+            }
+            return Dir(path);
+        }
+    }
+
+    #[cfg(not(windows))]
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+            let _ = std::fs::remove_dir(self.0.parent().unwrap());
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn energy_battery() {
+        let dir = Dir::new(
+            "BAT0",
+            &[
+                ("model_name", "5B10W13930"),
+                ("status", "Discharging"),
+                ("cycle_count", "212"),
+                ("energy_now", "30000000"),
+                ("energy_full", "45000000"),
+                ("energy_full_design", "50000000"),
+                ("power_now", "7500000"),
+            ],
+        );
+        let (battery, drain) = read_battery(&dir.0);
+        assert_eq!(battery.name, "BAT0 (5B10W13930)");
+        assert_eq!((battery.state.as_str(), battery.cycle_count), ("Discharging", Some(212)));
+        assert_eq!((battery.full_capacity, battery.design_capacity), (Some(45_000), Some(50_000)));
+        //No capacity file, so the charge comes from energy now over full
+        assert!((battery.charge_pct.unwrap() - 66.666).abs() < 0.01);
+        assert_eq!(battery.wear(), Some(10.0));
+        assert_eq!(drain, Some((30_000_000.0, 7_500_000.0)));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn charge_battery() {
+        //µAh and µA at a 15 V design voltage
+        let dir = Dir::new(
+            "BAT1",
+            &[
+                ("status", "Charging"),
+                ("capacity", "81"),
+                ("cycle_count", "0"),
+                ("voltage_min_design", "15000000"),
+                ("charge_now", "2000000"),
+                ("charge_full", "3000000"),
+                ("charge_full_design", "4000000"),
+                ("current_now", "500000"),
+            ],
+        );
+        let (battery, drain) = read_battery(&dir.0);
+        assert_eq!(battery.name, "BAT1");
+        assert_eq!((battery.full_capacity, battery.design_capacity), (Some(45_000), Some(60_000)));
+        //The kernel's capacity wins over the computed charge, and a zero cycle count is unknown
+        assert_eq!((battery.charge_pct, battery.cycle_count), (Some(81.0), None));
+        assert_eq!(battery.wear(), Some(25.0));
+        //Only a discharging battery counts towards the time left
+        assert_eq!(drain, None);
+
+        let bare = Dir::new("BAT2", &[("charge_full", "3000000")]);
+        let (battery, drain) = read_battery(&bare.0);
+        assert_eq!((battery.state.as_str(), battery.charge_pct, battery.full_capacity, drain), ("Unknown", None, None, None));
+    }
+}
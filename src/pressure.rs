@@ -0,0 +1,154 @@
+use crate::config::Config;
+
+use std::error::Error;
+
+//Pressure stall information: the share of time tasks were waiting on a resource.
+//"some" is at least one task stalled, "full" is every non-idle task stalled at once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Resource {
+    Cpu,
+    #[cfg(not(windows))]
+    Memory,
+    Io,
+}
+
+impl Resource {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Resource::Cpu => "CPU",
+            #[cfg(not(windows))]
+            Resource::Memory => "Memory",
+            Resource::Io => "IO",
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub(crate) struct Stall {
+    //(window in seconds, percent of that window stalled)
+    pub(crate) averages: Vec<(u32, f64)>,
+    //Cumulative stall time since boot, Linux only
+    pub(crate) total_us: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Pressure {
+    pub(crate) resource: Resource,
+    pub(crate) some: Stall,
+    pub(crate) full: Option<Stall>,
+}
+
+//"some avg10=0.69 avg60=1.70 avg300=1.23 total=18122304"
+#[cfg(not(windows))]
+fn parse_line(line: &str) -> Option<(&str, Stall)> {
+    let mut parts = line.split_whitespace();
+    let kind = parts.next()?;
+    let mut stall = Stall::default();
+
+    for part in parts {
+        let (key, value) = part.split_once('=')?;
+        match key.strip_prefix("avg") {
+            Some(window) => stall.averages.push((window.parse().ok()?, value.parse().ok()?)),
+            None if key == "total" => stall.total_us = value.parse().ok(),
+            None => {}
+        }
+    }
+    Some((kind, stall))
+}
+
+#[cfg(not(windows))]
+pub(crate) fn parse_pressure(text: &str, resource: Resource) -> Option<Pressure> {
+    let mut some = None;
+    let mut full = None;
+    for (kind, stall) in text.lines().filter_map(parse_line) {
+        match kind {
+            "some" => some = Some(stall),
+            "full" => full = Some(stall),
+            _ => {}
+        }
+    }
+    Some(Pressure { resource, some: some?, full })
+}
+
+//The kernel keeps its own running averages, so there is nothing to sample
+#[cfg(not(windows))]
+pub(crate) fn sample(_interval: u32) -> Result<Vec<Pressure>, Box<dyn Error>> {
+    let mut list = vec![];
+    for (file, resource) in [("cpu", Resource::Cpu), ("memory", Resource::Memory), ("io", Resource::Io)] {
+        let path = format!("/proc/pressure/{}", file);
+        //Missing when the kernel is older than 4.20 or booted without psi=1
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        list.extend(parse_pressure(&text, resource));
+    }
+    return Ok(list);
+}
+
+//Windows has no stall accounting, approximate "some" from how often threads were queued
+//for a processor and I/O was queued on a disk over the sampling interval
+#[cfg(windows)]
+pub(crate) fn sample(interval: u32) -> Result<Vec<Pressure>, Box<dyn Error>> {
+    let (cpu, io) = unsafe { crate::system::get_queue_pressure(interval)? };
+    let stall = |pct: f64| Stall { averages: vec![(interval.max(1), pct)], total_us: None };
+
+    return Ok(vec![
+        Pressure { resource: Resource::Cpu, some: stall(cpu), full: None },
+        Pressure { resource: Resource::Io, some: stall(io), full: None },
+    ]);
+}
+
+fn format_stall(stall: &Stall) -> String {
+    let mut s: Vec<String> = stall.averages.iter().map(|(window, pct)| format!("{}s: {:.2}%", window, pct)).collect();
+    if let Some(total) = stall.total_us {
+        s.push(format!("Stalled: {:.1}s", total as f64 / 1_000_000.0));
+    }
+    return s.join(" ");
+}
+
+pub(crate) fn print_pressure(list: &[Pressure]) {
+    for p in list {
+        match &p.full {
+            Some(full) => println!("Pressure {}: Some: {} | Full: {}", p.resource.name(), format_stall(&p.some), format_stall(full)),
+            None => println!("Pressure {}: Some: {}", p.resource.name(), format_stall(&p.some)),
+        }
+    }
+}
+
+//Entry point for `uptime pressure`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    if !args.is_empty() {
+        return Err("usage: uptime pressure".into());
+    }
+
+    print_pressure(&sample(config.cpu_interval)?);
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stall_is_formatted_per_window() {
+        let stall = Stall { averages: vec![(10, 0.69), (60, 1.7)], total_us: Some(18_122_304) };
+        assert_eq!(format_stall(&stall), "10s: 0.69% 60s: 1.70% Stalled: 18.1s");
+        let windows = Stall { averages: vec![(2, 12.5)], total_us: None };
+        assert_eq!(format_stall(&windows), "2s: 12.50%");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn proc_pressure_file() {
+        let text = "some avg10=0.69 avg60=1.70 avg300=1.23 total=18122304\n\
+                    full avg10=0.00 avg60=0.25 avg300=0.10 total=2003\n";
+        let p = parse_pressure(text, Resource::Memory).unwrap();
+        assert_eq!(p.some.averages, vec![(10, 0.69), (60, 1.7), (300, 1.23)]);
+        assert_eq!(p.some.total_us, Some(18_122_304));
+        assert_eq!(p.full.unwrap().total_us, Some(2003));
+
+        //The cpu file has no full line before Linux 5.13
+        let cpu = parse_pressure("some avg10=1.00 avg60=2.00 avg300=3.00 total=1\n", Resource::Cpu).unwrap();
+        assert!(cpu.full.is_none());
+        assert!(parse_pressure("", Resource::Io).is_none());
+    }
+}
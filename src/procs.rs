@@ -0,0 +1,730 @@
+use crate::config::{Config, Unit};
+use crate::history::{format_duration, format_time};
+use crate::system::{Processor, SystemInfo};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+#[derive(Default, Debug, Clone)]
+pub struct Process {
+    pub pid: u32,
+    pub ppid: u32,
+    pub name: String,
+    //Unix timestamp in seconds
+    pub start_time: i64,
+    pub threads: u32,
+    pub user_time_ms: u64,
+    pub kernel_time_ms: u64,
+    //Share of the whole machine, 100% means every logical processor was busy
+    pub cpu_percent: f64,
+    //Working set on Windows, RSS on Linux
+    pub working_set: u64,
+    //Private bytes on Windows, anonymous RSS on Linux
+    pub private_bytes: u64,
+    //Handles on Windows, open file descriptors on Linux
+    pub handles: u32,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    //Bytes per second between the two samples
+    pub read_rate: f64,
+    pub write_rate: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SortKey {
+    Cpu,
+    Memory,
+    Private,
+    Handles,
+    Io,
+}
+
+impl SortKey {
+    pub(crate) fn parse(s: &str) -> Option<SortKey> {
+        match s.to_lowercase().as_str() {
+            "cpu" => Some(SortKey::Cpu),
+            "mem" | "memory" | "rss" | "ws" => Some(SortKey::Memory),
+            "private" => Some(SortKey::Private),
+            "handles" | "fds" => Some(SortKey::Handles),
+            "io" => Some(SortKey::Io),
+            _ => None,
+        }
+    }
+
+    fn value(&self, p: &Process) -> f64 {
+        match self {
+            SortKey::Cpu => p.cpu_percent,
+            SortKey::Memory => p.working_set as f64,
+            SortKey::Private => p.private_bytes as f64,
+            SortKey::Handles => p.handles as f64,
+            SortKey::Io => p.read_rate + p.write_rate,
+        }
+    }
+}
+
+#[cfg(windows)]
+unsafe fn read_counters(p: &mut Process) {
+    use crate::history::filetime_to_unix;
+    use winapi::shared::minwindef::{FALSE, FILETIME};
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetProcessHandleCount, GetProcessTimes, OpenProcess};
+    use winapi::um::psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS, PROCESS_MEMORY_COUNTERS_EX};
+    use winapi::um::winbase::GetProcessIoCounters;
+    use winapi::um::winnt::{IO_COUNTERS, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_VM_READ};
+
+    //Older systems need PROCESS_VM_READ for the memory counters, protected processes refuse it
+    let mut handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_VM_READ, FALSE, p.pid);
+    if handle.is_null() {
+        handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, p.pid);
+    }
+    if handle.is_null() {
+        return;
+    }
+
+    let filetime = |ft: FILETIME| (ft.dwHighDateTime as u64) << 32 | ft.dwLowDateTime as u64;
+
+    let (mut creation, mut exit, mut kernel, mut user): (FILETIME, FILETIME, FILETIME, FILETIME) =
+        std::mem::zeroed();
+    if GetProcessTimes(handle, &mut creation, &mut exit, &mut kernel, &mut user) != 0 {
+        p.kernel_time_ms = filetime(kernel) / 10_000;
+        p.user_time_ms = filetime(user) / 10_000;
+        p.start_time = filetime_to_unix(filetime(creation) as i64);
+    }
+
+    let mut mem: PROCESS_MEMORY_COUNTERS_EX = std::mem::zeroed();
+    mem.cb = std::mem::size_of::<PROCESS_MEMORY_COUNTERS_EX>() as u32;
+    if GetProcessMemoryInfo(handle, &mut mem as *mut _ as *mut PROCESS_MEMORY_COUNTERS, mem.cb) != 0 {
+        p.working_set = mem.WorkingSetSize as u64;
+        p.private_bytes = mem.PrivateUsage as u64;
+    }
+
+    let mut handles = 0_u32;
+    if GetProcessHandleCount(handle, &mut handles) != 0 {
+        p.handles = handles;
+    }
+
+    let mut io: IO_COUNTERS = std::mem::zeroed();
+    if GetProcessIoCounters(handle, &mut io) != 0 {
+        p.read_bytes = io.ReadTransferCount;
+        p.write_bytes = io.WriteTransferCount;
+    }
+
+    CloseHandle(handle);
+}
+
+//Every process with its cumulative counters at this instant
+#[cfg(windows)]
+pub(crate) fn snapshot() -> Vec<Process> {
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::tlhelp32::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+
+    let mut processes: Vec<Process> = vec![];
+
+    unsafe {
+        let snap = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snap == INVALID_HANDLE_VALUE {
+            return processes;
+        }
+
+        let mut entry: PROCESSENTRY32W = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+
+        let mut more = Process32FirstW(snap, &mut entry) != 0;
+        while more {
+            let len = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+            let mut p = Process {
+                pid: entry.th32ProcessID,
+                ppid: entry.th32ParentProcessID,
+                name: String::from_utf16_lossy(&entry.szExeFile[..len]),
+                threads: entry.cntThreads,
+                ..Default::default()
+            };
+
+            //The System Idle Process is not a real process
+            if p.pid != 0 {
+                read_counters(&mut p);
+                processes.push(p);
+            }
+
+            more = Process32NextW(snap, &mut entry) != 0;
+        }
+
+        CloseHandle(snap);
+    }
+
+    return processes;
+}
+
+//USER_HZ is 100 on every mainstream architecture
+#[cfg(not(windows))]
+const TICK_MS: u64 = 10;
+
+//Fields after the command name in /proc/<pid>/stat, the name itself may contain spaces.
+//boot_time is the btime line from /proc/stat, start times are in ticks since boot
+#[cfg(not(windows))]
+pub(crate) fn parse_stat(text: &str, boot_time: i64) -> Option<Process> {
+    let open = text.find('(')?;
+    let close = text.rfind(')')?;
+    let fields: Vec<&str> = text[close + 1..].split_whitespace().collect();
+
+    Some(Process {
+        pid: text[..open].trim().parse().ok()?,
+        ppid: fields.get(1)?.parse().ok()?,
+        name: text[open + 1..close].to_string(),
+        start_time: boot_time + (fields.get(19)?.parse::<u64>().ok()? * TICK_MS / 1000) as i64,
+        user_time_ms: fields.get(11)?.parse::<u64>().ok()? * TICK_MS,
+        kernel_time_ms: fields.get(12)?.parse::<u64>().ok()? * TICK_MS,
+        threads: fields.get(17)?.parse().ok()?,
+        ..Default::default()
+    })
+}
+
+//"VmRSS:     1234 kB" style lines from /proc/<pid>/status
+#[cfg(not(windows))]
+pub(crate) fn parse_status(p: &mut Process, text: &str) {
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        let key = parts.next().unwrap_or("");
+        let kb = parts.next().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+        match key {
+            "VmRSS:" => p.working_set = kb * 1024,
+            "RssAnon:" => p.private_bytes = kb * 1024,
+            _ => {}
+        }
+    }
+}
+
+//read_bytes/write_bytes from /proc/<pid>/io, only readable for our own processes unless root
+#[cfg(not(windows))]
+pub(crate) fn parse_io(p: &mut Process, text: &str) {
+    for line in text.lines() {
+        match line.split_once(':') {
+            Some(("read_bytes", v)) => p.read_bytes = v.trim().parse().unwrap_or(0),
+            Some(("write_bytes", v)) => p.write_bytes = v.trim().parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub(crate) fn read_boot_time() -> i64 {
+    std::fs::read_to_string("/proc/stat")
+        .ok()
+        .and_then(|s| {
+            s.lines()
+                .find_map(|l| l.strip_prefix("btime ").and_then(|v| v.trim().parse().ok()))
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(not(windows))]
+pub(crate) fn snapshot() -> Vec<Process> {
+    let mut processes: Vec<Process> = vec![];
+    let boot_time = read_boot_time();
+
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(e) => e,
+        Err(_) => return processes,
+    };
+
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        if !entry.file_name().to_string_lossy().chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        let mut p = match std::fs::read_to_string(dir.join("stat")).ok().and_then(|s| parse_stat(&s, boot_time)) {
+            Some(p) => p,
+            None => continue,
+        };
+        if let Ok(status) = std::fs::read_to_string(dir.join("status")) {
+            parse_status(&mut p, &status);
+        }
+        if let Ok(io) = std::fs::read_to_string(dir.join("io")) {
+            parse_io(&mut p, &io);
+        }
+        p.handles = std::fs::read_dir(dir.join("fd")).map(|d| d.count() as u32).unwrap_or(0);
+
+        processes.push(p);
+    }
+
+    return processes;
+}
+
+//Take two snapshots interval seconds apart, the same way get_processor_activity
+//samples the PDH counters, and work out the rates in between
+pub(crate) fn sample(interval: u32) -> Vec<Process> {
+    let mut pr: Processor = SystemInfo::new();
+    let cpus = pr.info().number_processors.max(1) as f64;
+
+    let first: HashMap<(u32, String), Process> = snapshot()
+        .into_iter()
+        .map(|p| ((p.pid, p.name.clone()), p))
+        .collect();
+    let start = Instant::now();
+
+    std::thread::sleep(Duration::from_secs(interval as u64));
+
+    let mut second = snapshot();
+    let elapsed_ms = start.elapsed().as_millis().max(1) as f64;
+
+    for p in second.iter_mut() {
+        //A pid can be reused, so only compare against the same executable
+        if let Some(old) = first.get(&(p.pid, p.name.clone())) {
+            let cpu_ms = (p.user_time_ms + p.kernel_time_ms).saturating_sub(old.user_time_ms + old.kernel_time_ms);
+            p.cpu_percent = cpu_ms as f64 / (elapsed_ms * cpus) * 100.0;
+            p.read_rate = p.read_bytes.saturating_sub(old.read_bytes) as f64 * 1000.0 / elapsed_ms;
+            p.write_rate = p.write_bytes.saturating_sub(old.write_bytes) as f64 * 1000.0 / elapsed_ms;
+        }
+    }
+
+    return second;
+}
+
+//Filter matches a pid exactly or a case insensitive part of the process name
+pub(crate) fn top(mut processes: Vec<Process>, sort: SortKey, count: usize, filter: &str) -> Vec<Process> {
+    let filter = filter.to_lowercase();
+    processes.retain(|p| matches(p, &filter));
+    processes.sort_by(|a, b| sort.value(b).partial_cmp(&sort.value(a)).unwrap_or(std::cmp::Ordering::Equal));
+    processes.truncate(count);
+    return processes;
+}
+
+pub(crate) fn format_rate(bytes_per_sec: f64) -> String {
+    if bytes_per_sec >= 1024.0 * 1024.0 {
+        format!("{:.1} MB/s", bytes_per_sec / (1024.0 * 1024.0))
+    } else {
+        format!("{:.1} KB/s", bytes_per_sec / 1024.0)
+    }
+}
+
+pub(crate) fn print_processes(processes: &[Process], unit: Unit) {
+    println!(
+        "{:>7} {:<28}{:>7} {:>12} {:>12} {:>8} {:>8} {:>12} {:>12}",
+        "PID", "Name", "CPU%", format!("WS {}", unit.label()), format!("Priv {}", unit.label()), "Handles", "Threads", "Read", "Write"
+    );
+    for p in processes {
+        println!(
+            "{:>7} {:<28}{:>7.1} {:>12} {:>12} {:>8} {:>8} {:>12} {:>12}",
+            p.pid,
+            p.name.chars().take(27).collect::<String>(),
+            p.cpu_percent,
+            unit.format_bytes(p.working_set),
+            unit.format_bytes(p.private_bytes),
+            p.handles,
+            p.threads,
+            format_rate(p.read_rate),
+            format_rate(p.write_rate)
+        );
+    }
+}
+
+//Entry point for `uptime procs [--top N] [--sort cpu|mem|private|handles|io] [--filter NAME|PID]`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime procs [--top N] [--sort cpu|mem|private|handles|io] [--filter NAME|PID]";
+    let (mut count, mut sort, mut filter) = (config.procs_top, config.procs_sort, config.procs_filter.clone());
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let value = iter.next().ok_or(usage)?;
+        match arg.as_str() {
+            "--top" => count = value.parse().map_err(|_| usage)?,
+            "--sort" => sort = SortKey::parse(value).ok_or(usage)?,
+            "--filter" => filter = value.clone(),
+            _ => return Err(usage.into()),
+        }
+    }
+
+    let processes = top(sample(config.cpu_interval), sort, count, &filter);
+    print_processes(&processes, config.memory_unit);
+
+    return Ok(());
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct ProcessDetail {
+    pub process: Process,
+    pub command_line: String,
+    pub exe: String,
+    //DOMAIN\user on Windows, user name (or uid if unknown) on Linux
+    pub owner: String,
+}
+
+#[cfg(windows)]
+const HANDLES_LABEL: &str = "Handles";
+#[cfg(not(windows))]
+const HANDLES_LABEL: &str = "Open FDs";
+
+//Every process from a single SystemProcessInformation query, which also covers
+//the processes OpenProcess is refused for
+#[cfg(windows)]
+pub(crate) fn process_table() -> Vec<Process> {
+    use crate::history::filetime_to_unix;
+    use ntapi::ntexapi::{NtQuerySystemInformation, SystemProcessInformation, SYSTEM_PROCESS_INFORMATION};
+    use winapi::shared::ntstatus::STATUS_INFO_LENGTH_MISMATCH;
+
+    let mut processes: Vec<Process> = vec![];
+    //u64 keeps the entries 8 byte aligned
+    let mut buffer: Vec<u64> = vec![0; 64 * 1024];
+
+    unsafe {
+        loop {
+            let mut needed = 0_u32;
+            let status = NtQuerySystemInformation(
+                SystemProcessInformation,
+                buffer.as_mut_ptr() as _,
+                (buffer.len() * 8) as u32,
+                &mut needed,
+            );
+            if status == STATUS_INFO_LENGTH_MISMATCH {
+                //Leave room for processes started before the next call
+                buffer.resize(needed as usize / 8 + 16 * 1024, 0);
+                continue;
+            }
+            if status < 0 {
+                return processes;
+            }
+            break;
+        }
+
+        let mut offset = 0_usize;
+        loop {
+            let info = &*((buffer.as_ptr() as *const u8).add(offset) as *const SYSTEM_PROCESS_INFORMATION);
+            let name = if info.ImageName.Buffer.is_null() {
+                String::new()
+            } else {
+                String::from_utf16_lossy(std::slice::from_raw_parts(info.ImageName.Buffer, info.ImageName.Length as usize / 2))
+            };
+
+            let p = Process {
+                pid: info.UniqueProcessId as usize as u32,
+                ppid: info.InheritedFromUniqueProcessId as usize as u32,
+                name,
+                start_time: filetime_to_unix(*info.CreateTime.QuadPart()),
+                threads: info.NumberOfThreads,
+                user_time_ms: *info.UserTime.QuadPart() as u64 / 10_000,
+                kernel_time_ms: *info.KernelTime.QuadPart() as u64 / 10_000,
+                working_set: info.WorkingSetSize as u64,
+                private_bytes: info.PrivatePageCount as u64,
+                handles: info.HandleCount,
+                read_bytes: *info.ReadTransferCount.QuadPart() as u64,
+                write_bytes: *info.WriteTransferCount.QuadPart() as u64,
+                ..Default::default()
+            };
+
+            //The System Idle Process is not a real process
+            if p.pid != 0 {
+                processes.push(p);
+            }
+
+            if info.NextEntryOffset == 0 {
+                break;
+            }
+            offset += info.NextEntryOffset as usize;
+        }
+    }
+
+    return processes;
+}
+
+#[cfg(not(windows))]
+pub(crate) fn process_table() -> Vec<Process> {
+    snapshot()
+}
+
+//UNICODE_STRING results such as the command line and Win32 image path
+#[cfg(windows)]
+unsafe fn query_process_string(handle: winapi::um::winnt::HANDLE, class: ntapi::ntpsapi::PROCESSINFOCLASS) -> String {
+    use ntapi::ntpsapi::NtQueryInformationProcess;
+    use winapi::shared::ntdef::UNICODE_STRING;
+
+    let mut buffer: Vec<u64> = vec![0; 1024];
+    let mut needed = 0_u32;
+    let mut status = NtQueryInformationProcess(handle, class, buffer.as_mut_ptr() as _, (buffer.len() * 8) as u32, &mut needed);
+    if status < 0 && needed as usize > buffer.len() * 8 {
+        buffer.resize(needed as usize / 8 + 1, 0);
+        status = NtQueryInformationProcess(handle, class, buffer.as_mut_ptr() as _, (buffer.len() * 8) as u32, &mut needed);
+    }
+    if status < 0 {
+        return String::new();
+    }
+
+    let s = &*(buffer.as_ptr() as *const UNICODE_STRING);
+    if s.Buffer.is_null() {
+        return String::new();
+    }
+    return String::from_utf16_lossy(std::slice::from_raw_parts(s.Buffer, s.Length as usize / 2));
+}
+
+#[cfg(windows)]
+unsafe fn process_owner(handle: winapi::um::winnt::HANDLE) -> String {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::OpenProcessToken;
+    use winapi::um::securitybaseapi::GetTokenInformation;
+    use winapi::um::winbase::LookupAccountSidW;
+    use winapi::um::winnt::{TokenUser, SID_NAME_USE, TOKEN_QUERY, TOKEN_USER};
+
+    let mut token = std::ptr::null_mut();
+    if OpenProcessToken(handle, TOKEN_QUERY, &mut token) == 0 {
+        return String::new();
+    }
+
+    let mut buffer: Vec<u64> = vec![0; 64];
+    let mut needed = 0_u32;
+    let ok = GetTokenInformation(token, TokenUser, buffer.as_mut_ptr() as _, (buffer.len() * 8) as u32, &mut needed) != 0;
+    CloseHandle(token);
+    if !ok {
+        return String::new();
+    }
+
+    let user = &*(buffer.as_ptr() as *const TOKEN_USER);
+    let (mut name, mut domain) = ([0_u16; 256], [0_u16; 256]);
+    let (mut name_len, mut domain_len) = (name.len() as u32, domain.len() as u32);
+    let mut kind: SID_NAME_USE = 0;
+    if LookupAccountSidW(
+        std::ptr::null(),
+        user.User.Sid,
+        name.as_mut_ptr(),
+        &mut name_len,
+        domain.as_mut_ptr(),
+        &mut domain_len,
+        &mut kind,
+    ) == 0
+    {
+        return String::new();
+    }
+
+    return format!(
+        "{}\\{}",
+        String::from_utf16_lossy(&domain[..domain_len as usize]),
+        String::from_utf16_lossy(&name[..name_len as usize])
+    );
+}
+
+#[cfg(windows)]
+unsafe fn read_detail(d: &mut ProcessDetail) {
+    use ntapi::ntpsapi::{ProcessCommandLineInformation, ProcessImageFileNameWin32};
+    use winapi::shared::minwindef::FALSE;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::OpenProcess;
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, d.process.pid);
+    if handle.is_null() {
+        return;
+    }
+
+    d.command_line = query_process_string(handle, ProcessCommandLineInformation);
+    d.exe = query_process_string(handle, ProcessImageFileNameWin32);
+    d.owner = process_owner(handle);
+
+    CloseHandle(handle);
+}
+
+//Arguments in /proc/<pid>/cmdline are NUL separated, kernel threads have none
+#[cfg(not(windows))]
+pub(crate) fn parse_cmdline(bytes: &[u8]) -> String {
+    bytes
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+//Real uid from the "Uid:" line of /proc/<pid>/status
+#[cfg(not(windows))]
+pub(crate) fn parse_uid(text: &str) -> Option<u32> {
+    text.lines()
+        .find_map(|l| l.strip_prefix("Uid:"))
+        .and_then(|v| v.split_whitespace().next())
+        .and_then(|v| v.parse().ok())
+}
+
+#[cfg(not(windows))]
+pub(crate) fn user_name(passwd: &str, uid: u32) -> Option<String> {
+    passwd.lines().find_map(|l| {
+        let fields: Vec<&str> = l.split(':').collect();
+        match fields.get(2).and_then(|v| v.parse::<u32>().ok()) {
+            Some(id) if id == uid => Some(fields[0].to_string()),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(not(windows))]
+unsafe fn read_detail(d: &mut ProcessDetail) {
+    let dir = std::path::Path::new("/proc").join(d.process.pid.to_string());
+
+    d.command_line = std::fs::read(dir.join("cmdline")).map(|b| parse_cmdline(&b)).unwrap_or_default();
+    d.exe = std::fs::read_link(dir.join("exe")).map(|p| p.display().to_string()).unwrap_or_default();
+    if let Some(uid) = std::fs::read_to_string(dir.join("status")).ok().and_then(|s| parse_uid(&s)) {
+        d.owner = std::fs::read_to_string("/etc/passwd")
+            .ok()
+            .and_then(|passwd| user_name(&passwd, uid))
+            .unwrap_or_else(|| uid.to_string());
+    }
+}
+
+//Parent of p, unless the pid has since been reused by a younger process
+fn parent<'a>(by_pid: &HashMap<u32, &'a Process>, p: &Process) -> Option<&'a Process> {
+    by_pid
+        .get(&p.ppid)
+        .copied()
+        .filter(|q| q.pid != p.pid && q.start_time <= p.start_time)
+}
+
+//Ancestors from the oldest down to pid, then its descendants depth first, with the depth of each
+pub(crate) fn tree(processes: &[Process], pid: u32) -> Vec<(usize, &Process)> {
+    let by_pid: HashMap<u32, &Process> = processes.iter().map(|p| (p.pid, p)).collect();
+    let target = match by_pid.get(&pid) {
+        Some(p) => *p,
+        None => return vec![],
+    };
+
+    let mut chain = vec![target];
+    while let Some(q) = parent(&by_pid, chain[chain.len() - 1]) {
+        if chain.iter().any(|c| c.pid == q.pid) {
+            break;
+        }
+        chain.push(q);
+    }
+    chain.reverse();
+
+    let depth = chain.len() - 1;
+    let mut lines: Vec<(usize, &Process)> = chain.into_iter().enumerate().collect();
+
+    let mut children: HashMap<u32, Vec<&Process>> = HashMap::new();
+    for p in processes {
+        if let Some(q) = parent(&by_pid, p) {
+            children.entry(q.pid).or_default().push(p);
+        }
+    }
+
+    //Reversed so the lowest pid comes off the stack first
+    for c in children.values_mut() {
+        c.sort_by_key(|p| std::cmp::Reverse(p.pid));
+    }
+
+    let mut seen = vec![pid];
+    let mut stack: Vec<(usize, &Process)> = match children.get(&pid) {
+        Some(c) => c.iter().map(|&c| (depth + 1, c)).collect(),
+        None => vec![],
+    };
+    while let Some((d, p)) = stack.pop() {
+        if seen.contains(&p.pid) {
+            continue;
+        }
+        seen.push(p.pid);
+        lines.push((d, p));
+        if let Some(c) = children.get(&p.pid) {
+            stack.extend(c.iter().map(|&c| (d + 1, c)));
+        }
+    }
+
+    return lines;
+}
+
+//filter is lowercase. A number only matches that pid, anything else is part of the name
+fn matches(p: &Process, filter: &str) -> bool {
+    match filter.parse::<u32>() {
+        Ok(pid) => p.pid == pid,
+        Err(_) => p.name.to_lowercase().contains(filter),
+    }
+}
+
+fn format_cpu_time(ms: u64) -> String {
+    format!("{}.{:03}s", ms / 1000, ms % 1000)
+}
+
+pub(crate) fn print_detail(d: &ProcessDetail, processes: &[Process], unit: Unit) {
+    let p = &d.process;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|t| t.as_secs() as i64)
+        .unwrap_or(0);
+    let or_unknown = |s: &str| if s.is_empty() { "-".to_string() } else { s.to_string() };
+
+    println!("Process: {} | PID: {} | Parent PID: {}", p.name, p.pid, p.ppid);
+    println!("Command Line: {}", or_unknown(&d.command_line));
+    println!("Executable: {}", or_unknown(&d.exe));
+    println!("Owner: {}", or_unknown(&d.owner));
+    println!("Started: {} | Elapsed: {}", format_time(p.start_time), format_duration(now - p.start_time));
+    println!("Threads: {} | {}: {}", p.threads, HANDLES_LABEL, p.handles);
+    println!(
+        "Working Set: {} {u} | Private: {} {u} | Read: {} {u} | Written: {} {u}",
+        unit.format_bytes(p.working_set),
+        unit.format_bytes(p.private_bytes),
+        unit.format_bytes(p.read_bytes),
+        unit.format_bytes(p.write_bytes),
+        u = unit.label()
+    );
+    println!(
+        "CPU Time: User: {} | Kernel: {} | Total: {}",
+        format_cpu_time(p.user_time_ms),
+        format_cpu_time(p.kernel_time_ms),
+        format_cpu_time(p.user_time_ms + p.kernel_time_ms)
+    );
+    println!("Tree:");
+    for (depth, q) in tree(processes, p.pid) {
+        let marker = if q.pid == p.pid { "*" } else { " " };
+        println!("  {}{}{} ({})", "  ".repeat(depth), marker, q.name, q.pid);
+    }
+}
+
+//Entry point for `uptime proc <PID|NAME>`, a name shows every matching process
+pub(crate) fn run_detail(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let filter = match args {
+        [target] => target.to_lowercase(),
+        _ => return Err("usage: uptime proc <PID|NAME>".into()),
+    };
+
+    let processes = process_table();
+    let found: Vec<&Process> = processes.iter().filter(|p| matches(p, &filter)).collect();
+    if found.is_empty() {
+        return Err(format!("no process matches '{}'", args[0]).into());
+    }
+
+    for (i, p) in found.into_iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let mut d = ProcessDetail {
+            process: p.clone(),
+            ..Default::default()
+        };
+        unsafe { read_detail(&mut d) };
+        print_detail(&d, &processes, config.memory_unit);
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, name: &str) -> Process {
+        Process {
+            pid,
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn numeric_filter_only_matches_the_pid() {
+        let processes = [process(12, "init"), process(400, "app12.exe"), process(1200, "svchost.exe")];
+        let found: Vec<u32> = processes.iter().filter(|p| matches(p, "12")).map(|p| p.pid).collect();
+        assert_eq!(found, vec![12]);
+    }
+
+    #[test]
+    fn name_filter_matches_substrings() {
+        let processes = [process(1, "Code.exe"), process(2, "explorer.exe"), process(3, "vscode")];
+        let found: Vec<u32> = processes.iter().filter(|p| matches(p, "code")).map(|p| p.pid).collect();
+        assert_eq!(found, vec![1, 3]);
+        assert!(processes.iter().all(|p| matches(p, "")));
+    }
+}
@@ -0,0 +1,530 @@
+use crate::config::Config;
+use crate::daemon;
+use crate::disks::Disks;
+use crate::host::Host;
+use crate::ipc;
+use crate::system::{Memory, Processor, SystemInfo, Uptime};
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//Datagrams are packed with whole lines up to this size, under a typical Ethernet MTU so
+//they are not fragmented. A single longer line is still sent on its own
+const MAX_DATAGRAM: usize = 1400;
+
+//Lines held while the endpoint is unreachable, the oldest are dropped beyond this
+const MAX_QUEUE: usize = 10_000;
+
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Protocol {
+    Influx,
+    Statsd,
+    //StatsD with |#tag:value tags, as accepted by the Datadog agent
+    Dogstatsd,
+}
+
+impl Protocol {
+    pub(crate) fn parse(s: &str) -> Option<Protocol> {
+        match s.to_lowercase().as_str() {
+            "influx" | "influxdb" => Some(Protocol::Influx),
+            "statsd" => Some(Protocol::Statsd),
+            "dogstatsd" => Some(Protocol::Dogstatsd),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transport {
+    Udp,
+    Tcp,
+}
+
+//udp://host:port or tcp://host:port, a bare host:port is UDP
+#[derive(Debug, Clone)]
+struct Target {
+    transport: Transport,
+    address: String,
+}
+
+impl Target {
+    fn parse(s: &str) -> Result<Target, Box<dyn Error>> {
+        let (transport, address) = match s.split_once("://") {
+            Some(("udp", a)) => (Transport::Udp, a),
+            Some(("tcp", a)) => (Transport::Tcp, a),
+            Some((scheme, _)) => return Err(format!("Unsupported scheme '{}' in '{}', expected udp:// or tcp://", scheme, s).into()),
+            None => (Transport::Udp, s),
+        };
+        if !address.contains(':') {
+            return Err(format!("Missing port in push target '{}'", s).into());
+        }
+        return Ok(Target { transport, address: address.to_string() });
+    }
+
+    fn resolve(&self) -> Result<SocketAddr, Box<dyn Error>> {
+        //Resolved on every send so a DNS change is picked up without a restart
+        return self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("{}: no addresses", self.address).into());
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Int(u64),
+    Float(f64),
+}
+
+impl Field {
+    //Neither protocol has a way to write NaN or infinity, so such fields are left out
+    fn is_finite(&self) -> bool {
+        match self {
+            Field::Int(_) => true,
+            Field::Float(f) => f.is_finite(),
+        }
+    }
+}
+
+//One measurement with its tags, e.g. disk,host=web1,drive=C free=..,used=..
+#[derive(Debug, Clone)]
+struct Point {
+    measurement: &'static str,
+    tags: Vec<(&'static str, String)>,
+    fields: Vec<(&'static str, Field)>,
+}
+
+fn collect(hostname: &str, config: &Config) -> Vec<Point> {
+    let host = || vec![("host", hostname.to_string())];
+    let mut points = vec![];
+
+    let mut u: Uptime = SystemInfo::new();
+    let u = u.info();
+    points.push(Point {
+        measurement: "uptime",
+        tags: host(),
+        fields: vec![("seconds", Field::Int(u.uptime_ms / 1000)), ("active_seconds", Field::Int(u.active_ms / 1000))],
+    });
+
+    let mut m: Memory = SystemInfo::new();
+    let m = m.info();
+    //Memory reports megabytes
+    let bytes = |v: u64| Field::Int(v * 1024 * 1024);
+    let mut fields = vec![
+        ("installed", bytes(m.installed_ram)),
+        ("available", bytes(m.available_memory)),
+        ("used", bytes(m.installed_ram.saturating_sub(m.available_memory))),
+        ("page_total", bytes(m.page_file_size)),
+        ("page_used", bytes(m.used_page)),
+    ];
+    if let (Some(limit), Some(used)) = (m.limit_memory, m.limit_used) {
+        fields.push(("limit", bytes(limit)));
+        fields.push(("limit_used", bytes(used)));
+    }
+    points.push(Point { measurement: "memory", tags: host(), fields });
+
+    let l = ipc::load(config);
+    points.push(Point {
+        measurement: "load",
+        tags: host(),
+        fields: vec![
+            ("current", Field::Float(l.current_load)),
+            ("load1", Field::Float(l.avg_1_load)),
+            ("load5", Field::Float(l.avg_5_load)),
+            ("load15", Field::Float(l.avg_15_load)),
+        ],
+    });
+
+    let mut p: Processor = SystemInfo::new();
+    let p = p.info();
+    points.push(Point {
+        measurement: "cpu",
+        tags: host(),
+        fields: vec![
+            ("usage", Field::Float(l.processor_activity)),
+            ("mhz", Field::Int(p.current_mhz)),
+            ("cores", Field::Int(p.number_processors as u64)),
+            ("effective_cores", Field::Float(p.effective_processors)),
+        ],
+    });
+
+    let mut d = Disks::new();
+    for disk in unsafe { d.info() }.iter().filter(|d| config.include_disk(d)) {
+        let letter = disk.drive.trim_end_matches('\\').trim_end_matches(':');
+        let mut tags = host();
+        tags.push(("drive", letter.to_string()));
+        points.push(Point {
+            measurement: "disk",
+            tags,
+            fields: vec![
+                ("size", Field::Int(disk.total_bytes)),
+                ("free", Field::Int(disk.available_bytes)),
+                ("used", Field::Int(disk.total_bytes.saturating_sub(disk.available_bytes))),
+            ],
+        });
+    }
+
+    return points;
+}
+
+//Line protocol escaping: measurement names escape commas and spaces, tag keys and values
+//also escape equals signs
+fn escape_influx(s: &str, equals: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == ',' || c == ' ' || (equals && c == '=') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    return out;
+}
+
+//StatsD names are dot separated, so dots, colons, pipes and spaces inside a part become _
+fn sanitise_statsd(s: &str) -> String {
+    return s.chars().map(|c| if matches!(c, '.' | ':' | '|' | '@' | '#' | ',' | ' ') { '_' } else { c }).collect();
+}
+
+//None when no field is left to write, a line needs at least one
+fn format_influx(p: &Point, timestamp_ns: u128) -> Option<String> {
+    let fields: Vec<String> = p
+        .fields
+        .iter()
+        .filter(|(_, v)| v.is_finite())
+        .map(|(k, v)| match v {
+            Field::Int(i) => format!("{}={}i", k, i),
+            Field::Float(f) => format!("{}={}", k, f),
+        })
+        .collect();
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut line = escape_influx(p.measurement, false);
+    for (k, v) in &p.tags {
+        line.push_str(&format!(",{}={}", k, escape_influx(v, true)));
+    }
+    line.push_str(&format!(" {} {}", fields.join(","), timestamp_ns));
+    return Some(line);
+}
+
+//Every field becomes a gauge. Plain StatsD has no tags, so host and drive go into the name:
+//prefix.host.disk.C.free. DogStatsD keeps the name short and sends them as tags
+fn format_statsd(p: &Point, prefix: &str, protocol: Protocol) -> Vec<String> {
+    let mut lines = vec![];
+
+    for (k, v) in p.fields.iter().filter(|(_, v)| v.is_finite()) {
+        let value = match v {
+            Field::Int(i) => i.to_string(),
+            Field::Float(f) => f.to_string(),
+        };
+        let mut name: Vec<String> = vec![];
+        if !prefix.is_empty() {
+            name.push(prefix.to_string());
+        }
+
+        if protocol == Protocol::Dogstatsd {
+            name.push(p.measurement.to_string());
+            name.push(k.to_string());
+            //Tag values may contain dots, only the tag and field separators need replacing
+            let tags: Vec<String> = p.tags.iter().map(|(t, v)| format!("{}:{}", t, v.replace([',', '|', '#', ' '], "_"))).collect();
+            lines.push(format!("{}:{}|g|#{}", name.join("."), value, tags.join(",")));
+        } else {
+            for (t, v) in &p.tags {
+                if *t == "host" {
+                    name.push(sanitise_statsd(v));
+                }
+            }
+            name.push(p.measurement.to_string());
+            for (t, v) in &p.tags {
+                if *t != "host" {
+                    name.push(sanitise_statsd(v));
+                }
+            }
+            name.push(k.to_string());
+            lines.push(format!("{}:{}|g", name.join("."), value));
+        }
+    }
+
+    return lines;
+}
+
+fn format_points(points: &[Point], protocol: Protocol, prefix: &str) -> Vec<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    return match protocol {
+        Protocol::Influx => points.iter().filter_map(|p| format_influx(p, now)).collect(),
+        _ => points.iter().flat_map(|p| format_statsd(p, prefix, protocol)).collect(),
+    };
+}
+
+//Lines waiting to be sent. They leave the queue only once delivered, so a failed send is
+//retried with the next batch
+struct Sender {
+    target: Target,
+    queue: VecDeque<String>,
+    dropped: usize,
+}
+
+impl Sender {
+    fn new(target: Target) -> Sender {
+        return Sender { target, queue: VecDeque::new(), dropped: 0 };
+    }
+
+    fn enqueue(&mut self, lines: Vec<String>) {
+        self.queue.extend(lines);
+        while self.queue.len() > MAX_QUEUE {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    //Whole lines packed into datagrams of at most MAX_DATAGRAM bytes, with the line count of each
+    fn datagrams(&self) -> Vec<(usize, String)> {
+        let mut list: Vec<(usize, String)> = vec![];
+        for line in &self.queue {
+            match list.last_mut() {
+                Some((count, payload)) if payload.len() + 1 + line.len() <= MAX_DATAGRAM => {
+                    payload.push('\n');
+                    payload.push_str(line);
+                    *count += 1;
+                }
+                _ => list.push((1, line.clone())),
+            }
+        }
+        return list;
+    }
+
+    fn send_once(&mut self) -> Result<(), Box<dyn Error>> {
+        let addr = self.target.resolve()?;
+
+        match self.target.transport {
+            Transport::Udp => {
+                let local = if addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(addr)?;
+                for (count, payload) in self.datagrams() {
+                    socket.send(payload.as_bytes())?;
+                    self.queue.drain(..count);
+                }
+            }
+            Transport::Tcp => {
+                //A connection per flush, so a restarted collector is picked up. If it fails
+                //part way the whole batch is resent, which both protocols tolerate for gauges
+                let mut stream = TcpStream::connect_timeout(&addr, TCP_TIMEOUT)?;
+                stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+                let mut payload = String::new();
+                for line in &self.queue {
+                    payload.push_str(line);
+                    payload.push('\n');
+                }
+                stream.write_all(payload.as_bytes())?;
+                stream.flush()?;
+                self.queue.clear();
+            }
+        }
+
+        return Ok(());
+    }
+
+    //Send everything queued, retrying with a doubling delay. Lines that still fail stay queued.
+    //A stop or reload request ends the retries early so the service is not held up
+    fn flush(&mut self, retries: u32) -> Result<(), Box<dyn Error>> {
+        if self.dropped > 0 {
+            eprintln!("push: dropped {} lines while {} was unreachable", self.dropped, self.target.address);
+            self.dropped = 0;
+        }
+
+        let mut delay = Duration::from_secs(1);
+        let mut attempt = 0;
+        loop {
+            match self.send_once() {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    if attempt >= retries || !daemon::wait(delay) {
+                        return Err(format!("push to {}: {} ({} lines queued)", self.target.address, e, self.queue.len()).into());
+                    }
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+//Entry point for `uptime push [--target URL] [--protocol P] [--interval N] [--prefix P] [--once] [--print]`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime push [--target udp://HOST:PORT|tcp://HOST:PORT] [--protocol influx|statsd|dogstatsd] [--interval SECONDS] [--prefix NAME] [--once] [--print]";
+    let mut target = config.push_target.clone();
+    let mut protocol = config.push_protocol;
+    let mut interval = config.push_interval;
+    let mut prefix = config.push_prefix.clone();
+    let mut once = false;
+    let mut print = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--once" => once = true,
+            //Write the lines to stdout instead of sending them, to check the format
+            "--print" => print = true,
+            "--target" => target = Some(iter.next().ok_or(usage)?.clone()),
+            "--protocol" => protocol = iter.next().and_then(|p| Protocol::parse(p)).ok_or(usage)?,
+            "--interval" => interval = iter.next().and_then(|v| v.parse().ok()).filter(|v| *v > 0).ok_or(usage)?,
+            "--prefix" => prefix = iter.next().ok_or(usage)?.clone(),
+            _ => return Err(usage.into()),
+        }
+    }
+
+    let target = match (target, print) {
+        (Some(t), _) => Target::parse(&t)?,
+        (None, true) => Target { transport: Transport::Udp, address: String::new() },
+        (None, false) => return Err(format!("No push target, set [push] target or --target\n{}", usage).into()),
+    };
+
+    //Looked up once, the hostname does not change while we run
+    let hostname = Host::info().hostname;
+    let mut sender = Sender::new(target);
+
+    loop {
+        let lines = format_points(&collect(&hostname, config), protocol, &prefix);
+
+        if print {
+            for line in &lines {
+                println!("{}", line);
+            }
+        } else {
+            sender.enqueue(lines);
+            match sender.flush(config.push_retries) {
+                Ok(()) => {}
+                Err(e) if once => return Err(e),
+                //Keep collecting, the queued lines go out with the next batch
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        if once || !daemon::wait(Duration::from_secs(interval as u64)) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn point() -> Point {
+        Point {
+            measurement: "disk",
+            tags: vec![("host", "web 1.example".to_string()), ("drive", "C".to_string())],
+            fields: vec![("free", Field::Int(100)), ("ratio", Field::Float(0.25))],
+        }
+    }
+
+    #[test]
+    fn influx_line_protocol() {
+        assert_eq!(format_influx(&point(), 1_700_000_000_000_000_000).unwrap(), "disk,host=web\\ 1.example,drive=C free=100i,ratio=0.25 1700000000000000000");
+        assert_eq!(escape_influx("a b,c=d", true), "a\\ b\\,c\\=d");
+        assert_eq!(escape_influx("a b,c=d", false), "a\\ b\\,c=d");
+    }
+
+    #[test]
+    fn non_finite_fields_are_skipped() {
+        let mut p = point();
+        p.fields.push(("load", Field::Float(f64::NAN)));
+        p.fields.push(("rate", Field::Float(f64::INFINITY)));
+        assert_eq!(format_influx(&p, 1).unwrap(), "disk,host=web\\ 1.example,drive=C free=100i,ratio=0.25 1");
+        assert_eq!(format_statsd(&p, "", Protocol::Statsd).len(), 2);
+
+        p.fields = vec![("load", Field::Float(f64::NAN))];
+        assert_eq!(format_influx(&p, 1), None);
+        assert!(format_statsd(&p, "", Protocol::Dogstatsd).is_empty());
+    }
+
+    #[test]
+    fn statsd_names_and_dogstatsd_tags() {
+        assert_eq!(
+            format_statsd(&point(), "servers", Protocol::Statsd),
+            vec!["servers.web_1_example.disk.C.free:100|g", "servers.web_1_example.disk.C.ratio:0.25|g"]
+        );
+        assert_eq!(
+            format_statsd(&point(), "", Protocol::Dogstatsd),
+            vec!["disk.free:100|g|#host:web_1.example,drive:C", "disk.ratio:0.25|g|#host:web_1.example,drive:C"]
+        );
+    }
+
+    #[test]
+    fn targets() {
+        let t = Target::parse("tcp://collector:8125").unwrap();
+        assert_eq!((t.transport, t.address.as_str()), (Transport::Tcp, "collector:8125"));
+        assert_eq!(Target::parse("127.0.0.1:8089").unwrap().transport, Transport::Udp);
+        assert!(Target::parse("http://collector:8086").is_err());
+        assert!(Target::parse("udp://collector").is_err());
+    }
+
+    #[test]
+    fn lines_are_packed_into_datagrams() {
+        let mut sender = Sender::new(Target::parse("127.0.0.1:1").unwrap());
+        let line = "x".repeat(99);
+        sender.enqueue(vec![line.clone(); 30]);
+        sender.enqueue(vec!["y".repeat(MAX_DATAGRAM + 10)]);
+
+        let datagrams = sender.datagrams();
+        //14 lines of 99 bytes and 13 newlines fill 1399 of the 1400 bytes
+        let counts: Vec<usize> = datagrams.iter().map(|(count, _)| *count).collect();
+        assert_eq!(counts, vec![14, 14, 2, 1]);
+        assert!(datagrams[..3].iter().all(|(_, payload)| payload.len() <= MAX_DATAGRAM));
+        assert_eq!(datagrams[3].1.len(), MAX_DATAGRAM + 10);
+    }
+
+    #[test]
+    fn flush_to_a_local_udp_listener() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let target = format!("udp://{}", listener.local_addr().unwrap());
+
+        let lines: Vec<String> = (0..40).map(|i| format!("uptime,host=web{} seconds={}i", i, i * 1000)).collect();
+        let mut sender = Sender::new(Target::parse(&target).unwrap());
+        sender.enqueue(lines.clone());
+        sender.flush(0).unwrap();
+        assert!(sender.queue.is_empty());
+
+        let mut received: Vec<String> = vec![];
+        let mut buf = [0u8; 65536];
+        while received.len() < lines.len() {
+            let n = listener.recv(&mut buf).unwrap();
+            assert!(n <= MAX_DATAGRAM);
+            received.extend(String::from_utf8_lossy(&buf[..n]).lines().map(|l| l.to_string()));
+        }
+        assert_eq!(received, lines);
+    }
+
+    #[test]
+    fn flush_over_tcp_and_keep_lines_when_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = Target::parse(&format!("tcp://{}", listener.local_addr().unwrap())).unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut text = String::new();
+            listener.accept().unwrap().0.read_to_string(&mut text).unwrap();
+            text
+        });
+
+        let mut sender = Sender::new(target.clone());
+        sender.enqueue(vec!["a:1|g".to_string(), "b:2|g".to_string()]);
+        sender.flush(0).unwrap();
+        assert_eq!(reader.join().unwrap(), "a:1|g\nb:2|g\n");
+
+        //Nothing listens on the port any more, the lines wait for the next flush
+        sender.enqueue(vec!["c:3|g".to_string()]);
+        assert!(sender.flush(0).is_err());
+        assert_eq!(sender.queue.len(), 1);
+    }
+}
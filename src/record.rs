@@ -0,0 +1,465 @@
+use crate::check::parse_duration;
+use crate::config::Config;
+use crate::daemon;
+use crate::disks::Disks;
+use crate::history::format_time;
+use crate::ipc;
+use crate::system::{Memory, Processor, SystemInfo, Uptime};
+
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+//The store is a directory of text files, one line per sample: "<unix time> name=value ...".
+//Raw samples age into 5 minute averages, then hourly averages, then are dropped, so the
+//tiers never overlap and a query just reads all three
+const TIERS: [(&str, i64); 3] = [("raw.log", 0), ("5m.log", 300), ("1h.log", 3600)];
+
+//How often the recorder moves expired samples down a tier
+const COMPACT_SECONDS: i64 = 3600;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Sample {
+    pub(crate) time: i64,
+    pub(crate) values: Vec<(String, f64)>,
+}
+
+impl Sample {
+    fn to_line(&self) -> String {
+        let mut line = self.time.to_string();
+        for (name, value) in &self.values {
+            line.push_str(&format!(" {}={}", name, value));
+        }
+        return line;
+    }
+
+    fn parse(line: &str) -> Option<Sample> {
+        let mut parts = line.split_whitespace();
+        let time = parts.next()?.parse().ok()?;
+        let values = parts
+            .filter_map(|p| p.split_once('=').and_then(|(n, v)| v.parse().ok().map(|v| (n.to_string(), v))))
+            .collect();
+        Some(Sample { time, values })
+    }
+}
+
+pub(crate) fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+//Same metric names as the perfdata labels in check mode
+fn collect(config: &Config) -> Sample {
+    let mut values: Vec<(String, f64)> = vec![];
+
+    let mut u: Uptime = SystemInfo::new();
+    values.push(("uptime".to_string(), (u.info().uptime_ms / 1000) as f64));
+
+    let mut m: Memory = SystemInfo::new();
+    let m = m.info();
+    let (installed, available) = (m.installed_ram * 1024 * 1024, m.available_memory * 1024 * 1024);
+    values.push(("memory_available".to_string(), available as f64));
+    values.push(("memory_used".to_string(), installed.saturating_sub(available) as f64));
+
+    let l = ipc::load(config);
+    values.push(("load".to_string(), l.current_load));
+    values.push(("load1".to_string(), l.avg_1_load));
+    values.push(("load5".to_string(), l.avg_5_load));
+    values.push(("load15".to_string(), l.avg_15_load));
+    values.push(("cpu".to_string(), l.processor_activity));
+
+    let mut p: Processor = SystemInfo::new();
+    let p = p.info();
+    values.push(("cores".to_string(), p.effective_processors));
+    values.push(("mhz".to_string(), p.current_mhz as f64));
+
+    let mut d = Disks::new();
+    for disk in unsafe { d.info() }.iter().filter(|d| config.include_disk(d)) {
+        let letter = disk.drive.trim_end_matches('\\').trim_end_matches(':');
+        values.push((format!("disk_{}_free", letter), disk.available_bytes as f64));
+        values.push((format!("disk_{}_used", letter), disk.total_bytes.saturating_sub(disk.available_bytes) as f64));
+    }
+
+    return Sample { time: now(), values };
+}
+
+fn read_tier(path: &Path) -> Result<Vec<Sample>, Box<dyn Error>> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+    };
+    let mut samples = vec![];
+    for line in BufReader::new(file).lines() {
+        //A torn last line from a crash is skipped rather than failing the whole query
+        samples.extend(Sample::parse(&line?));
+    }
+    return Ok(samples);
+}
+
+fn append(path: &Path, samples: &[Sample]) -> Result<(), Box<dyn Error>> {
+    if samples.is_empty() {
+        return Ok(());
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut text = String::new();
+    for s in samples {
+        text.push_str(&s.to_line());
+        text.push('\n');
+    }
+    file.write_all(text.as_bytes())?;
+    return Ok(());
+}
+
+//Replace via a temporary file so a crash leaves either the old or the new tier
+fn rewrite(path: &Path, samples: &[Sample]) -> Result<(), Box<dyn Error>> {
+    let temp = path.with_extension("tmp");
+    let _ = std::fs::remove_file(&temp);
+    append(&temp, samples)?;
+    if samples.is_empty() {
+        let _ = std::fs::remove_file(path);
+        return Ok(());
+    }
+    std::fs::rename(&temp, path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    return Ok(());
+}
+
+//Average each metric over fixed buckets, timestamped at the start of the bucket
+fn downsample(samples: &[Sample], bucket: i64) -> Vec<Sample> {
+    let mut buckets: BTreeMap<i64, BTreeMap<String, (f64, u32)>> = BTreeMap::new();
+    for s in samples {
+        let entry = buckets.entry(s.time - s.time.rem_euclid(bucket)).or_default();
+        for (name, value) in &s.values {
+            let (sum, count) = entry.entry(name.clone()).or_insert((0.0, 0));
+            *sum += value;
+            *count += 1;
+        }
+    }
+    return buckets
+        .into_iter()
+        .map(|(time, metrics)| Sample {
+            time,
+            values: metrics.into_iter().map(|(name, (sum, count))| (name, sum / count as f64)).collect(),
+        })
+        .collect();
+}
+
+//Move samples past each tier's retention into the next tier, dropping them from the last.
+//The next tier is appended to before this one is rewritten, so a crash in between leaves
+//the moved buckets in both. Buckets the next tier already has are not added again
+pub(crate) fn compact(dir: &Path, retention: &[u64; 3], now: i64) -> Result<(), Box<dyn Error>> {
+    for (i, (file, _)) in TIERS.iter().enumerate() {
+        let path = dir.join(file);
+        let samples = read_tier(&path)?;
+        let mut cutoff = now - retention[i] as i64;
+
+        //Cut on a bucket boundary of the next tier so every bucket moves down whole
+        if let Some((_, next_bucket)) = TIERS.get(i + 1) {
+            cutoff -= cutoff.rem_euclid(*next_bucket);
+        }
+        let (expired, keep): (Vec<Sample>, Vec<Sample>) = samples.into_iter().partition(|s| s.time < cutoff);
+        if expired.is_empty() {
+            continue;
+        }
+
+        if let Some((next_file, next_bucket)) = TIERS.get(i + 1) {
+            let next = dir.join(next_file);
+            let moved: BTreeSet<i64> = read_tier(&next)?.iter().map(|s| s.time).collect();
+            let buckets: Vec<Sample> = downsample(&expired, *next_bucket)
+                .into_iter()
+                .filter(|s| !moved.contains(&s.time))
+                .collect();
+            append(&next, &buckets)?;
+        }
+        rewrite(&path, &keep)?;
+    }
+    return Ok(());
+}
+
+//Values of one metric between from and to, oldest first
+pub(crate) fn query(dir: &Path, metric: &str, from: i64, to: i64) -> Result<Vec<(i64, f64)>, Box<dyn Error>> {
+    let mut points = vec![];
+    for (file, _) in TIERS.iter() {
+        for s in read_tier(&dir.join(file))?.into_iter().filter(|s| s.time >= from && s.time <= to) {
+            if let Some((_, v)) = s.values.iter().find(|(n, _)| n == metric) {
+                points.push((s.time, *v));
+            }
+        }
+    }
+    points.sort_by_key(|(t, _)| *t);
+    return Ok(points);
+}
+
+pub(crate) fn metric_names(dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut names = BTreeSet::new();
+    for (file, _) in TIERS.iter() {
+        for s in read_tier(&dir.join(file))? {
+            names.extend(s.values.into_iter().map(|(n, _)| n));
+        }
+    }
+    return Ok(names.into_iter().collect());
+}
+
+//"now", a duration ago ("3h", "2d"), or a local date and time ("2024-10-19", "2024-10-19 03:00")
+pub(crate) fn parse_time(s: &str, now: i64) -> Result<i64, Box<dyn Error>> {
+    let s = s.trim();
+    if s == "now" {
+        return Ok(now);
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, format) {
+            if let Some(local) = Local.from_local_datetime(&t).earliest() {
+                return Ok(local.timestamp());
+            }
+        }
+    }
+    if let Some(midnight) = NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)) {
+        if let Some(local) = Local.from_local_datetime(&midnight).earliest() {
+            return Ok(local.timestamp());
+        }
+    }
+    match parse_duration(s) {
+        Ok(secs) => Ok(now - secs as i64),
+        Err(_) => Err(format!("Invalid time '{}', expected now, a duration ago like 3h, or YYYY-MM-DD [HH:MM[:SS]]", s).into()),
+    }
+}
+
+//Entry point for `uptime record [--interval SECONDS] [--dir PATH]`, runs until killed or stopped
+pub(crate) fn run_record(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime record [--interval SECONDS] [--dir PATH]";
+    let mut interval = config.record_interval;
+    let mut dir = config.record_dir.clone();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match (arg.as_str(), iter.next()) {
+            ("--interval", Some(v)) => interval = v.parse().ok().filter(|i| *i > 0).ok_or(usage)?,
+            ("--dir", Some(v)) => dir = PathBuf::from(v),
+            _ => return Err(usage.into()),
+        }
+    }
+
+    std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    println!("Recording every {}s to {}", interval, dir.display());
+
+    let mut last_compact = 0;
+    loop {
+        let start = Instant::now();
+        let sample = collect(config);
+        append(&dir.join(TIERS[0].0), std::slice::from_ref(&sample))?;
+
+        if sample.time - last_compact >= COMPACT_SECONDS {
+            compact(&dir, &config.record_retention, sample.time)?;
+            last_compact = sample.time;
+        }
+
+        //Sampling load takes a few seconds itself, keep to the interval rather than drifting
+        let wait = Duration::from_secs(interval as u64).saturating_sub(start.elapsed());
+        if !daemon::wait(wait) {
+            return Ok(());
+        }
+    }
+}
+
+//Entry point for `uptime query [--metric NAME] [--from TIME] [--to TIME] [--dir PATH]`
+pub(crate) fn run_query(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime query [--metric NAME] [--from TIME] [--to TIME] [--dir PATH]";
+    let now = now();
+    let (mut metric, mut from, mut to) = (None, now - 86400, now);
+    let mut dir = config.record_dir.clone();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match (arg.as_str(), iter.next()) {
+            ("--metric", Some(v)) => metric = Some(v.clone()),
+            ("--from", Some(v)) => from = parse_time(v, now)?,
+            ("--to", Some(v)) => to = parse_time(v, now)?,
+            ("--dir", Some(v)) => dir = PathBuf::from(v),
+            _ => return Err(usage.into()),
+        }
+    }
+
+    //Without a metric list what has been recorded
+    let metric = match metric {
+        Some(m) => m,
+        None => {
+            let names = metric_names(&dir)?;
+            if names.is_empty() {
+                return Err(format!("No metrics recorded in {}", dir.display()).into());
+            }
+            println!("Metrics: {}", names.join(", "));
+            return Ok(());
+        }
+    };
+
+    let points = query(&dir, &metric, from, to)?;
+    if points.is_empty() {
+        println!("No samples of {} between {} and {}", metric, format_time(from), format_time(to));
+        return Ok(());
+    }
+
+    for (time, value) in &points {
+        println!("{}  {}", format_time(*time), value);
+    }
+    let (min, max, sum) = points
+        .iter()
+        .fold((f64::MAX, f64::MIN, 0.0), |(lo, hi, sum), (_, v)| (lo.min(*v), hi.max(*v), sum + v));
+    println!(
+        "Samples: {} | Min: {} | Avg: {:.2} | Max: {}",
+        points.len(),
+        min,
+        sum / points.len() as f64,
+        max
+    );
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Hour aligned, so 5 minute and hourly buckets start at BASE
+    const BASE: i64 = 1_700_006_400;
+    const RETENTION: [u64; 3] = [3600, 86400, 7 * 86400];
+
+    //A scratch store per test, removed when the test finishes
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str) -> Dir {
+            let path = std::env::temp_dir().join(format!("uptime-record-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            return Dir(path);
+        }
+
+        fn tier(&self, i: usize) -> Vec<(i64, f64)> {
+            return read_tier(&self.0.join(TIERS[i].0)).unwrap().iter().map(|s| (s.time, s.values[0].1)).collect();
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    //One raw sample a minute for the given number of minutes, the value is the minute
+    fn record_minutes(dir: &Dir, minutes: i64) {
+        let samples: Vec<Sample> = (0..minutes)
+            .map(|m| Sample { time: BASE + m * 60, values: vec![("load".to_string(), m as f64)] })
+            .collect();
+        append(&dir.0.join(TIERS[0].0), &samples).unwrap();
+    }
+
+    #[test]
+    fn sample_lines() {
+        let s = Sample { time: BASE, values: vec![("load".to_string(), 0.5), ("disk_C_free".to_string(), 1e12)] };
+        assert_eq!(s.to_line(), "1700006400 load=0.5 disk_C_free=1000000000000");
+        let back = Sample::parse(&s.to_line()).unwrap();
+        assert_eq!((back.time, back.values), (s.time, s.values));
+
+        //Values that do not parse are dropped, a line without a time is skipped
+        assert_eq!(Sample::parse("10 a=1 b=x c").unwrap().values, vec![("a".to_string(), 1.0)]);
+        assert!(Sample::parse("load=1").is_none());
+        assert!(Sample::parse("").is_none());
+    }
+
+    #[test]
+    fn buckets_start_on_the_boundary() {
+        let samples: Vec<Sample> = [(BASE + 10, 1.0), (BASE + 299, 3.0), (BASE + 300, 5.0), (BASE - 1, 7.0)]
+            .iter()
+            .map(|(t, v)| Sample { time: *t, values: vec![("load".to_string(), *v)] })
+            .collect();
+        let buckets: Vec<(i64, f64)> = downsample(&samples, 300).iter().map(|s| (s.time, s.values[0].1)).collect();
+        assert_eq!(buckets, vec![(BASE - 300, 7.0), (BASE, 2.0), (BASE + 300, 5.0)]);
+    }
+
+    #[test]
+    fn samples_age_through_the_tiers() {
+        let dir = Dir::new("tiers");
+        record_minutes(&dir, 180);
+
+        //Raw older than an hour moves to 5 minute buckets, cut on a bucket boundary
+        let now = BASE + 3 * 3600 + 100;
+        compact(&dir.0, &RETENTION, now).unwrap();
+        let raw = dir.tier(0);
+        assert_eq!((raw.len(), raw[0].0), (60, BASE + 7200));
+        let five = dir.tier(1);
+        assert_eq!(five.len(), 24);
+        assert_eq!((five[0], five[23]), ((BASE, 2.0), (BASE + 23 * 300, 117.0)));
+        assert!(dir.tier(2).is_empty());
+
+        //Compacting again moves nothing until a whole bucket has expired, then moves it whole
+        compact(&dir.0, &RETENTION, now + 60).unwrap();
+        assert_eq!((dir.tier(0).len(), dir.tier(1).len()), (60, 24));
+        compact(&dir.0, &RETENTION, now + 300).unwrap();
+        let five = dir.tier(1);
+        assert_eq!((dir.tier(0).len(), five.len()), (55, 25));
+        assert_eq!(five[24], (BASE + 7200, 122.0));
+
+        //A day later everything is in hourly buckets
+        compact(&dir.0, &RETENTION, now + 86400).unwrap();
+        assert!(dir.tier(0).is_empty() && dir.tier(1).is_empty());
+        assert_eq!(dir.tier(2), vec![(BASE, 29.5), (BASE + 3600, 89.5), (BASE + 7200, 149.5)]);
+
+        //And past the last retention they are dropped
+        compact(&dir.0, &RETENTION, now + 86400 + 7 * 86400 + 3 * 3600).unwrap();
+        assert!(dir.tier(2).is_empty());
+    }
+
+    #[test]
+    fn interrupted_compaction_is_not_duplicated() {
+        let dir = Dir::new("crash");
+        record_minutes(&dir, 180);
+        let now = BASE + 3 * 3600 + 100;
+
+        //As if the last compaction stopped after adding the buckets but before rewriting raw
+        let raw = read_tier(&dir.0.join(TIERS[0].0)).unwrap();
+        let expired: Vec<Sample> = raw.into_iter().filter(|s| s.time < BASE + 7200).collect();
+        append(&dir.0.join(TIERS[1].0), &downsample(&expired, 300)).unwrap();
+
+        compact(&dir.0, &RETENTION, now).unwrap();
+        let five = dir.tier(1);
+        let times: BTreeSet<i64> = five.iter().map(|(t, _)| *t).collect();
+        assert_eq!((five.len(), times.len()), (24, 24));
+        assert_eq!(dir.tier(0).len(), 60);
+    }
+
+    #[test]
+    fn queries_read_every_tier() {
+        let dir = Dir::new("query");
+        let line = |t: i64, v: f64| Sample { time: t, values: vec![("load".to_string(), v), ("cpu".to_string(), v * 10.0)] };
+        append(&dir.0.join(TIERS[2].0), &[line(BASE, 1.0)]).unwrap();
+        append(&dir.0.join(TIERS[1].0), &[line(BASE + 3600, 2.0)]).unwrap();
+        append(&dir.0.join(TIERS[0].0), &[line(BASE + 7260, 4.0), line(BASE + 7200, 3.0)]).unwrap();
+
+        let all = query(&dir.0, "load", BASE, BASE + 7260).unwrap();
+        assert_eq!(all, vec![(BASE, 1.0), (BASE + 3600, 2.0), (BASE + 7200, 3.0), (BASE + 7260, 4.0)]);
+        assert_eq!(query(&dir.0, "cpu", BASE + 1, BASE + 7200).unwrap(), vec![(BASE + 3600, 20.0), (BASE + 7200, 30.0)]);
+        assert!(query(&dir.0, "mhz", BASE, BASE + 7260).unwrap().is_empty());
+        assert_eq!(metric_names(&dir.0).unwrap(), vec!["cpu".to_string(), "load".to_string()]);
+    }
+
+    #[test]
+    fn query_times() {
+        assert_eq!(parse_time("now", BASE).unwrap(), BASE);
+        assert_eq!(parse_time("3h", BASE).unwrap(), BASE - 3 * 3600);
+        assert_eq!(parse_time("90", BASE).unwrap(), BASE - 90);
+
+        let local = |y, mo, d, h, mi| Local.with_ymd_and_hms(y, mo, d, h, mi, 0).earliest().unwrap().timestamp();
+        assert_eq!(parse_time("2024-10-19", BASE).unwrap(), local(2024, 10, 19, 0, 0));
+        assert_eq!(parse_time("2024-10-19 03:00", BASE).unwrap(), local(2024, 10, 19, 3, 0));
+        assert_eq!(parse_time("2024-10-19T03:00:00", BASE).unwrap(), local(2024, 10, 19, 3, 0));
+        assert!(parse_time("yesterday", BASE).is_err());
+        assert!(parse_time("2024-13-01", BASE).is_err());
+    }
+}
@@ -0,0 +1,573 @@
+use crate::config::{Config, Format, Section};
+use crate::daemon;
+use crate::history::format_duration;
+use crate::json::{self, Value};
+use crate::table;
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub(crate) const DEFAULT_PORT: u16 = 7070;
+
+//A snapshot is a few KB, anything far beyond that is not an agent talking
+const MAX_RESPONSE: u64 = 1024 * 1024;
+
+//How long the agent waits for a request line once a client has connected
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+//Requests the agent samples at once, more connections are turned away with "error busy"
+const MAX_ANSWERS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Transport {
+    Agent,
+    Ssh,
+}
+
+impl Transport {
+    pub(crate) fn parse(s: &str) -> Option<Transport> {
+        match s.to_lowercase().as_str() {
+            "agent" => Some(Transport::Agent),
+            "ssh" => Some(Transport::Ssh),
+            _ => None,
+        }
+    }
+}
+
+//One --host entry: agent://host[:port], ssh://[user@]host[:port], or host[:port] with the
+//configured transport. IPv6 addresses go in brackets, [::1]:7070
+#[derive(Debug, Clone)]
+struct Target {
+    name: String,
+    transport: Transport,
+    host: String,
+    port: Option<u16>,
+}
+
+impl Target {
+    fn parse(spec: &str, default: Transport) -> Result<Target, String> {
+        let (transport, rest) = match spec.split_once("://") {
+            Some((scheme, rest)) => match Transport::parse(scheme) {
+                Some(t) => (t, rest),
+                None => return Err(format!("{}: unknown transport '{}', expected agent:// or ssh://", spec, scheme)),
+            },
+            None => (default, spec),
+        };
+
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+                let port: u16 = port.parse().map_err(|_| format!("{}: invalid port '{}'", spec, port))?;
+                (host, Some(port))
+            }
+            _ => (rest, None),
+        };
+        if host.is_empty() {
+            return Err(format!("{}: missing host name", spec));
+        }
+        //ssh would take it as an option
+        if host.starts_with('-') {
+            return Err(format!("{}: host name cannot start with '-'", spec));
+        }
+
+        return Ok(Target {
+            name: rest.to_string(),
+            transport,
+            host: host.to_string(),
+            port,
+        });
+    }
+
+    //The host without the brackets around an IPv6 address, keeping any ssh user in front
+    fn unbracketed(&self) -> String {
+        let (user, host) = match self.host.rsplit_once('@') {
+            Some((user, host)) => (format!("{}@", user), host),
+            None => (String::new(), self.host.as_str()),
+        };
+        return format!("{}{}", user, host.trim_start_matches('[').trim_end_matches(']'));
+    }
+}
+
+struct Outcome {
+    name: String,
+    result: Result<Value, String>,
+}
+
+//Sections a snapshot can carry, in the form --sections takes them
+fn section_list(config: &Config) -> String {
+    let names: Vec<String> = config
+        .sections
+        .iter()
+        .filter(|s| table::ROW_SECTIONS.contains(s) || **s == Section::Disks)
+        .map(|s| format!("{:?}", s).to_lowercase())
+        .collect();
+    return names.join(",");
+}
+
+//A response line is either a JSON snapshot or "error <reason>"
+fn parse_response(line: &str) -> Result<Value, String> {
+    let line = line.trim();
+    if let Some(reason) = line.strip_prefix("error ") {
+        return Err(reason.to_string());
+    }
+    if line.is_empty() {
+        return Err("empty response".to_string());
+    }
+    return json::parse(line).map_err(|e| e.to_string());
+}
+
+fn fetch_agent(target: &Target, sections: &str, config: &Config) -> Result<Value, String> {
+    let deadline = Instant::now() + Duration::from_secs(config.remote_timeout as u64);
+    let host = target.unbracketed();
+    let address = (host.as_str(), target.port.unwrap_or(DEFAULT_PORT))
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or("no address found")?;
+
+    let remaining = || deadline.saturating_duration_since(Instant::now()).max(Duration::from_millis(1));
+    let mut stream = TcpStream::connect_timeout(&address, remaining()).map_err(|e| e.to_string())?;
+    let io = |e: std::io::Error| match e.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => "timed out".to_string(),
+        _ => e.to_string(),
+    };
+
+    let mut request = format!("sample {}", sections);
+    if let Some(token) = &config.agent_token {
+        request.push(' ');
+        request.push_str(token);
+    }
+    stream.set_write_timeout(Some(remaining())).map_err(io)?;
+    writeln!(stream, "{}", request).map_err(io)?;
+
+    //The agent samples before it answers, so the read gets whatever time is left
+    stream.set_read_timeout(Some(remaining())).map_err(io)?;
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_RESPONSE)).read_line(&mut line).map_err(io)?;
+    return parse_response(&line);
+}
+
+//Run this tool on the host over ssh. BatchMode stops ssh asking for a password nobody will type
+fn fetch_ssh(target: &Target, sections: &str, config: &Config) -> Result<Value, String> {
+    let timeout = Duration::from_secs(config.remote_timeout as u64);
+    let mut command = Command::new("ssh");
+    command.args(["-o", "BatchMode=yes", "-o", &format!("ConnectTimeout={}", config.remote_timeout)]);
+    if let Some(port) = target.port {
+        command.args(["-p", &port.to_string()]);
+    }
+    command
+        .arg("--")
+        .arg(target.unbracketed())
+        .arg(&config.remote_command)
+        .args(["--format", "json", "--sections", sections])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = command.spawn().map_err(|e| format!("ssh: {}", e))?;
+    //Read both pipes while waiting, a chatty child would otherwise block on a full pipe
+    let mut stdout = child.stdout.take().ok_or("ssh: no output")?;
+    let mut stderr = child.stderr.take().ok_or("ssh: no output")?;
+    let out = std::thread::spawn(move || {
+        let mut text = String::new();
+        let _ = (&mut stdout).take(MAX_RESPONSE).read_to_string(&mut text);
+        text
+    });
+    let err = std::thread::spawn(move || {
+        let mut text = String::new();
+        let _ = (&mut stderr).take(MAX_RESPONSE).read_to_string(&mut text);
+        text
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if start.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err("timed out".to_string());
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(format!("ssh: {}", e)),
+        }
+    };
+    let out = out.join().unwrap_or_default();
+    let err = err.join().unwrap_or_default();
+
+    if !status.success() {
+        //The last line is the one that says what went wrong, e.g. permission denied or not found
+        let reason = err.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("").trim().to_string();
+        return Err(match reason.is_empty() {
+            true => format!("ssh exited with {}", status),
+            false => reason,
+        });
+    }
+    //The JSON line is the last one, anything before it is a login banner or note on stdout
+    let line = out.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("");
+    return parse_response(line);
+}
+
+//Query every host, at most remote_parallel at a time, and return the outcomes in --host order
+fn collect(targets: &[Target], config: &Config) -> Vec<Outcome> {
+    let sections = section_list(config);
+    let next = Arc::new(AtomicUsize::new(0));
+    let targets = Arc::new(targets.to_vec());
+    let (tx, rx) = channel();
+
+    for _ in 0..config.remote_parallel.min(targets.len()) {
+        let (next, targets, tx) = (Arc::clone(&next), Arc::clone(&targets), tx.clone());
+        let (sections, config) = (sections.clone(), config.clone());
+        std::thread::spawn(move || loop {
+            let i = next.fetch_add(1, Ordering::SeqCst);
+            let target = match targets.get(i) {
+                Some(t) => t,
+                None => return,
+            };
+            let result = match target.transport {
+                Transport::Agent => fetch_agent(target, &sections, &config),
+                Transport::Ssh => fetch_ssh(target, &sections, &config),
+            };
+            let _ = tx.send((i, Outcome { name: target.name.clone(), result }));
+        });
+    }
+    drop(tx);
+
+    let mut outcomes: Vec<(usize, Outcome)> = rx.iter().collect();
+    outcomes.sort_by_key(|(i, _)| *i);
+    return outcomes.into_iter().map(|(_, o)| o).collect();
+}
+
+fn number(snapshot: &Value, key: &str) -> Option<f64> {
+    return snapshot.get(key).and_then(|v| v.as_f64());
+}
+
+//The fullest disk, as drive and free percentage
+fn lowest_disk(snapshot: &Value) -> Option<String> {
+    let disks = snapshot.get("disks")?.as_array()?;
+    return disks
+        .iter()
+        .filter_map(|d| {
+            let (size, free) = (number(d, "size")?, number(d, "free")?);
+            if size <= 0.0 {
+                return None;
+            }
+            Some((d.get("drive").and_then(|v| v.as_str()).unwrap_or("?").to_string(), free / size * 100.0))
+        })
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(drive, pct)| format!("{} {:.1}%", drive, pct));
+}
+
+fn print_outcomes(outcomes: &[Outcome], config: &Config) {
+    let unit = config.memory_unit;
+    let missing = || "-".to_string();
+    println!(
+        "{:<24}{:<18}{:<20}{:>7}  {:>14}  {:<16}Error",
+        "Host", "Uptime", "Load 1m 5m 15m", "CPU%", format!("Mem Free {}", unit.label()), "Lowest Disk"
+    );
+
+    for o in outcomes {
+        let snapshot = match &o.result {
+            Ok(s) => s,
+            Err(e) => {
+                println!("{:<24}{:<18}{:<20}{:>7}  {:>14}  {:<16}{}", o.name, "-", "-", "-", "-", "-", e);
+                continue;
+            }
+        };
+        let load = match (number(snapshot, "load1"), number(snapshot, "load5"), number(snapshot, "load15")) {
+            (Some(a), Some(b), Some(c)) => format!("{:.2} {:.2} {:.2}", a, b, c),
+            _ => missing(),
+        };
+        println!(
+            "{:<24}{:<18}{:<20}{:>7}  {:>14}  {:<16}",
+            o.name,
+            number(snapshot, "uptime").map(|u| format_duration(u as i64)).unwrap_or_else(missing),
+            load,
+            number(snapshot, "cpu").map(|c| format!("{:.1}", c)).unwrap_or_else(missing),
+            number(snapshot, "memory_available").map(|m| unit.format_bytes(m as u64)).unwrap_or_else(missing),
+            lowest_disk(snapshot).unwrap_or_else(missing),
+        );
+    }
+}
+
+//Delimited rows: the target, every field the hosts reported in the order first seen, then the
+//error. Disk arrays do not fit a row and are left to the JSON output
+fn write_rows(outcomes: &[Outcome], columns: &mut Vec<String>, header: &mut bool, format: Format) -> Result<(), Box<dyn Error>> {
+    if !*header {
+        for o in outcomes {
+            if let Ok(Value::Object(fields)) = &o.result {
+                for (key, value) in fields {
+                    if !matches!(value, Value::Array(_) | Value::Object(_)) && !columns.contains(key) {
+                        columns.push(key.clone());
+                    }
+                }
+            }
+        }
+        let mut names = vec!["target".to_string()];
+        names.extend(columns.iter().cloned());
+        names.push("error".to_string());
+        table::write_row(&names, format)?;
+        *header = true;
+    }
+
+    for o in outcomes {
+        let mut row = vec![o.name.clone()];
+        for column in columns.iter() {
+            let value = match &o.result {
+                Ok(s) => match s.get(column) {
+                    Some(Value::String(text)) => text.clone(),
+                    Some(Value::Number(n)) => n.to_string(),
+                    Some(Value::Bool(b)) => b.to_string(),
+                    _ => String::new(),
+                },
+                Err(_) => String::new(),
+            };
+            row.push(value);
+        }
+        row.push(o.result.as_ref().err().cloned().unwrap_or_default());
+        table::write_row(&row, format)?;
+    }
+    return Ok(());
+}
+
+//One line per host: {"target":..,"error":..,"snapshot":{..}}
+fn write_json(outcomes: &[Outcome]) -> Result<(), Box<dyn Error>> {
+    let mut out = std::io::stdout();
+    for o in outcomes {
+        let (snapshot, error) = match &o.result {
+            Ok(s) => (s.clone(), Value::Null),
+            Err(e) => (Value::Null, Value::String(e.clone())),
+        };
+        let line = Value::Object(vec![
+            ("target".to_string(), Value::String(o.name.clone())),
+            ("error".to_string(), error),
+            ("snapshot".to_string(), snapshot),
+        ]);
+        writeln!(out, "{}", line)?;
+    }
+    out.flush()?;
+    return Ok(());
+}
+
+//Entry point for `--host a,b,c`: query every host in parallel and show the results together.
+//Fails when any host did, after the table, so scripts notice
+pub(crate) fn run(config: &Config) -> Result<(), Box<dyn Error>> {
+    let targets = config
+        .hosts
+        .iter()
+        .map(|h| Target::parse(h, config.remote_transport))
+        .collect::<Result<Vec<Target>, String>>()?;
+    if section_list(config).is_empty() {
+        return Err("--host supports the host, uptime, memory, load, cpu and disks sections".into());
+    }
+
+    let (mut columns, mut header) = (vec![], false);
+    loop {
+        let outcomes = collect(&targets, config);
+        match config.format {
+            Format::Text => print_outcomes(&outcomes, config),
+            Format::Json => write_json(&outcomes)?,
+            Format::Csv | Format::Tsv => write_rows(&outcomes, &mut columns, &mut header, config.format)?,
+        }
+
+        let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+        match config.watch {
+            Some(seconds) => {
+                if config.format == Format::Text {
+                    println!();
+                }
+                std::thread::sleep(Duration::from_secs(seconds as u64));
+            }
+            None if failed > 0 => return Err(format!("{} of {} hosts failed", failed, outcomes.len()).into()),
+            None => return Ok(()),
+        }
+    }
+}
+
+//Answer one request: "sample <sections> [token]" gets a snapshot line, anything else an error line
+fn answer(stream: TcpStream, config: &Config) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new((&stream).take(4096)).read_line(&mut line)?;
+
+    //The token is the rest of the line, so it may contain spaces
+    let mut parts = line.trim_end_matches(['\r', '\n']).splitn(3, ' ');
+    let response = match (parts.next(), parts.next(), parts.next()) {
+        (Some("sample"), Some(_), token) if config.agent_token.is_some() && token != config.agent_token.as_deref() => {
+            "error invalid token".to_string()
+        }
+        (Some("sample"), Some(sections), _) => {
+            let parsed: Option<Vec<Section>> = sections
+                .split(',')
+                .map(|name| Section::parse(name).filter(|s| table::ROW_SECTIONS.contains(s) || *s == Section::Disks))
+                .collect();
+            match parsed {
+                Some(list) => match table::snapshot(&list, config) {
+                    Ok(snapshot) => snapshot.to_string(),
+                    Err(e) => format!("error {}", e),
+                },
+                None => "error unsupported sections".to_string(),
+            }
+        }
+        _ => "error expected: sample SECTIONS [TOKEN]".to_string(),
+    };
+
+    let mut stream = &stream;
+    writeln!(stream, "{}", response.replace('\n', " "))?;
+    stream.flush()?;
+    return Ok(());
+}
+
+//Reply "error busy" without sampling. The request is read first, if it comes quickly, as
+//closing a socket with unread data resets it and the client may lose the reply
+fn turn_away(stream: TcpStream) {
+    let wait = Some(Duration::from_millis(100));
+    let _ = stream.set_read_timeout(wait);
+    let _ = stream.set_write_timeout(wait);
+    let _ = BufReader::new((&stream).take(4096)).read_line(&mut String::new());
+    let _ = writeln!(&stream, "error busy");
+}
+
+//Frees an answer slot when the thread holding it ends, even by a panic
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//Entry point for `uptime agent [--listen ADDR]`: serve snapshots to `--host` until stopped.
+//Each connection is answered on its own thread, as sampling takes a couple of seconds,
+//with at most MAX_ANSWERS at a time
+pub(crate) fn run_agent(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime agent [--listen ADDR:PORT]";
+    let mut listen = config.agent_listen.clone();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--listen" => listen = iter.next().ok_or(usage)?.clone(),
+            _ => return Err(usage.into()),
+        }
+    }
+
+    let listener = TcpListener::bind(&listen).map_err(|e| format!("{}: {}", listen, e))?;
+    //Non-blocking so a stop or reload is noticed between connections
+    listener.set_nonblocking(true)?;
+    println!("Agent listening on {}{}", listen, if config.agent_token.is_some() { " (token required)" } else { "" });
+
+    let active = Arc::new(AtomicUsize::new(0));
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                if active.fetch_add(1, Ordering::SeqCst) >= MAX_ANSWERS {
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    turn_away(stream);
+                    continue;
+                }
+                let slot = Slot(Arc::clone(&active));
+                let config = config.clone();
+                std::thread::spawn(move || {
+                    let _slot = slot;
+                    let _ = answer(stream, &config);
+                });
+            }
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::WouldBlock {
+                    eprintln!("Agent: {}", e);
+                }
+                if !daemon::wait(Duration::from_millis(100)) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        let t = Target::parse("[::1]:7070", Transport::Agent).unwrap();
+        assert_eq!((t.host.as_str(), t.port, t.transport), ("[::1]", Some(7070), Transport::Agent));
+        let t = Target::parse("[::1]", Transport::Agent).unwrap();
+        assert_eq!((t.host.as_str(), t.port), ("[::1]", None));
+        let t = Target::parse("ssh://user@h:22", Transport::Agent).unwrap();
+        assert_eq!((t.name.as_str(), t.host.as_str(), t.port, t.transport), ("user@h:22", "user@h", Some(22), Transport::Ssh));
+        let t = Target::parse("web1", Transport::Ssh).unwrap();
+        assert_eq!((t.host.as_str(), t.port, t.transport), ("web1", None, Transport::Ssh));
+
+        //ssh and the resolver both want IPv6 addresses without brackets
+        let t = Target::parse("ssh://admin@[fe80::1]:2222", Transport::Agent).unwrap();
+        assert_eq!((t.host.as_str(), t.unbracketed(), t.port), ("admin@[fe80::1]", "admin@fe80::1".to_string(), Some(2222)));
+        assert_eq!(Target::parse("[::1]:7070", Transport::Agent).unwrap().unbracketed(), "::1");
+        assert_eq!(Target::parse("user@web1", Transport::Ssh).unwrap().unbracketed(), "user@web1");
+
+        assert!(Target::parse("ftp://web1", Transport::Agent).is_err());
+        assert!(Target::parse("web1:ssh", Transport::Agent).is_err());
+        assert!(Target::parse("agent://:7070", Transport::Agent).is_err());
+        assert!(Target::parse("-oProxyCommand=sh", Transport::Ssh).is_err());
+        assert!(Target::parse("ssh://-oProxyCommand=sh@h", Transport::Agent).is_err());
+    }
+
+    //An agent on a loopback port that answers the given number of requests
+    fn agent(config: Config, requests: usize) -> (Target, std::thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = Target::parse(&listener.local_addr().unwrap().to_string(), Transport::Agent).unwrap();
+        let handle = std::thread::spawn(move || {
+            for _ in 0..requests {
+                let (stream, _) = listener.accept().unwrap();
+                answer(stream, &config).unwrap();
+            }
+        });
+        return (target, handle);
+    }
+
+    #[test]
+    fn agent_answers_with_the_right_token() {
+        let token = |t: &str| Config { agent_token: Some(t.to_string()), ..Config::default() };
+        let agent_config = token("s3cret phrase");
+        let (target, handle) = agent(agent_config.clone(), 4);
+
+        assert_eq!(fetch_agent(&target, "host", &Config::default()).unwrap_err(), "invalid token");
+        let wrong = token("s3cret");
+        assert_eq!(fetch_agent(&target, "host", &wrong).unwrap_err(), "invalid token");
+
+        assert_eq!(fetch_agent(&target, "host,users", &agent_config).unwrap_err(), "unsupported sections");
+        let snapshot = fetch_agent(&target, "host", &agent_config).unwrap();
+        assert!(snapshot.get("time").and_then(|v| v.as_str()).is_some());
+        assert!(snapshot.get("host").and_then(|v| v.as_str()).is_some());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn agent_without_a_token() {
+        let (target, handle) = agent(Config::default(), 1);
+        //A client token is ignored when the agent does not ask for one
+        let client = Config { agent_token: Some("unused".to_string()), ..Config::default() };
+        assert_eq!(fetch_agent(&target, "nothing", &client).unwrap_err(), "unsupported sections");
+        handle.join().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = Target::parse(&listener.local_addr().unwrap().to_string(), Transport::Agent).unwrap();
+        let handle = std::thread::spawn(move || turn_away(listener.accept().unwrap().0));
+        assert_eq!(fetch_agent(&target, "host", &Config::default()).unwrap_err(), "busy");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn responses() {
+        assert_eq!(parse_response("error invalid token\r\n").unwrap_err(), "invalid token");
+        assert_eq!(parse_response("\n").unwrap_err(), "empty response");
+        assert!(parse_response("{\"host\":").is_err());
+        assert_eq!(parse_response("{\"load1\":0.5}\n").unwrap().get("load1"), Some(&Value::Number(0.5)));
+    }
+}
@@ -0,0 +1,631 @@
+use crate::config::Config;
+use crate::forecast::median;
+use crate::history::format_duration;
+use crate::json::{self, Value};
+
+use chrono::DateTime;
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+//A robust z-score above this is an outlier (Iglewicz and Hoaglin)
+const OUTLIER_SCORE: f64 = 3.5;
+
+//Fewer hosts than this have no meaningful "normal" to stand out from
+const OUTLIER_MIN_HOSTS: usize = 5;
+
+//A host whose latest snapshot is this much older than the newest one has stopped reporting
+const STALE_SECONDS: i64 = 86400;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Style {
+    Text,
+    Markdown,
+    Html,
+}
+
+impl Style {
+    pub(crate) fn parse(s: &str) -> Option<Style> {
+        match s.to_lowercase().as_str() {
+            "text" => Some(Style::Text),
+            "markdown" | "md" => Some(Style::Markdown),
+            "html" => Some(Style::Html),
+            _ => None,
+        }
+    }
+}
+
+//A host's fullest disk, sizes in bytes
+struct Disk {
+    drive: String,
+    free_pct: f64,
+    free: f64,
+    size: f64,
+}
+
+//The latest snapshot of one host and the figures the report ranks hosts by
+struct Host {
+    name: String,
+    time: String,
+    //Unix seconds of time, None when it has no UTC offset to place it by
+    at: Option<i64>,
+    snapshot: Value,
+}
+
+impl Host {
+    fn number(&self, key: &str) -> Option<f64> {
+        return self.snapshot.get(key).and_then(|v| v.as_f64());
+    }
+
+    fn text(&self, key: &str) -> Option<&str> {
+        return self.snapshot.get(key).and_then(|v| v.as_str());
+    }
+
+    //The disk with the least free space relative to its size
+    fn lowest_disk(&self) -> Option<Disk> {
+        let disks = self.snapshot.get("disks")?.as_array()?;
+        return disks
+            .iter()
+            .filter_map(|d| {
+                let size = d.get("size")?.as_f64().filter(|s| *s > 0.0)?;
+                let free = d.get("free")?.as_f64()?;
+                let drive = d.get("drive").and_then(|v| v.as_str()).unwrap_or("?").to_string();
+                Some(Disk { drive, free_pct: free / size * 100.0, free, size })
+            })
+            .min_by(|a, b| a.free_pct.partial_cmp(&b.free_pct).unwrap_or(std::cmp::Ordering::Equal));
+    }
+
+    //Load per CPU when the snapshot knows the CPUs, so hosts of different sizes compare fairly
+    fn load_per_cpu(&self) -> Option<f64> {
+        let load = self.number("load1")?;
+        return match self.number("cores").filter(|c| *c > 0.0) {
+            Some(cores) => Some(load / cores),
+            None => Some(load),
+        };
+    }
+
+    fn memory_used_pct(&self) -> Option<f64> {
+        let installed = self.number("memory_installed").filter(|i| *i > 0.0)?;
+        let available = self.number("memory_available")?;
+        return Some((installed - available).max(0.0) / installed * 100.0);
+    }
+
+    fn page_used_pct(&self) -> Option<f64> {
+        let total = self.number("page_total").filter(|t| *t > 0.0)?;
+        return Some(self.number("page_used")? / total * 100.0);
+    }
+}
+
+//Everything read from the snapshot directory
+struct Fleet {
+    hosts: Vec<Host>,
+    files: usize,
+    snapshots: usize,
+    //Targets that only ever reported an error, with the latest error
+    failed: Vec<(String, String)>,
+    skipped: usize,
+}
+
+//Snapshot times are RFC 3339 with the writer's UTC offset, so hosts in any timezone compare.
+//Older snapshots with local times and no offset cannot be placed, so they never count as stale
+fn parse_time(time: &str) -> Option<i64> {
+    return DateTime::parse_from_rfc3339(time).ok().map(|t| t.timestamp());
+}
+
+//Snapshots in a file: `--format json` lines, `--host --format json` lines carrying a target and
+//error, or a single pretty printed object or array of either
+fn file_entries(text: &str) -> (Vec<Value>, usize) {
+    match json::parse(text) {
+        Ok(Value::Array(items)) => return (items, 0),
+        Ok(value) => return (vec![value], 0),
+        Err(_) => {}
+    }
+    let (mut entries, mut skipped) = (vec![], 0);
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        match json::parse(line) {
+            Ok(value) => entries.push(value),
+            Err(_) => skipped += 1,
+        }
+    }
+    return (entries, skipped);
+}
+
+fn load_fleet(dir: &Path) -> Result<Fleet, Box<dyn Error>> {
+    let mut fleet = Fleet { hosts: vec![], files: 0, snapshots: 0, failed: vec![], skipped: 0 };
+    let mut errors: Vec<(String, String)> = vec![];
+    let mut reached: Vec<String> = vec![];
+
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("json") | Some("jsonl") | Some("ndjson")))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("Skipped {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        fleet.files += 1;
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let (entries, skipped) = file_entries(&text);
+        fleet.skipped += skipped;
+
+        for entry in entries {
+            let target = entry.get("target").and_then(|t| t.as_str()).map(|t| t.to_string());
+            let snapshot = match entry.get("snapshot") {
+                Some(Value::Object(_)) => entry.get("snapshot").cloned().unwrap_or(Value::Null),
+                Some(_) => {
+                    let error = entry.get("error").and_then(|e| e.as_str()).unwrap_or("no snapshot");
+                    errors.push((target.unwrap_or_else(|| stem.clone()), error.to_string()));
+                    continue;
+                }
+                None => entry,
+            };
+            let time = match snapshot.get("time").and_then(|t| t.as_str()) {
+                Some(t) => t.to_string(),
+                None => {
+                    fleet.skipped += 1;
+                    continue;
+                }
+            };
+            fleet.snapshots += 1;
+            if let Some(t) = &target {
+                reached.push(t.clone());
+            }
+
+            //The host's own name first, as one host may be reached under several targets
+            let name = snapshot.get("host").and_then(|h| h.as_str()).map(|h| h.to_string()).or(target).unwrap_or_else(|| stem.clone());
+            let at = parse_time(&time);
+            match fleet.hosts.iter_mut().find(|h| h.name.eq_ignore_ascii_case(&name)) {
+                //Placed times win over ones without an offset, which can still sort among themselves as text
+                Some(host) if (host.at, &host.time) < (at, &time) => {
+                    host.time = time;
+                    host.at = at;
+                    host.snapshot = snapshot;
+                }
+                Some(_) => {}
+                None => fleet.hosts.push(Host { name, time, at, snapshot }),
+            }
+        }
+    }
+
+    for (target, error) in errors.into_iter().filter(|(t, _)| !reached.contains(t)) {
+        match fleet.failed.iter_mut().find(|(t, _)| *t == target) {
+            Some(failed) => failed.1 = error,
+            None => fleet.failed.push((target, error)),
+        }
+    }
+    return Ok(fleet);
+}
+
+struct Table {
+    title: String,
+    note: String,
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+//Hosts far from the fleet median by the modified z-score, 0.6745 (x - median) / MAD. The median
+//absolute deviation is not pulled along by the outliers themselves, unlike a standard deviation
+fn outliers(hosts: &[Host], metric: &str, value: impl Fn(&Host) -> Option<f64>, show: impl Fn(f64) -> String) -> Vec<Vec<String>> {
+    let values: Vec<(&Host, f64)> = hosts.iter().filter_map(|h| value(h).map(|v| (h, v))).collect();
+    if values.len() < OUTLIER_MIN_HOSTS {
+        return vec![];
+    }
+    let center = median(&mut values.iter().map(|(_, v)| *v).collect::<Vec<f64>>());
+    let mad = median(&mut values.iter().map(|(_, v)| (v - center).abs()).collect::<Vec<f64>>());
+    //Most hosts identical: fall back to the mean absolute deviation, scaled to match
+    let spread = match mad {
+        m if m > 0.0 => m / 0.6745,
+        _ => values.iter().map(|(_, v)| (v - center).abs()).sum::<f64>() / values.len() as f64 * 1.253314,
+    };
+    if spread <= 0.0 {
+        return vec![];
+    }
+
+    let mut found: Vec<(f64, Vec<String>)> = values
+        .iter()
+        .map(|(h, v)| ((v - center) / spread, h, v))
+        .filter(|(score, _, _)| score.abs() > OUTLIER_SCORE)
+        .map(|(score, h, v)| {
+            let direction = if score > 0.0 { "high" } else { "low" };
+            (score.abs(), vec![h.name.clone(), metric.to_string(), show(*v), show(center), format!("{:.1} {}", score.abs(), direction)])
+        })
+        .collect();
+    found.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    return found.into_iter().map(|(_, row)| row).collect();
+}
+
+fn build(fleet: &Fleet, top: usize, reboot_days: u32, config: &Config) -> (Vec<String>, Vec<Table>) {
+    let hosts = &fleet.hosts;
+    let (disk, memory) = (config.disk_unit, config.memory_unit);
+    let pct = |v: f64| format!("{:.1}%", v);
+    let mut summary = vec![
+        format!("Hosts: {}", hosts.len()),
+        format!("Snapshots: {} from {} files", fleet.snapshots, fleet.files),
+    ];
+    if fleet.skipped > 0 {
+        summary.push(format!("Unreadable entries: {}", fleet.skipped));
+    }
+
+    let newest = hosts.iter().filter_map(|h| h.at).max();
+    if let Some(newest) = newest {
+        let mut stale: Vec<&Host> = hosts.iter().filter(|h| h.at.is_some_and(|t| newest - t > STALE_SECONDS)).collect();
+        stale.sort_by_key(|h| h.at);
+        if !stale.is_empty() {
+            let names: Vec<String> = stale.iter().map(|h| format!("{} ({})", h.name, h.time)).collect();
+            summary.push(format!("Not reporting for over a day: {}", names.join(", ")));
+        }
+    }
+
+    let mut tables = vec![];
+
+    let mut by_uptime: Vec<(&Host, f64)> = hosts.iter().filter_map(|h| h.number("uptime").map(|u| (h, u))).collect();
+    by_uptime.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    let due = by_uptime.iter().filter(|(_, u)| *u >= reboot_days as f64 * 86400.0).count();
+    tables.push(Table {
+        title: "Longest uptime".to_string(),
+        note: format!("{} of {} hosts have not rebooted in {} days or more, so may be missing patches that need one", due, by_uptime.len(), reboot_days),
+        headers: vec!["Host", "Uptime", "Boot Time", "Snapshot", "Reboot Due"],
+        rows: by_uptime
+            .iter()
+            .take(top)
+            .map(|(h, u)| {
+                vec![
+                    h.name.clone(),
+                    format_duration(*u as i64),
+                    h.text("boot_time").unwrap_or("-").to_string(),
+                    h.time.clone(),
+                    if *u >= reboot_days as f64 * 86400.0 { "yes".to_string() } else { String::new() },
+                ]
+            })
+            .collect(),
+    });
+
+    let mut by_disk: Vec<(&Host, Disk)> = hosts.iter().filter_map(|h| h.lowest_disk().map(|d| (h, d))).collect();
+    by_disk.sort_by(|a, b| a.1.free_pct.partial_cmp(&b.1.free_pct).unwrap_or(std::cmp::Ordering::Equal));
+    tables.push(Table {
+        title: "Lowest free disk".to_string(),
+        note: "The fullest disk of each host".to_string(),
+        headers: vec!["Host", "Drive", "Free %", "Free", "Size"],
+        rows: by_disk
+            .iter()
+            .take(top)
+            .map(|(h, d)| {
+                vec![
+                    h.name.clone(),
+                    d.drive.clone(),
+                    pct(d.free_pct),
+                    format!("{} {}", disk.format_bytes(d.free as u64), disk.label()),
+                    format!("{} {}", disk.format_bytes(d.size as u64), disk.label()),
+                ]
+            })
+            .collect(),
+    });
+
+    let mut by_load: Vec<(&Host, f64)> = hosts.iter().filter_map(|h| h.load_per_cpu().map(|l| (h, l))).collect();
+    by_load.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    let figure = |h: &Host, key: &str| h.number(key).map(|v| format!("{:.2}", v)).unwrap_or_else(|| "-".to_string());
+    tables.push(Table {
+        title: "Highest load".to_string(),
+        note: "Ranked by 1 minute load per CPU, or the raw load when the CPU count is not in the snapshot".to_string(),
+        headers: vec!["Host", "Load 1m", "5m", "15m", "Per CPU", "CPU %"],
+        rows: by_load
+            .iter()
+            .take(top)
+            .map(|(h, per_cpu)| {
+                vec![
+                    h.name.clone(),
+                    figure(h, "load1"),
+                    figure(h, "load5"),
+                    figure(h, "load15"),
+                    format!("{:.2}", per_cpu),
+                    h.number("cpu").map(pct).unwrap_or_else(|| "-".to_string()),
+                ]
+            })
+            .collect(),
+    });
+
+    let mut by_memory: Vec<(&Host, f64)> = hosts.iter().filter_map(|h| h.memory_used_pct().map(|m| (h, m))).collect();
+    by_memory.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    let bytes = |v: Option<f64>| v.map(|b| format!("{} {}", memory.format_bytes(b as u64), memory.label())).unwrap_or_else(|| "-".to_string());
+    tables.push(Table {
+        title: "Memory pressure".to_string(),
+        note: "Ranked by memory in use, with page file use alongside as it grows once memory runs short".to_string(),
+        headers: vec!["Host", "Used %", "Available", "Installed", "Page Used %"],
+        rows: by_memory
+            .iter()
+            .take(top)
+            .map(|(h, used)| {
+                vec![
+                    h.name.clone(),
+                    pct(*used),
+                    bytes(h.number("memory_available")),
+                    bytes(h.number("memory_installed")),
+                    h.page_used_pct().map(pct).unwrap_or_else(|| "-".to_string()),
+                ]
+            })
+            .collect(),
+    });
+
+    let mut rows = vec![];
+    rows.extend(outliers(hosts, "load per CPU", |h| h.load_per_cpu(), |v| format!("{:.2}", v)));
+    rows.extend(outliers(hosts, "CPU %", |h| h.number("cpu"), pct));
+    rows.extend(outliers(hosts, "memory used %", |h| h.memory_used_pct(), pct));
+    rows.extend(outliers(hosts, "lowest disk free %", |h| h.lowest_disk().map(|d| d.free_pct), pct));
+    rows.extend(outliers(hosts, "uptime", |h| h.number("uptime"), |v| format_duration(v as i64)));
+    tables.push(Table {
+        title: "Outliers".to_string(),
+        note: format!(
+            "Hosts more than {} robust deviations from the fleet median, for metrics at least {} hosts report",
+            OUTLIER_SCORE, OUTLIER_MIN_HOSTS
+        ),
+        headers: vec!["Host", "Metric", "Value", "Fleet Median", "Score"],
+        rows,
+    });
+
+    if !fleet.failed.is_empty() {
+        tables.push(Table {
+            title: "Unreachable".to_string(),
+            note: "Targets with only failed queries in the snapshots".to_string(),
+            headers: vec!["Target", "Error"],
+            rows: fleet.failed.iter().map(|(t, e)| vec![t.clone(), e.clone()]).collect(),
+        });
+    }
+
+    return (summary, tables);
+}
+
+fn render_text(title: &str, summary: &[String], tables: &[Table]) -> String {
+    let mut out = format!("{}\n{}\n", title, summary.join(" | "));
+    for t in tables {
+        out.push_str(&format!("\n{}\n{}\n", t.title, t.note));
+        if t.rows.is_empty() {
+            out.push_str("None\n");
+            continue;
+        }
+        let widths: Vec<usize> = (0..t.headers.len())
+            .map(|i| t.rows.iter().map(|r| r[i].chars().count()).chain(std::iter::once(t.headers[i].len())).max().unwrap_or(0))
+            .collect();
+        let line = |cells: Vec<&str>| {
+            let padded: Vec<String> = cells.iter().zip(&widths).map(|(c, w)| format!("{:<w$}", c, w = w)).collect();
+            format!("{}\n", padded.join("  ").trim_end())
+        };
+        out.push_str(&line(t.headers.clone()));
+        for row in &t.rows {
+            out.push_str(&line(row.iter().map(|c| c.as_str()).collect()));
+        }
+    }
+    return out;
+}
+
+fn render_markdown(title: &str, summary: &[String], tables: &[Table]) -> String {
+    //Pipes would split the cell and markdown renderers pass tags through
+    let cell = |c: &str| c.replace('|', "\\|").replace('<', "&lt;");
+    let mut out = format!("# {}\n\n", title);
+    for s in summary {
+        out.push_str(&format!("- {}\n", cell(s)));
+    }
+    for t in tables {
+        out.push_str(&format!("\n## {}\n\n{}\n\n", t.title, t.note));
+        if t.rows.is_empty() {
+            out.push_str("None\n");
+            continue;
+        }
+        out.push_str(&format!("| {} |\n", t.headers.join(" | ")));
+        out.push_str(&format!("|{}\n", "---|".repeat(t.headers.len())));
+        for row in &t.rows {
+            let cells: Vec<String> = row.iter().map(|c| cell(c)).collect();
+            out.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+    }
+    return out;
+}
+
+fn escape_html(s: &str) -> String {
+    return s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+}
+
+//A single self contained page, so it can be mailed or dropped on a file share as it is
+fn render_html(title: &str, summary: &[String], tables: &[Table]) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{t}</title>\n<style>\n\
+         body {{ font-family: sans-serif; margin: 2em; }}\n\
+         table {{ border-collapse: collapse; margin-bottom: 1em; }}\n\
+         th, td {{ border: 1px solid #ccc; padding: 4px 10px; text-align: left; }}\n\
+         th {{ background: #f0f0f0; }}\n\
+         p.note {{ color: #555; }}\n\
+         </style>\n</head>\n<body>\n<h1>{t}</h1>\n<ul>\n",
+        t = escape_html(title)
+    );
+    for s in summary {
+        out.push_str(&format!("<li>{}</li>\n", escape_html(s)));
+    }
+    out.push_str("</ul>\n");
+    for t in tables {
+        out.push_str(&format!("<h2>{}</h2>\n<p class=\"note\">{}</p>\n", escape_html(&t.title), escape_html(&t.note)));
+        if t.rows.is_empty() {
+            out.push_str("<p>None</p>\n");
+            continue;
+        }
+        out.push_str("<table>\n<tr>");
+        for h in &t.headers {
+            out.push_str(&format!("<th>{}</th>", escape_html(h)));
+        }
+        out.push_str("</tr>\n");
+        for row in &t.rows {
+            out.push_str("<tr>");
+            for c in row {
+                out.push_str(&format!("<td>{}</td>", escape_html(c)));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    return out;
+}
+
+//Entry point for `uptime report [DIR] [--as text|markdown|html] [--top N] [--reboot-days N] [--out FILE]`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime report [DIR] [--as text|markdown|html] [--top N] [--reboot-days N] [--out FILE]";
+    let mut dir = config.report_dir.clone();
+    let (mut style, mut top, mut reboot_days) = (config.report_style, config.report_top, config.report_reboot_days);
+    let mut out: Option<PathBuf> = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--as" => style = iter.next().and_then(|v| Style::parse(v)).ok_or(usage)?,
+            "--top" => top = iter.next().and_then(|v| v.parse().ok()).filter(|n| *n > 0).ok_or(usage)?,
+            "--reboot-days" => reboot_days = iter.next().and_then(|v| v.parse().ok()).filter(|n| *n > 0).ok_or(usage)?,
+            "--out" => out = Some(PathBuf::from(iter.next().ok_or(usage)?)),
+            flag if flag.starts_with("--") => return Err(usage.into()),
+            path => dir = Some(PathBuf::from(path)),
+        }
+    }
+
+    let dir = dir.ok_or(usage)?;
+    let fleet = load_fleet(&dir)?;
+    if fleet.hosts.is_empty() && fleet.failed.is_empty() {
+        return Err(format!("No snapshots in {}, collect them with `uptime --format json` or `uptime --host ... --format json`", dir.display()).into());
+    }
+
+    let (summary, tables) = build(&fleet, top, reboot_days, config);
+    let title = format!("Fleet report: {}", dir.display());
+    let text = match style {
+        Style::Text => render_text(&title, &summary, &tables),
+        Style::Markdown => render_markdown(&title, &summary, &tables),
+        Style::Html => render_html(&title, &summary, &tables),
+    };
+
+    match out {
+        Some(path) => {
+            std::fs::write(&path, text).map_err(|e| format!("{}: {}", path.display(), e))?;
+            println!("Report written to {}", path.display());
+        }
+        None => print!("{}", text),
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A scratch snapshot directory per test, removed when the test finishes
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Dir {
+            let path = std::env::temp_dir().join(format!("uptime-report-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            for (file, text) in files {
+                std::fs::write(path.join(file), text).unwrap();
+            }
+            return Dir(path);
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn snapshot(host: &str, time: &str, load: f64) -> String {
+        format!(r#"{{"time":"{}","host":"{}","uptime":3600,"load1":{},"cores":1}}"#, time, host, load)
+    }
+
+    fn summary(fleet: &Fleet) -> Vec<String> {
+        return build(fleet, 10, 30, &Config::default()).0;
+    }
+
+    #[test]
+    fn times_are_compared_by_utc_offset() {
+        assert_eq!(parse_time("2026-10-19T10:00:00+02:00"), parse_time("2026-10-19T08:00:00Z"));
+        assert_eq!(parse_time("2026-10-19 10:00:00"), None);
+
+        //25 hours apart on the wall clock but an hour apart in UTC
+        let east = snapshot("east", "2026-10-20T11:00:00+14:00", 1.0);
+        let west = snapshot("west", "2026-10-19T10:00:00-10:00", 1.0);
+        let dir = Dir::new("offsets", &[("east.json", &east), ("west.json", &west)]);
+        let fleet = load_fleet(&dir.0).unwrap();
+        assert_eq!(fleet.hosts.len(), 2);
+        assert!(!summary(&fleet).iter().any(|s| s.starts_with("Not reporting")));
+    }
+
+    #[test]
+    fn stale_hosts_are_listed() {
+        let fresh = snapshot("fresh", "2026-10-19T10:00:00+02:00", 1.0);
+        let old = snapshot("old", "2026-10-17T10:00:00+02:00", 1.0);
+        let legacy = snapshot("legacy", "2026-10-01 10:00:00", 1.0);
+        let dir = Dir::new("stale", &[("fleet.jsonl", &format!("{}\n{}\n{}\n", fresh, old, legacy))]);
+        let fleet = load_fleet(&dir.0).unwrap();
+        let summary = summary(&fleet);
+        assert!(summary.contains(&"Not reporting for over a day: old (2026-10-17T10:00:00+02:00)".to_string()));
+    }
+
+    #[test]
+    fn latest_snapshot_of_a_host_wins() {
+        //The second line is later as text but earlier in UTC
+        let later = snapshot("web1", "2026-10-19T09:00:00+00:00", 2.0);
+        let earlier = snapshot("WEB1", "2026-10-19T10:00:00+02:00", 1.0);
+        let dir = Dir::new("latest", &[("web1.jsonl", &format!("{}\n{}\nnot json\n", later, earlier))]);
+        let fleet = load_fleet(&dir.0).unwrap();
+        assert_eq!((fleet.hosts.len(), fleet.snapshots, fleet.skipped), (1, 2, 1));
+        assert_eq!(fleet.hosts[0].number("load1"), Some(2.0));
+    }
+
+    #[test]
+    fn failed_targets_are_only_unreachable_without_a_snapshot() {
+        let answered = format!(r#"{{"target":"web1","error":null,"snapshot":{}}}"#, snapshot("WEB1", "2026-10-19T10:00:00Z", 1.0));
+        let lines = format!(
+            "{}\n{}\n{}\n",
+            r#"{"target":"db1","error":"connection refused","snapshot":null}"#,
+            r#"{"target":"web1","error":"timed out","snapshot":null}"#,
+            answered
+        );
+        let dir = Dir::new("failed", &[("hosts.json", &lines)]);
+        let fleet = load_fleet(&dir.0).unwrap();
+        assert_eq!(fleet.failed, vec![("db1".to_string(), "connection refused".to_string())]);
+        assert_eq!(fleet.hosts[0].name, "WEB1");
+    }
+
+    #[test]
+    fn outlier_stands_out_from_the_fleet() {
+        let hosts: Vec<Host> = [0.5, 0.6, 0.55, 0.5, 0.65, 4.0]
+            .iter()
+            .enumerate()
+            .map(|(i, load)| Host {
+                name: format!("h{}", i),
+                time: String::new(),
+                at: None,
+                snapshot: json::parse(&snapshot(&format!("h{}", i), "", *load)).unwrap(),
+            })
+            .collect();
+        let rows = outliers(&hosts, "load per CPU", |h| h.load_per_cpu(), |v| format!("{:.2}", v));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][0], "h5");
+        assert!(rows[0][4].ends_with("high"));
+        assert!(outliers(&hosts[..4], "load per CPU", |h| h.load_per_cpu(), |v| format!("{:.2}", v)).is_empty());
+    }
+
+    #[test]
+    fn cells_are_escaped() {
+        let tables = vec![Table {
+            title: "T".to_string(),
+            note: String::new(),
+            headers: vec!["Host"],
+            rows: vec![vec!["a|b <i>".to_string()]],
+        }];
+        assert!(render_markdown("R", &[], &tables).contains("| a\\|b &lt;i> |"));
+        assert!(render_html("R", &[], &tables).contains("<td>a|b &lt;i&gt;</td>"));
+    }
+}
@@ -0,0 +1,477 @@
+use crate::config::Config;
+use crate::history::format_time;
+
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//A service running for less than this, but started well after boot, counts as restarted
+const RECENT_SECONDS: i64 = 3600;
+//Automatic services may still be starting this long after boot
+const BOOT_GRACE_SECONDS: i64 = 300;
+
+#[derive(Default, Debug, Clone)]
+pub struct Service {
+    pub name: String,
+    pub display_name: String,
+    pub start_type: StartType,
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    //systemd NRestarts, the Windows SCM does not count restarts
+    pub restarts: Option<u32>,
+    pub exit_code: u32,
+    //Unix timestamp in seconds the service last started, 0 if unknown
+    pub started: i64,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum StartType {
+    #[default]
+    Other,
+    Automatic,
+    DelayedAutomatic,
+    //Automatic (Trigger Start), only runs when a device or network event needs it
+    TriggerAutomatic,
+    Manual,
+    Disabled,
+    //systemd units with no [Install] section, started only as a dependency
+    #[cfg(not(windows))]
+    Static,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum ServiceState {
+    #[default]
+    Other,
+    Running,
+    Starting,
+    Stopping,
+    Stopped,
+    Paused,
+    Failed,
+    //A oneshot unit that ran to completion
+    #[cfg(not(windows))]
+    Exited,
+}
+
+impl StartType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StartType::Other => "other",
+            StartType::Automatic => "auto",
+            StartType::DelayedAutomatic => "auto (delayed)",
+            StartType::TriggerAutomatic => "auto (trigger)",
+            StartType::Manual => "manual",
+            StartType::Disabled => "disabled",
+            #[cfg(not(windows))]
+            StartType::Static => "static",
+        }
+    }
+
+    pub fn is_automatic(&self) -> bool {
+        matches!(self, StartType::Automatic | StartType::DelayedAutomatic)
+    }
+}
+
+impl ServiceState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServiceState::Other => "other",
+            ServiceState::Running => "running",
+            ServiceState::Starting => "starting",
+            ServiceState::Stopping => "stopping",
+            ServiceState::Stopped => "stopped",
+            ServiceState::Paused => "paused",
+            ServiceState::Failed => "failed",
+            #[cfg(not(windows))]
+            ServiceState::Exited => "exited",
+        }
+    }
+}
+
+impl Service {
+    pub fn failed(&self) -> bool {
+        self.state == ServiceState::Failed
+    }
+
+    pub fn stopped_automatic(&self) -> bool {
+        self.start_type.is_automatic() && matches!(self.state, ServiceState::Stopped | ServiceState::Failed)
+    }
+
+    pub fn recently_restarted(&self, boot_time: i64, now: i64) -> bool {
+        if self.restarts.unwrap_or(0) > 0 {
+            return true;
+        }
+        self.state == ServiceState::Running
+            && self.start_type.is_automatic()
+            && self.started > boot_time + BOOT_GRACE_SECONDS
+            && now - self.started < RECENT_SECONDS
+    }
+
+    //Why the service is listed, empty when there is nothing wrong with it
+    pub fn issue(&self, boot_time: i64, now: i64) -> &'static str {
+        if self.failed() {
+            "failed"
+        } else if self.stopped_automatic() {
+            "not running"
+        } else if self.recently_restarted(boot_time, now) {
+            "restarted"
+        } else {
+            ""
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(windows)]
+unsafe fn wide_ptr_to_string(ptr: *const u16) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len))
+}
+
+//QueryServiceConfig(2)W style call, asked once for the size and again for the data
+#[cfg(windows)]
+unsafe fn query_config(query: impl Fn(*mut u8, u32, &mut u32) -> i32) -> Option<Vec<u64>> {
+    let mut needed = 0_u32;
+    let mut buffer: Vec<u64> = vec![0; 1];
+    if query(buffer.as_mut_ptr() as _, 8, &mut needed) != 0 {
+        return Some(buffer);
+    }
+    buffer.resize(needed as usize / 8 + 1, 0);
+    if query(buffer.as_mut_ptr() as _, (buffer.len() * 8) as u32, &mut needed) != 0 {
+        return Some(buffer);
+    }
+    return None;
+}
+
+#[cfg(windows)]
+unsafe fn start_type(scm: winapi::um::winsvc::SC_HANDLE, name: *const u16) -> StartType {
+    use winapi::um::winnt::{SERVICE_AUTO_START, SERVICE_BOOT_START, SERVICE_DEMAND_START, SERVICE_DISABLED, SERVICE_SYSTEM_START};
+    use winapi::um::winsvc::{
+        CloseServiceHandle, OpenServiceW, QueryServiceConfig2W, QueryServiceConfigW, QUERY_SERVICE_CONFIGW,
+        SERVICE_CONFIG_DELAYED_AUTO_START_INFO, SERVICE_CONFIG_TRIGGER_INFO, SERVICE_QUERY_CONFIG,
+    };
+
+    let service = OpenServiceW(scm, name, SERVICE_QUERY_CONFIG);
+    if service.is_null() {
+        return StartType::Other;
+    }
+
+    let config = query_config(|buf, len, needed| QueryServiceConfigW(service, buf as _, len, needed));
+    let mut start = match config.map(|c| (*(c.as_ptr() as *const QUERY_SERVICE_CONFIGW)).dwStartType) {
+        Some(SERVICE_AUTO_START) => StartType::Automatic,
+        Some(SERVICE_DEMAND_START) => StartType::Manual,
+        Some(SERVICE_DISABLED) => StartType::Disabled,
+        Some(SERVICE_BOOT_START) | Some(SERVICE_SYSTEM_START) => StartType::Automatic,
+        _ => StartType::Other,
+    };
+
+    if start == StartType::Automatic {
+        //SERVICE_TRIGGER_INFO and SERVICE_DELAYED_AUTO_START_INFO both start with a 32 bit count/flag
+        let first = |level: u32| {
+            query_config(|buf, len, needed| QueryServiceConfig2W(service, level, buf, len, needed))
+                .map(|b| *(b.as_ptr() as *const u32))
+                .unwrap_or(0)
+        };
+        if first(SERVICE_CONFIG_TRIGGER_INFO) > 0 {
+            start = StartType::TriggerAutomatic;
+        } else if first(SERVICE_CONFIG_DELAYED_AUTO_START_INFO) != 0 {
+            start = StartType::DelayedAutomatic;
+        }
+    }
+
+    CloseServiceHandle(service);
+
+    return start;
+}
+
+//Every Win32 service from the service control manager
+#[cfg(windows)]
+pub(crate) fn snapshot() -> Vec<Service> {
+    use crate::procs;
+    use std::collections::HashMap;
+    use winapi::shared::winerror::{ERROR_MORE_DATA, ERROR_SERVICE_NEVER_STARTED, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR};
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::winnt::SERVICE_WIN32;
+    use winapi::um::winsvc::{
+        CloseServiceHandle, EnumServicesStatusExW, OpenSCManagerW, ENUM_SERVICE_STATUS_PROCESSW, SC_ENUM_PROCESS_INFO,
+        SC_MANAGER_CONNECT, SC_MANAGER_ENUMERATE_SERVICE, SERVICE_CONTINUE_PENDING, SERVICE_PAUSED, SERVICE_PAUSE_PENDING,
+        SERVICE_RUNNING, SERVICE_START_PENDING, SERVICE_STATE_ALL, SERVICE_STOPPED, SERVICE_STOP_PENDING,
+    };
+
+    let mut services: Vec<Service> = vec![];
+    let started: HashMap<u32, i64> = procs::process_table().into_iter().map(|p| (p.pid, p.start_time)).collect();
+
+    unsafe {
+        let scm = OpenSCManagerW(std::ptr::null(), std::ptr::null(), SC_MANAGER_CONNECT | SC_MANAGER_ENUMERATE_SERVICE);
+        if scm.is_null() {
+            return services;
+        }
+
+        //u64 keeps the entries aligned
+        let mut buffer: Vec<u64> = vec![0; 32 * 1024];
+        let mut count = 0_u32;
+        loop {
+            let (mut needed, mut resume) = (0_u32, 0_u32);
+            let ok = EnumServicesStatusExW(
+                scm,
+                SC_ENUM_PROCESS_INFO,
+                SERVICE_WIN32,
+                SERVICE_STATE_ALL,
+                buffer.as_mut_ptr() as _,
+                (buffer.len() * 8) as u32,
+                &mut needed,
+                &mut count,
+                &mut resume,
+                std::ptr::null(),
+            );
+            if ok != 0 {
+                break;
+            }
+            if GetLastError() != ERROR_MORE_DATA {
+                CloseServiceHandle(scm);
+                return services;
+            }
+            //needed only covers the entries that did not fit, start again with room for all of them
+            buffer.resize(buffer.len() + needed as usize / 8 + 1, 0);
+        }
+
+        let entries = std::slice::from_raw_parts(buffer.as_ptr() as *const ENUM_SERVICE_STATUS_PROCESSW, count as usize);
+        for e in entries {
+            let status = &e.ServiceStatusProcess;
+            let exit_code = match status.dwWin32ExitCode {
+                ERROR_SERVICE_SPECIFIC_ERROR => status.dwServiceSpecificExitCode,
+                ERROR_SERVICE_NEVER_STARTED => NO_ERROR,
+                code => code,
+            };
+            let pid = if status.dwProcessId != 0 { Some(status.dwProcessId) } else { None };
+
+            services.push(Service {
+                name: wide_ptr_to_string(e.lpServiceName),
+                display_name: wide_ptr_to_string(e.lpDisplayName),
+                start_type: start_type(scm, e.lpServiceName),
+                state: match status.dwCurrentState {
+                    SERVICE_STOPPED if exit_code != NO_ERROR => ServiceState::Failed,
+                    SERVICE_STOPPED => ServiceState::Stopped,
+                    SERVICE_START_PENDING | SERVICE_CONTINUE_PENDING => ServiceState::Starting,
+                    SERVICE_STOP_PENDING | SERVICE_PAUSE_PENDING => ServiceState::Stopping,
+                    SERVICE_RUNNING => ServiceState::Running,
+                    SERVICE_PAUSED => ServiceState::Paused,
+                    _ => ServiceState::Other,
+                },
+                pid,
+                restarts: None,
+                exit_code,
+                //Shared svchost services report when their host process started
+                started: pid.and_then(|p| started.get(&p).copied()).unwrap_or(0),
+            });
+        }
+
+        CloseServiceHandle(scm);
+    }
+
+    return services;
+}
+
+#[cfg(windows)]
+pub(crate) fn boot_time() -> i64 {
+    let uptime_ms = unsafe { winapi::um::sysinfoapi::GetTickCount64() };
+    now() - (uptime_ms / 1000) as i64
+}
+
+#[cfg(not(windows))]
+pub(crate) fn boot_time() -> i64 {
+    crate::procs::read_boot_time()
+}
+
+#[cfg(not(windows))]
+const SHOW_PROPERTIES: &str =
+    "Id,Description,UnitFileState,ActiveState,SubState,Type,Result,MainPID,NRestarts,ExecMainStatus,ActiveEnterTimestampMonotonic";
+
+//Blank line separated Key=Value blocks from `systemctl show -p ...`, one per unit
+#[cfg(not(windows))]
+pub(crate) fn parse_show(text: &str, boot_time: i64) -> Vec<Service> {
+    let mut services: Vec<Service> = vec![];
+
+    for block in text.split("\n\n") {
+        let get = |key: &str| {
+            block
+                .lines()
+                .find_map(|l| l.strip_prefix(key).and_then(|v| v.strip_prefix('=')))
+                .unwrap_or("")
+        };
+        if get("Id").is_empty() {
+            continue;
+        }
+
+        let oneshot_done = get("Type") == "oneshot" && get("Result") == "success";
+        let state = match (get("ActiveState"), get("SubState")) {
+            ("active", "exited") => ServiceState::Exited,
+            ("active", _) | ("reloading", _) => ServiceState::Running,
+            ("activating", _) => ServiceState::Starting,
+            ("deactivating", _) => ServiceState::Stopping,
+            ("failed", _) => ServiceState::Failed,
+            ("inactive", _) if oneshot_done => ServiceState::Exited,
+            ("inactive", _) => ServiceState::Stopped,
+            _ => ServiceState::Other,
+        };
+        let since_boot_us = get("ActiveEnterTimestampMonotonic").parse::<i64>().unwrap_or(0);
+
+        services.push(Service {
+            name: get("Id").to_string(),
+            display_name: get("Description").to_string(),
+            start_type: match get("UnitFileState") {
+                "enabled" | "enabled-runtime" => StartType::Automatic,
+                "disabled" => StartType::Manual,
+                "masked" | "masked-runtime" => StartType::Disabled,
+                "static" => StartType::Static,
+                _ => StartType::Other,
+            },
+            state,
+            pid: get("MainPID").parse::<u32>().ok().filter(|&p| p != 0),
+            restarts: get("NRestarts").parse().ok(),
+            exit_code: get("ExecMainStatus").parse().unwrap_or(0),
+            started: if since_boot_us > 0 && state != ServiceState::Stopped {
+                boot_time + since_boot_us / 1_000_000
+            } else {
+                0
+            },
+        });
+    }
+
+    return services;
+}
+
+//Every loaded service unit from systemd, read through systemctl
+#[cfg(not(windows))]
+pub(crate) fn snapshot() -> Vec<Service> {
+    use std::process::Command;
+
+    let listed = Command::new("systemctl")
+        .args(["list-units", "--type=service", "--all", "--plain", "--no-legend", "--no-pager"])
+        .output();
+    let listed = match listed {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout).into_owned(),
+        _ => return vec![],
+    };
+
+    //UNIT LOAD ACTIVE SUB DESCRIPTION, units that are referenced but missing show as not-found
+    let units: Vec<&str> = listed
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some(unit), Some("loaded")) => Some(unit),
+                _ => None,
+            }
+        })
+        .collect();
+    if units.is_empty() {
+        return vec![];
+    }
+
+    let shown = Command::new("systemctl")
+        .args(["show", "--no-pager", "-p", SHOW_PROPERTIES, "--"])
+        .args(&units)
+        .output();
+    match shown {
+        Ok(o) if o.status.success() => parse_show(&String::from_utf8_lossy(&o.stdout), boot_time()),
+        _ => vec![],
+    }
+}
+
+//Exclusions from [services] exclude match the service name, case insensitive
+pub(crate) fn included(service: &Service, config: &Config) -> bool {
+    !config.services_exclude.iter().any(|e| e.eq_ignore_ascii_case(&service.name))
+}
+
+pub(crate) fn print_services(services: &[Service], boot_time: i64, all: bool) {
+    let now = now();
+
+    println!(
+        "{:<32}{:<16}{:<10}{:>8}{:>10}  {:<21}{:<13}Description",
+        "Service", "Start Type", "State", "PID", "Restarts", "Started", "Issue"
+    );
+    for s in services {
+        let issue = s.issue(boot_time, now);
+        if issue.is_empty() && !all {
+            continue;
+        }
+        println!(
+            "{:<32}{:<16}{:<10}{:>8}{:>10}  {:<21}{:<13}{}",
+            s.name.chars().take(31).collect::<String>(),
+            s.start_type.name(),
+            s.state.name(),
+            s.pid.map(|p| p.to_string()).unwrap_or_else(|| "-".to_string()),
+            s.restarts.map(|r| r.to_string()).unwrap_or_else(|| "-".to_string()),
+            if s.started > 0 { format_time(s.started) } else { "-".to_string() },
+            if s.failed() && s.exit_code != 0 { format!("{} ({})", issue, s.exit_code) } else { issue.to_string() },
+            s.display_name
+        );
+    }
+}
+
+//Entry point for `uptime services [--all]`, without --all only services with an issue are listed
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let all = match args {
+        [] => false,
+        [flag] if flag == "--all" => true,
+        _ => return Err("usage: uptime services [--all]".into()),
+    };
+
+    let services: Vec<Service> = snapshot().into_iter().filter(|s| included(s, config)).collect();
+    print_services(&services, boot_time(), all);
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOT: i64 = 1_700_000_000;
+
+    fn service(start_type: StartType, state: ServiceState, started: i64) -> Service {
+        Service {
+            name: "svc".to_string(),
+            start_type,
+            state,
+            started,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn failed_and_stopped_automatic_services_are_issues() {
+        let now = BOOT + 86_400;
+        assert_eq!(service(StartType::Manual, ServiceState::Failed, 0).issue(BOOT, now), "failed");
+        assert_eq!(service(StartType::Automatic, ServiceState::Stopped, 0).issue(BOOT, now), "not running");
+        assert_eq!(service(StartType::Manual, ServiceState::Stopped, 0).issue(BOOT, now), "");
+        assert_eq!(service(StartType::TriggerAutomatic, ServiceState::Stopped, 0).issue(BOOT, now), "");
+    }
+
+    #[test]
+    fn restart_needs_a_start_well_after_boot() {
+        let now = BOOT + 86_400;
+        let restarted = service(StartType::Automatic, ServiceState::Running, now - 60);
+        assert_eq!(restarted.issue(BOOT, now), "restarted");
+        let at_boot = service(StartType::Automatic, ServiceState::Running, BOOT + 30);
+        assert_eq!(at_boot.issue(BOOT, BOOT + 120), "");
+        let counted = Service {
+            restarts: Some(2),
+            ..service(StartType::Manual, ServiceState::Running, BOOT + 30)
+        };
+        assert_eq!(counted.issue(BOOT, now), "restarted");
+    }
+}
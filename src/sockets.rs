@@ -0,0 +1,502 @@
+use crate::config::Config;
+use crate::procs;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TcpState {
+    Established,
+    SynSent,
+    SynRecv,
+    FinWait1,
+    FinWait2,
+    TimeWait,
+    Close,
+    CloseWait,
+    LastAck,
+    Listen,
+    Closing,
+}
+
+impl TcpState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TcpState::Established => "ESTABLISHED",
+            TcpState::SynSent => "SYN_SENT",
+            TcpState::SynRecv => "SYN_RECV",
+            TcpState::FinWait1 => "FIN_WAIT1",
+            TcpState::FinWait2 => "FIN_WAIT2",
+            TcpState::TimeWait => "TIME_WAIT",
+            TcpState::Close => "CLOSE",
+            TcpState::CloseWait => "CLOSE_WAIT",
+            TcpState::LastAck => "LAST_ACK",
+            TcpState::Listen => "LISTEN",
+            TcpState::Closing => "CLOSING",
+        }
+    }
+
+    //MIB_TCP_STATE values from the Windows tcpmib.h, DELETE_TCB is a socket being torn down
+    #[cfg(windows)]
+    fn from_mib(state: u32) -> Option<TcpState> {
+        match state {
+            1 => Some(TcpState::Close),
+            2 => Some(TcpState::Listen),
+            3 => Some(TcpState::SynSent),
+            4 => Some(TcpState::SynRecv),
+            5 => Some(TcpState::Established),
+            6 => Some(TcpState::FinWait1),
+            7 => Some(TcpState::FinWait2),
+            8 => Some(TcpState::CloseWait),
+            9 => Some(TcpState::Closing),
+            10 => Some(TcpState::LastAck),
+            11 => Some(TcpState::TimeWait),
+            12 => Some(TcpState::Close),
+            _ => None,
+        }
+    }
+
+    //TCP_* values from the Linux include/net/tcp_states.h
+    #[cfg(not(windows))]
+    fn from_linux(state: u32) -> Option<TcpState> {
+        match state {
+            0x01 => Some(TcpState::Established),
+            0x02 => Some(TcpState::SynSent),
+            0x03 => Some(TcpState::SynRecv),
+            0x04 => Some(TcpState::FinWait1),
+            0x05 => Some(TcpState::FinWait2),
+            0x06 => Some(TcpState::TimeWait),
+            0x07 => Some(TcpState::Close),
+            0x08 => Some(TcpState::CloseWait),
+            0x09 => Some(TcpState::LastAck),
+            0x0A => Some(TcpState::Listen),
+            0x0B => Some(TcpState::Closing),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Socket {
+    pub protocol: Protocol,
+    pub local: SocketAddr,
+    //None for UDP sockets that are only bound
+    pub remote: Option<SocketAddr>,
+    //None for UDP
+    pub state: Option<TcpState>,
+    pub pid: Option<u32>,
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct Summary {
+    pub tcp: usize,
+    pub udp: usize,
+    pub states: Vec<(TcpState, usize)>,
+    //TCP sockets in LISTEN and every bound UDP socket, with the owning process name
+    pub listening: Vec<(Socket, String)>,
+    //Remote addresses with the most established connections
+    pub peers: Vec<(IpAddr, usize)>,
+}
+
+//Calls one of the GetExtended*Table functions twice, first to size the buffer
+#[cfg(windows)]
+unsafe fn extended_table(query: impl Fn(*mut std::ffi::c_void, &mut u32) -> u32) -> Vec<u64> {
+    use winapi::shared::winerror::{ERROR_INSUFFICIENT_BUFFER, NO_ERROR};
+
+    //u64 keeps the rows aligned
+    let mut buffer: Vec<u64> = vec![];
+    let mut size = 0_u32;
+    loop {
+        match query(buffer.as_mut_ptr() as _, &mut size) {
+            NO_ERROR => return buffer,
+            ERROR_INSUFFICIENT_BUFFER => buffer.resize(size as usize / 8 + 1, 0),
+            _ => return vec![],
+        }
+    }
+}
+
+//Rows after the dwNumEntries header of a GetExtended*Table result
+#[cfg(windows)]
+unsafe fn rows<T>(buffer: &[u64]) -> &[T] {
+    if buffer.is_empty() {
+        return &[];
+    }
+    let count = *(buffer.as_ptr() as *const u32) as usize;
+    //The first row follows the count, padded to the row alignment
+    let offset = std::mem::align_of::<T>().max(std::mem::size_of::<u32>());
+    std::slice::from_raw_parts((buffer.as_ptr() as *const u8).add(offset) as *const T, count)
+}
+
+#[cfg(windows)]
+pub(crate) fn snapshot() -> Vec<Socket> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use winapi::shared::iprtrmib::{TCP_TABLE_OWNER_PID_ALL, UDP_TABLE_OWNER_PID};
+    use winapi::shared::tcpmib::{MIB_TCP6ROW_OWNER_PID, MIB_TCPROW_OWNER_PID};
+    use winapi::shared::udpmib::{MIB_UDP6ROW_OWNER_PID, MIB_UDPROW_OWNER_PID};
+    use winapi::shared::ws2def::{AF_INET, AF_INET6};
+    use winapi::um::iphlpapi::{GetExtendedTcpTable, GetExtendedUdpTable};
+
+    //Ports are in network byte order in the low 16 bits
+    let port = |p: u32| u16::from_be(p as u16);
+    let v4 = |a: u32, p: u32| SocketAddr::new(IpAddr::V4(Ipv4Addr::from(a.to_ne_bytes())), port(p));
+    let v6 = |a: [u8; 16], p: u32| SocketAddr::new(IpAddr::V6(Ipv6Addr::from(a)), port(p));
+
+    let mut sockets: Vec<Socket> = vec![];
+
+    unsafe {
+        let tcp4 = extended_table(|buf, size| GetExtendedTcpTable(buf, size, 0, AF_INET as u32, TCP_TABLE_OWNER_PID_ALL, 0));
+        for r in rows::<MIB_TCPROW_OWNER_PID>(&tcp4) {
+            let state = TcpState::from_mib(r.dwState);
+            sockets.push(Socket {
+                protocol: Protocol::Tcp,
+                local: v4(r.dwLocalAddr, r.dwLocalPort),
+                remote: if state == Some(TcpState::Listen) { None } else { Some(v4(r.dwRemoteAddr, r.dwRemotePort)) },
+                state,
+                pid: Some(r.dwOwningPid),
+            });
+        }
+
+        let tcp6 = extended_table(|buf, size| GetExtendedTcpTable(buf, size, 0, AF_INET6 as u32, TCP_TABLE_OWNER_PID_ALL, 0));
+        for r in rows::<MIB_TCP6ROW_OWNER_PID>(&tcp6) {
+            let state = TcpState::from_mib(r.dwState);
+            sockets.push(Socket {
+                protocol: Protocol::Tcp,
+                local: v6(r.ucLocalAddr, r.dwLocalPort),
+                remote: if state == Some(TcpState::Listen) { None } else { Some(v6(r.ucRemoteAddr, r.dwRemotePort)) },
+                state,
+                pid: Some(r.dwOwningPid),
+            });
+        }
+
+        let udp4 = extended_table(|buf, size| GetExtendedUdpTable(buf, size, 0, AF_INET as u32, UDP_TABLE_OWNER_PID, 0));
+        for r in rows::<MIB_UDPROW_OWNER_PID>(&udp4) {
+            sockets.push(Socket {
+                protocol: Protocol::Udp,
+                local: v4(r.dwLocalAddr, r.dwLocalPort),
+                remote: None,
+                state: None,
+                pid: Some(r.dwOwningPid),
+            });
+        }
+
+        let udp6 = extended_table(|buf, size| GetExtendedUdpTable(buf, size, 0, AF_INET6 as u32, UDP_TABLE_OWNER_PID, 0));
+        for r in rows::<MIB_UDP6ROW_OWNER_PID>(&udp6) {
+            sockets.push(Socket {
+                protocol: Protocol::Udp,
+                local: v6(r.ucLocalAddr, r.dwLocalPort),
+                remote: None,
+                state: None,
+                pid: Some(r.dwOwningPid),
+            });
+        }
+    }
+
+    return sockets;
+}
+
+//"0100007F:0035" style addresses, the kernel prints each 32 bit word of the
+//network order address as a native endian hex number
+#[cfg(not(windows))]
+fn parse_proc_addr(text: &str) -> Option<SocketAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    let (addr, port) = text.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+
+    let mut bytes: Vec<u8> = vec![];
+    for i in (0..addr.len()).step_by(8) {
+        let word = u32::from_str_radix(addr.get(i..i + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&bytes);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    return Some(SocketAddr::new(ip, port));
+}
+
+//Rows of /proc/net/{tcp,tcp6,udp,udp6} with the socket inode, a header line then
+//"sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode"
+#[cfg(not(windows))]
+pub(crate) fn parse_proc_net(text: &str, protocol: Protocol) -> Vec<(Socket, u64)> {
+    let mut sockets: Vec<(Socket, u64)> = vec![];
+
+    for line in text.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 10 {
+            continue;
+        }
+        let (local, remote) = match (parse_proc_addr(fields[1]), parse_proc_addr(fields[2])) {
+            (Some(l), Some(r)) => (l, r),
+            _ => continue,
+        };
+        let state = match protocol {
+            Protocol::Tcp => u32::from_str_radix(fields[3], 16).ok().and_then(TcpState::from_linux),
+            Protocol::Udp => None,
+        };
+
+        sockets.push((
+            Socket {
+                protocol,
+                local,
+                //Unconnected UDP and listening TCP sockets have an all zero remote address
+                remote: if remote.ip().is_unspecified() { None } else { Some(remote) },
+                state,
+                pid: None,
+            },
+            fields[9].parse().unwrap_or(0),
+        ));
+    }
+
+    return sockets;
+}
+
+//Socket inode to owning pid from the "socket:[inode]" links in /proc/<pid>/fd,
+//other users' processes are only visible to root
+#[cfg(not(windows))]
+fn socket_owners() -> HashMap<u64, u32> {
+    let mut owners: HashMap<u64, u32> = HashMap::new();
+
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(e) => e,
+        Err(_) => return owners,
+    };
+
+    for entry in entries.flatten() {
+        let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+            Ok(pid) => pid,
+            Err(_) => continue,
+        };
+        let fds = match std::fs::read_dir(entry.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        for fd in fds.flatten() {
+            let inode = std::fs::read_link(fd.path()).ok().and_then(|target| {
+                target
+                    .to_str()
+                    .and_then(|t| t.strip_prefix("socket:[")?.strip_suffix(']')?.parse::<u64>().ok())
+            });
+            if let Some(inode) = inode {
+                owners.insert(inode, pid);
+            }
+        }
+    }
+
+    return owners;
+}
+
+#[cfg(not(windows))]
+pub(crate) fn snapshot() -> Vec<Socket> {
+    let owners = socket_owners();
+    let mut sockets: Vec<Socket> = vec![];
+
+    for (file, protocol) in [("tcp", Protocol::Tcp), ("tcp6", Protocol::Tcp), ("udp", Protocol::Udp), ("udp6", Protocol::Udp)] {
+        let text = match std::fs::read_to_string(format!("/proc/net/{}", file)) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        for (mut s, inode) in parse_proc_net(&text, protocol) {
+            //TIME_WAIT sockets no longer belong to a process and have inode 0
+            s.pid = owners.get(&inode).copied().filter(|_| inode != 0);
+            sockets.push(s);
+        }
+    }
+
+    return sockets;
+}
+
+pub(crate) fn summarise(sockets: Vec<Socket>, top: usize) -> Summary {
+    let names: HashMap<u32, String> = procs::process_table().into_iter().map(|p| (p.pid, p.name)).collect();
+
+    let mut summary = Summary::default();
+    let mut states: HashMap<TcpState, usize> = HashMap::new();
+    let mut peers: HashMap<IpAddr, usize> = HashMap::new();
+
+    for s in sockets {
+        match s.protocol {
+            Protocol::Tcp => summary.tcp += 1,
+            Protocol::Udp => summary.udp += 1,
+        }
+        if let Some(state) = s.state {
+            *states.entry(state).or_default() += 1;
+        }
+        if s.state == Some(TcpState::Established) {
+            if let Some(remote) = s.remote {
+                *peers.entry(remote.ip()).or_default() += 1;
+            }
+        }
+        if s.state == Some(TcpState::Listen) || s.protocol == Protocol::Udp {
+            let name = s.pid.and_then(|pid| names.get(&pid).cloned()).unwrap_or_default();
+            summary.listening.push((s, name));
+        }
+    }
+
+    summary.states = states.into_iter().collect();
+    summary.states.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    summary
+        .listening
+        .sort_by(|a, b| (a.0.protocol.name(), a.0.local.port()).cmp(&(b.0.protocol.name(), b.0.local.port())));
+
+    summary.peers = peers.into_iter().collect();
+    summary.peers.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    summary.peers.truncate(top);
+
+    return summary;
+}
+
+pub(crate) fn print_summary(summary: &Summary) {
+    let states: Vec<String> = summary.states.iter().map(|(s, n)| format!("{}: {}", s.name(), n)).collect();
+    if states.is_empty() {
+        println!("Sockets: TCP: {} | UDP: {}", summary.tcp, summary.udp);
+    } else {
+        println!("Sockets: TCP: {} | UDP: {} | {}", summary.tcp, summary.udp, states.join(" | "));
+    }
+
+    println!("{:<6}{:<48}{:>8} Process", "Proto", "Listening", "PID");
+    for (s, name) in &summary.listening {
+        println!(
+            "{:<6}{:<48}{:>8} {}",
+            s.protocol.name(),
+            s.local.to_string(),
+            s.pid.map(|p| p.to_string()).unwrap_or_else(|| "-".to_string()),
+            name
+        );
+    }
+
+    if !summary.peers.is_empty() {
+        let peers: Vec<String> = summary.peers.iter().map(|(ip, n)| format!("{} ({})", ip, n)).collect();
+        println!("Top Peers: {}", peers.join(" | "));
+    }
+}
+
+//Entry point for `uptime sockets [--top N]`
+pub(crate) fn run(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime sockets [--top N]";
+    let top = match args {
+        [] => config.sockets_top,
+        [flag, value] if flag == "--top" => value.parse().map_err(|_| usage)?,
+        _ => return Err(usage.into()),
+    };
+
+    print_summary(&summarise(snapshot(), top));
+
+    return Ok(());
+}
+
+//The /proc parsers only exist off Windows
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    //Captured on x86_64, where the kernel prints each address word little endian
+    const TCP: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:0277 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21236 1 0000000000000000 100 0 0 10 0
+   1: 0F02000A:0016 0202000A:D4C2 01 00000000:00000000 02:000A7E3E 00000000     0        0 33345 4 0000000000000000 20 4 30 10 -1
+   2: 0F02000A:A2B4 22D8B85D:01BB 06 00000000:00000000 03:00000F9B 00000000     0        0 0 3 0000000000000000
+   3: 0F02000A:C350 22D8B85D:01BB 0C 00000000:00000000 00:00000000 00000000  1000        0 40512 1 0000000000000000 20 4 0 10 -1
+";
+
+    #[cfg(target_endian = "little")]
+    const TCP6: &str = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000000000000:0016 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21240 1 0000000000000000 100 0 0 10 0
+   1: 00000000000000000000000001000000:0277 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 21235 1 0000000000000000 100 0 0 10 0
+   2: 0000000000000000FFFF00000F02000A:1F90 0000000000000000FFFF00000202000A:D4C4 01 00000000:00000000 00:00000000 00000000  1000        0 40977 1 0000000000000000 20 4 28 10 -1
+   3: B80D0120000000000000000001000000:01BB B80D01200000000000000000FE000000:E0F1 08 00000000:00000001 00:00000000 00000000    33        0 41022 1 0000000000000000 20 4 30 10 -1
+";
+
+    fn addr(text: &str) -> SocketAddr {
+        return text.parse().unwrap();
+    }
+
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn proc_addresses() {
+        assert_eq!(parse_proc_addr("0100007F:0277"), Some(addr("127.0.0.1:631")));
+        assert_eq!(parse_proc_addr("0202000A:D4C2"), Some(addr("10.0.2.2:54466")));
+        assert_eq!(parse_proc_addr("00000000:0000"), Some(addr("0.0.0.0:0")));
+        assert_eq!(parse_proc_addr("00000000000000000000000001000000:0277"), Some(addr("[::1]:631")));
+        assert_eq!(parse_proc_addr("B80D0120000000000000000001000000:01BB"), Some(addr("[2001:db8::1]:443")));
+        assert_eq!(parse_proc_addr("0000000000000000FFFF00000F02000A:1F90"), Some(addr("[::ffff:10.0.2.15]:8080")));
+
+        assert_eq!(parse_proc_addr("0100007F"), None);
+        assert_eq!(parse_proc_addr("0100007:0277"), None);
+        assert_eq!(parse_proc_addr("0100007F0100007F:0277"), None);
+        assert_eq!(parse_proc_addr("0100007G:0277"), None);
+        assert_eq!(parse_proc_addr("0100007F:10000"), None);
+    }
+
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn proc_tcp() {
+        let sockets = parse_proc_net(TCP, Protocol::Tcp);
+        assert_eq!(sockets.len(), 4);
+
+        let (listen, inode) = &sockets[0];
+        assert_eq!((listen.local, listen.remote, listen.state, *inode), (addr("127.0.0.1:631"), None, Some(TcpState::Listen), 21236));
+        let (ssh, inode) = &sockets[1];
+        assert_eq!((ssh.local, ssh.remote, ssh.state, *inode), (addr("10.0.2.15:22"), Some(addr("10.0.2.2:54466")), Some(TcpState::Established), 33345));
+        assert_eq!(ssh.protocol, Protocol::Tcp);
+        assert_eq!(ssh.pid, None);
+        //Time wait sockets have no owner left
+        assert_eq!((sockets[2].0.state, sockets[2].1), (Some(TcpState::TimeWait), 0));
+        //TCP_NEW_SYN_RECV is internal to the kernel and has no name here
+        assert_eq!((sockets[3].0.state, sockets[3].0.remote), (None, Some(addr("93.184.216.34:443"))));
+    }
+
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn proc_tcp6() {
+        let sockets = parse_proc_net(TCP6, Protocol::Tcp);
+        let found: Vec<(SocketAddr, Option<SocketAddr>, Option<TcpState>, u64)> = sockets.iter().map(|(s, i)| (s.local, s.remote, s.state, *i)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (addr("[::]:22"), None, Some(TcpState::Listen), 21240),
+                (addr("[::1]:631"), None, Some(TcpState::Listen), 21235),
+                (addr("[::ffff:10.0.2.15]:8080"), Some(addr("[::ffff:10.0.2.2]:54468")), Some(TcpState::Established), 40977),
+                (addr("[2001:db8::1]:443"), Some(addr("[2001:db8::fe]:57585")), Some(TcpState::CloseWait), 41022),
+            ]
+        );
+    }
+
+    #[test]
+    fn proc_udp_and_short_lines() {
+        //The st column of a UDP socket is not a TCP state
+        let text = "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops
+  345: 00000000:14E9 00000000:0000 07 00000000:00000000 00:00000000 00000000   104        0 19850 2 0000000000000000 0
+  346: 00000000:14E9
+  347: 0100007G:0035 00000000:0000 07 00000000:00000000 00:00000000 00000000   101        0 19843 2 0000000000000000 0
+";
+        let sockets = parse_proc_net(text, Protocol::Udp);
+        assert_eq!(sockets.len(), 1);
+        assert_eq!((sockets[0].0.protocol, sockets[0].0.local, sockets[0].0.state, sockets[0].1), (Protocol::Udp, addr("0.0.0.0:5353"), None, 19850));
+
+        assert!(parse_proc_net("", Protocol::Tcp).is_empty());
+        assert!(parse_proc_net(TCP.lines().next().unwrap(), Protocol::Tcp).is_empty());
+    }
+}
@@ -5,11 +5,13 @@ use windows::Win32::System::Performance::PdhAddEnglishCounterA;
 use windows::Win32::System::Performance::PdhCloseQuery;
 use windows::Win32::System::Performance::PdhCollectQueryData;
 use windows::Win32::System::Performance::PdhCollectQueryDataEx;
+use windows::Win32::System::Performance::PdhGetFormattedCounterArrayA;
 use windows::Win32::System::Performance::PdhGetFormattedCounterValue;
 use windows::Win32::System::Performance::PdhOpenQueryA;
 use windows::Win32::System::Performance::PdhRemoveCounter;
 use windows::Win32::System::Performance::PDH_FMT;
 use windows::Win32::System::Performance::PDH_FMT_COUNTERVALUE;
+use windows::Win32::System::Performance::PDH_FMT_COUNTERVALUE_ITEM_A;
 use windows::Win32::System::Power::CallNtPowerInformation;
 use windows::Win32::System::Power::POWER_INFORMATION_LEVEL;
 use windows::Win32::System::SystemInformation::GetPhysicallyInstalledSystemMemory;
@@ -21,20 +23,29 @@ use windows::Win32::System::SystemInformation::SYSTEM_INFO;
 use windows::Win32::System::Threading::CreateEventA;
 use windows::Win32::System::Threading::WaitForSingleObject;
 use windows::Win32::System::Threading::WAIT_OBJECT_0;
+use windows::Win32::System::WindowsProgramming::QueryUnbiasedInterruptTime;
 
 use ntapi::ntpoapi::PROCESSOR_POWER_INFORMATION;
 
+use crate::eventlog::{read_events, Direction};
+use crate::limits;
+
 use std::error::Error;
 use std::mem::MaybeUninit;
 use std::mem::{size_of, zeroed};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const FALSE: BOOL = BOOL(0);
 //const TRUE:BOOL = BOOL(1);
 const INFINITE: u32 = 4294967295u32;
 
+//"The system has returned from a low power state"
+const POWER_TROUBLESHOOTER_SOURCE: &str = "Microsoft-Windows-Power-Troubleshooter";
+const POWER_RESUME_EVENT: u32 = 1;
+
 //Credits to https://github.com/GuillaumeGomez/sysinfo
 // This formula comes from linux's include/linux/sched/loadavg.h
 // https://github.com/torvalds/linux/blob/345671ea0f9258f410eb057b9ced9cefbbe5dc78/include/linux/sched/loadavg.h#L20-L23
@@ -47,14 +58,17 @@ const INFINITE: u32 = 4294967295u32;
 // const LOADAVG_FACTOR_15F: f64 = 0.9944598480048967508795473394;
 // The time interval in seconds between taking load counts, same as Linux
 
-//This is the 2 second average calculations - above are for 5 second intervals
-//Use 2 seconds for now - so the result displays quicker
-#[allow(clippy::excessive_precision)]
-const LOADAVG_FACTOR_1F: f64 = 0.9672161004820059020409731093;
-#[allow(clippy::excessive_precision)]
-const LOADAVG_FACTOR_5F: f64 = 0.9933555062550344153694146759;
-#[allow(clippy::excessive_precision)]
-const LOADAVG_FACTOR_15F: f64 = 0.9977802450856064174364805839;
+//The factors are exp(-interval / period), for the default 2 second interval these are
+//1 min: 0.9672161004820059020409731093
+//5 min: 0.9933555062550344153694146759
+//15 min: 0.9977802450856064174364805839
+//Use 2 seconds by default - so the result displays quicker
+pub(crate) const DEFAULT_CPU_INTERVAL: u32 = 1;
+pub(crate) const DEFAULT_LOAD_INTERVAL: u32 = 2;
+
+fn load_factor(interval: u32, period_secs: f64) -> f64 {
+    (-(interval as f64) / period_secs).exp()
+}
 
 #[derive(Default, Debug, Clone)]
 pub(crate) struct Uptime {
@@ -63,6 +77,16 @@ pub(crate) struct Uptime {
     pub(crate) uptime_hours: u64,
     pub(crate) uptime_minutes: u64,
     pub(crate) uptime_seconds: u64,
+    //Unix timestamp in seconds
+    pub(crate) boot_time: i64,
+    //uptime_ms is wall clock time since boot including sleep and hibernate,
+    //active_ms only counts time the system was awake
+    pub(crate) active_ms: u64,
+    pub(crate) suspended_ms: u64,
+    //Equal to uptime_ms when the system has not been suspended since boot.
+    //This and resume_count are only filled in by resumes(), which reads the event log
+    pub(crate) since_resume_ms: u64,
+    pub(crate) resume_count: u32,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -72,6 +96,10 @@ pub(crate) struct Memory {
     pub(crate) page_file_size: u64,
     pub(crate) available_page: u64,
     pub(crate) used_page: u64,
+    //Container or job object memory limit and its usage, None when the host is the limit
+    pub(crate) limit_memory: Option<u64>,
+    pub(crate) limit_used: Option<u64>,
+    pub(crate) limit_source: Option<&'static str>,
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -89,6 +117,8 @@ struct LoadAvg(f64, f64, f64);
 pub(crate) struct Processor {
     pub(crate) current_mhz: u64,
     pub(crate) number_processors: u32,
+    //Processors' worth of CPU after quotas and affinity, equal to number_processors when unlimited
+    pub(crate) effective_processors: f64,
 }
 
 pub trait SystemInfo {
@@ -123,19 +153,24 @@ impl SystemInfo for Processor {
                 }
             };
         }
+        self.effective_processors = limits::detect().effective_cpus(self.number_processors);
         return self;
     }
 }
 
-fn get_load_averages(first_load: f64, second_load: f64) -> (f64, f64, f64) {
+fn get_load_averages(first_load: f64, second_load: f64, interval: u32) -> (f64, f64, f64) {
     //Calculations derived from this article - looks like we need the previous load to calculate average
     //https://www.linuxjournal.com/article/9001
-    let avg_one = (LOADAVG_FACTOR_1F + first_load * (1.0 - LOADAVG_FACTOR_1F))
-        * (LOADAVG_FACTOR_1F + second_load * (1.0 - LOADAVG_FACTOR_1F));
-    let avg_five = (LOADAVG_FACTOR_5F + first_load * (1.0 - LOADAVG_FACTOR_5F))
-        * (LOADAVG_FACTOR_5F + second_load * (1.0 - LOADAVG_FACTOR_5F));
-    let avg_fifteen = (LOADAVG_FACTOR_5F + first_load * (1.0 - LOADAVG_FACTOR_5F))
-        * (LOADAVG_FACTOR_15F + second_load * (1.0 - LOADAVG_FACTOR_15F));
+    let factor_1 = load_factor(interval, 60.0);
+    let factor_5 = load_factor(interval, 300.0);
+    let factor_15 = load_factor(interval, 900.0);
+
+    let avg_one = (factor_1 + first_load * (1.0 - factor_1))
+        * (factor_1 + second_load * (1.0 - factor_1));
+    let avg_five = (factor_5 + first_load * (1.0 - factor_5))
+        * (factor_5 + second_load * (1.0 - factor_5));
+    let avg_fifteen = (factor_15 + first_load * (1.0 - factor_15))
+        * (factor_15 + second_load * (1.0 - factor_15));
 
     return (avg_one, avg_five, avg_fifteen);
 }
@@ -146,6 +181,13 @@ impl SystemInfo for Load {
     }
 
     fn info(&mut self) -> &mut Self {
+        return self.sample(DEFAULT_CPU_INTERVAL, DEFAULT_LOAD_INTERVAL);
+    }
+}
+
+impl Load {
+    //Same as info() but with the PDH sampling intervals in seconds
+    pub(crate) fn sample(&mut self, cpu_interval: u32, load_interval: u32) -> &mut Self {
         unsafe {
             let data = Arc::new(Mutex::new(Load::default()));
             let (tx, rx): (Sender<Load>, Receiver<Load>) = channel();
@@ -157,7 +199,7 @@ impl SystemInfo for Load {
             let h1 = thread::spawn(move || {
 
                 let mut data1 = data1.lock().unwrap();
-                match get_processor_activity() {
+                match get_processor_activity(cpu_interval) {
                     Ok(pa) => data1.processor_activity = pa,
                     Err(_) => data1.processor_activity = 0_f64,
                 };
@@ -168,12 +210,12 @@ impl SystemInfo for Load {
 
             let h2 = thread::spawn(move || {
                 let mut data2 = data2.lock().unwrap();
-                match get_load_activity() {
+                match get_load_activity(load_interval) {
                     Ok(loadnow) => {
-                        //Of the 2 load queries 5 seconds apart this is the most recent
+                        //Of the 2 load queries load_interval seconds apart this is the most recent
                         data2.current_load = loadnow[1];
                         (data2.avg_1_load, data2.avg_5_load, data2.avg_15_load) =
-                            get_load_averages(loadnow[0], loadnow[1]);
+                            get_load_averages(loadnow[0], loadnow[1], load_interval);
                     }
                     Err(_) => data2.current_load = 0_f64,
                 };
@@ -214,10 +256,57 @@ impl SystemInfo for Uptime {
             self.uptime_minutes = self.uptime_ms / (1000 * 60) % 60;
             self.uptime_hours = (self.uptime_ms / (1000 * 3600)) % 24;
         }
+
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.boot_time = (now_ms.saturating_sub(self.uptime_ms) / 1000) as i64;
+
+        unsafe {
+            //Unbiased interrupt time does not advance while asleep, in 100ns units
+            let mut unbiased: u64 = 0;
+            if QueryUnbiasedInterruptTime(&mut unbiased).as_bool() {
+                self.active_ms = (unbiased / 10_000).min(self.uptime_ms);
+            } else {
+                self.active_ms = self.uptime_ms;
+            }
+        }
+        self.suspended_ms = self.uptime_ms - self.active_ms;
+        self.since_resume_ms = self.uptime_ms;
+        return self;
+    }
+}
+
+impl Uptime {
+    //Count resumes since boot. This walks the System event log back to boot time,
+    //so it is left out of info() which every sample and --watch tick calls
+    pub(crate) fn resumes(&mut self) -> &mut Self {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        let resumes = get_resume_times(self.boot_time);
+        self.resume_count = resumes.len() as u32;
+        self.since_resume_ms = match resumes.iter().max() {
+            Some(last) => now_ms.saturating_sub(*last as u64 * 1000),
+            None => self.uptime_ms,
+        };
         return self;
     }
 }
 
+//Resume from sleep or hibernate (including fast startup) since the given unix time, newest first
+fn get_resume_times(since: i64) -> Vec<i64> {
+    match read_events("System", Direction::BackwardsUntil(since), |source, id| {
+        source == POWER_TROUBLESHOOTER_SOURCE && id == POWER_RESUME_EVENT
+    }) {
+        Ok(events) => events.iter().map(|e| e.time).collect(),
+        Err(_) => vec![],
+    }
+}
+
 fn return_error<T>(msg: &str, reference: &String) -> Result<T, Box<dyn Error>> {
     let mut err_str = String::new();
     err_str.push_str(msg);
@@ -313,7 +402,7 @@ unsafe fn get_formatted_counter_value(counter_query: isize) -> f64 {
     return current_value;
 }
 
-pub unsafe fn get_processor_activity() -> Result<f64, Box<dyn Error>> {
+pub unsafe fn get_processor_activity(interval: u32) -> Result<f64, Box<dyn Error>> {
     match open_query() {
         Some(px_query) => {
             match add_counter(
@@ -326,7 +415,7 @@ pub unsafe fn get_processor_activity() -> Result<f64, Box<dyn Error>> {
 
                     //Lets get 2 samples
                     //https://docs.microsoft.com/en-us/windows/win32/api/pdh/nf-pdh-pdhcollectquerydataex
-                    let event = collect_data_interval(px_query, 1);
+                    let event = collect_data_interval(px_query, interval);
                     let wait_result = WaitForSingleObject(event, INFINITE);
 
                     if wait_result == WAIT_OBJECT_0 {
@@ -346,7 +435,7 @@ pub unsafe fn get_processor_activity() -> Result<f64, Box<dyn Error>> {
     };
 }
 
-pub unsafe fn get_load_activity() -> Result<Vec<f64>, Box<dyn Error>> {
+pub unsafe fn get_load_activity(interval: u32) -> Result<Vec<f64>, Box<dyn Error>> {
     match open_query() {
         Some(px_query) => {
             match add_counter(
@@ -361,7 +450,7 @@ pub unsafe fn get_load_activity() -> Result<Vec<f64>, Box<dyn Error>> {
                     
                     //Lets get 2 samples
                     //https://docs.microsoft.com/en-us/windows/win32/api/pdh/nf-pdh-pdhcollectquerydataex
-                    let event = collect_data_interval(px_query, 2);
+                    let event = collect_data_interval(px_query, interval);
                     let wait_result = WaitForSingleObject(event, INFINITE);
 
                     if wait_result == WAIT_OBJECT_0 {
@@ -381,6 +470,133 @@ pub unsafe fn get_load_activity() -> Result<Vec<f64>, Box<dyn Error>> {
     };
 }
 
+//User, privileged, interrupt, DPC and idle percentages followed by context switches/sec
+//and interrupts/sec. All are rate counters so they need two collections interval seconds apart.
+//Interrupt and DPC time are counted in privileged time too
+pub unsafe fn get_cpu_times(interval: u32) -> Result<Vec<f64>, Box<dyn Error>> {
+    let paths: [&[u8]; 7] = [
+        b"\\Processor(_Total)\\% User Time\0",
+        b"\\Processor(_Total)\\% Privileged Time\0",
+        b"\\Processor(_Total)\\% Interrupt Time\0",
+        b"\\Processor(_Total)\\% DPC Time\0",
+        b"\\Processor(_Total)\\% Idle Time\0",
+        b"\\System\\Context Switches/sec\0",
+        b"\\Processor(_Total)\\Interrupts/sec\0",
+    ];
+
+    let px_query = match open_query() {
+        Some(px_query) => px_query,
+        None => return Err("Open Query failed".into()),
+    };
+
+    let mut counters = vec![];
+    for path in paths.iter() {
+        match add_counter(px_query, PSTR(path.as_ptr())) {
+            Some(counter) => counters.push(counter),
+            None => {
+                PdhCloseQuery(px_query as isize);
+                return return_error("Add counter failed", &"PdhAddEnglishCounterA".to_string());
+            }
+        }
+    }
+
+    collect_data_now(px_query);
+    let event = collect_data_interval(px_query, interval);
+    let wait_result = WaitForSingleObject(event, INFINITE);
+
+    let values = if wait_result == WAIT_OBJECT_0 {
+        counters.iter().map(|c| get_formatted_counter_value(*c)).collect()
+    } else {
+        vec![0_f64; counters.len()]
+    };
+
+    for counter in counters {
+        PdhRemoveCounter(counter);
+    }
+    PdhCloseQuery(px_query as isize);
+    return Ok(values);
+}
+
+//Temperature of each ACPI thermal zone in Celsius. The counter is in Kelvin and, unlike the
+//MSAcpi_ThermalZoneTemperature WMI class, can be read without administrator rights
+pub unsafe fn get_thermal_zones() -> Result<Vec<(String, f64)>, Box<dyn Error>> {
+    let px_query = match open_query() {
+        Some(px_query) => px_query,
+        None => return Err("Open Query failed".into()),
+    };
+
+    let counter = match add_counter(px_query, PSTR(b"\\Thermal Zone Information(*)\\Temperature\0" as *const u8)) {
+        Some(counter) => counter,
+        None => {
+            PdhCloseQuery(px_query as isize);
+            return return_error("Add counter failed", &"PdhAddEnglishCounterA".to_string());
+        }
+    };
+
+    collect_data_now(px_query);
+
+    //First call sizes the buffer, the item names are stored after the items themselves
+    let dwformat = PDH_FMT(0x00000200);
+    let (mut size, mut count) = (0_u32, 0_u32);
+    PdhGetFormattedCounterArrayA(counter, dwformat, &mut size, &mut count, std::ptr::null_mut());
+    let mut buffer = vec![0_u64; (size as usize).div_ceil(8)];
+    let items = buffer.as_mut_ptr() as *mut PDH_FMT_COUNTERVALUE_ITEM_A;
+
+    let mut zones = vec![];
+    if size > 0 && PdhGetFormattedCounterArrayA(counter, dwformat, &mut size, &mut count, items) == 0 {
+        for item in std::slice::from_raw_parts(items, count as usize) {
+            let name = std::ffi::CStr::from_ptr(item.szName.0 as *const _).to_string_lossy().to_string();
+            zones.push((name, item.FmtValue.Anonymous.doubleValue - 273.15));
+        }
+    }
+
+    PdhRemoveCounter(counter);
+    PdhCloseQuery(px_query as isize);
+    return Ok(zones);
+}
+
+//Percent of samples over the interval where threads were waiting for a processor and
+//where I/O was queued on any disk. Both counters are instantaneous so sample every 100ms
+pub unsafe fn get_queue_pressure(interval: u32) -> Result<(f64, f64), Box<dyn Error>> {
+    let px_query = match open_query() {
+        Some(px_query) => px_query,
+        None => return Err("Open Query failed".into()),
+    };
+
+    let cpu_query = add_counter(px_query, PSTR(b"\\System\\Processor Queue Length\0" as *const u8));
+    let disk_query = add_counter(
+        px_query,
+        PSTR(b"\\PhysicalDisk(_Total)\\Current Disk Queue Length\0" as *const u8),
+    );
+    let (cpu_query, disk_query) = match (cpu_query, disk_query) {
+        (Some(c), Some(d)) => (c, d),
+        _ => {
+            PdhCloseQuery(px_query as isize);
+            return return_error("Add counter failed", &"PdhAddEnglishCounterA".to_string());
+        }
+    };
+
+    let samples = interval.max(1) * 10;
+    let (mut cpu_waiting, mut disk_waiting) = (0_u32, 0_u32);
+    for _ in 0..samples {
+        PdhCollectQueryData(px_query);
+        if get_formatted_counter_value(cpu_query) > 0.0 {
+            cpu_waiting += 1;
+        }
+        if get_formatted_counter_value(disk_query) > 0.0 {
+            disk_waiting += 1;
+        }
+        thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    PdhRemoveCounter(cpu_query);
+    PdhRemoveCounter(disk_query);
+    PdhCloseQuery(px_query as isize);
+
+    let pct = |n: u32| n as f64 * 100.0 / samples as f64;
+    return Ok((pct(cpu_waiting), pct(disk_waiting)));
+}
+
 impl SystemInfo for Memory {
     fn new() -> Memory {
         return Memory::default();
@@ -411,6 +627,11 @@ impl SystemInfo for Memory {
             }
             self.used_page = self.page_file_size - self.available_page;
 
+            let limits = limits::detect();
+            self.limit_memory = limits.memory_limit.map(|b| b / (1024 * 1024));
+            self.limit_source = limits.source.map(|s| s.name());
+            self.limit_used = limits.memory_used.filter(|_| limits.memory_limit.is_some()).map(|b| b / (1024 * 1024));
+
             let mut _power_info: POWER_INFORMATION_LEVEL = zeroed();
             let _inputbuffer: *const ::core::ffi::c_void = std::ptr::null();
             let _outputbuffer: *mut ::core::ffi::c_void = std::mem::zeroed();
@@ -0,0 +1,260 @@
+use crate::config::{Config, Format, Section};
+use crate::cpu;
+use crate::disks::Disks;
+use crate::history::format_time_rfc3339;
+use crate::host::Host;
+use crate::ipc;
+use crate::json::Value;
+use crate::record;
+use crate::system::{Memory, Processor, SystemInfo, Uptime};
+use crate::users::Session;
+
+use std::error::Error;
+use std::io::Write;
+
+//Sections with a fixed set of values per sample, each adds its columns to the sample row
+pub(crate) const ROW_SECTIONS: [Section; 5] = [Section::Host, Section::Uptime, Section::Memory, Section::Load, Section::Cpu];
+
+const DISK_COLUMNS: [&str; 7] = ["time", "drive", "type", "fs", "size", "free", "used"];
+
+//Columns kept as strings in JSON, every other column is a number
+const TEXT_COLUMNS: [&str; 6] = ["time", "host", "boot_time", "drive", "type", "fs"];
+
+//Quote CSV fields that need it, TSV has no quoting so tabs and line breaks become spaces
+fn escape(field: &str, format: Format) -> String {
+    match format {
+        Format::Tsv => field.replace(['\t', '\r', '\n'], " "),
+        _ if field.contains([',', '"', '\r', '\n']) => format!("\"{}\"", field.replace('"', "\"\"")),
+        _ => field.to_string(),
+    }
+}
+
+fn json_field(column: &str, value: &str) -> (String, Value) {
+    let value = match value.parse::<f64>() {
+        Ok(n) if !TEXT_COLUMNS.contains(&column) => Value::Number(n),
+        _ => Value::String(value.to_string()),
+    };
+    return (column.to_string(), value);
+}
+
+//Written and flushed a row at a time so a redirected stream can be tailed while it is logged
+pub(crate) fn write_row<S: AsRef<str>>(fields: &[S], format: Format) -> Result<(), Box<dyn Error>> {
+    let separator = if format == Format::Tsv { "\t" } else { "," };
+    let fields: Vec<String> = fields.iter().map(|f| escape(f.as_ref(), format)).collect();
+
+    let mut out = std::io::stdout();
+    writeln!(out, "{}", fields.join(separator))?;
+    out.flush()?;
+    return Ok(());
+}
+
+//One sample of the row sections as (column, value) pairs. Sizes are bytes and times are
+//RFC 3339 with the local offset, so the columns do not change with the unit settings and
+//snapshots from hosts in other timezones can be compared
+pub(crate) fn sample(sections: &[Section], config: &Config) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut row = vec![("time".to_string(), format_time_rfc3339(record::now()))];
+    let mut push = |name: &str, value: String| row.push((name.to_string(), value));
+
+    for section in sections {
+        match section {
+            Section::Host => push("host", Host::info().hostname),
+            Section::Uptime => {
+                let mut u: Uptime = SystemInfo::new();
+                let u = u.info();
+                push("uptime", (u.uptime_ms / 1000).to_string());
+                push("boot_time", format_time_rfc3339(u.boot_time));
+                push("users", unsafe { Session::new().info() }.len().to_string());
+            }
+            Section::Memory => {
+                let mut m: Memory = SystemInfo::new();
+                let m = m.info();
+                //Memory reports megabytes
+                let bytes = |v: u64| (v * 1024 * 1024).to_string();
+                push("memory_installed", bytes(m.installed_ram));
+                push("memory_available", bytes(m.available_memory));
+                push("page_total", bytes(m.page_file_size));
+                push("page_available", bytes(m.available_page));
+                push("page_used", bytes(m.used_page));
+            }
+            Section::Load => {
+                let mut p: Processor = SystemInfo::new();
+                let p = p.info().clone();
+                let l = ipc::load(config);
+                push("load", format!("{:.2}", l.current_load));
+                push("load1", format!("{:.2}", l.avg_1_load));
+                push("load5", format!("{:.2}", l.avg_5_load));
+                push("load15", format!("{:.2}", l.avg_15_load));
+                push("cpu", format!("{:.2}", l.processor_activity));
+                push("mhz", p.current_mhz.to_string());
+                push("cores", format!("{:.2}", p.effective_processors));
+            }
+            Section::Cpu => {
+                let c = cpu::sample(config.cpu_interval)?;
+                for (name, pct) in &c.times {
+                    push(&format!("cpu_{}", name), format!("{:.2}", pct));
+                }
+                push("context_switches", format!("{:.0}", c.context_switches));
+                push("interrupts", format!("{:.0}", c.interrupts));
+            }
+            _ => {}
+        }
+    }
+
+    return Ok(row);
+}
+
+fn disk_rows(config: &Config) -> Vec<Vec<String>> {
+    let time = format_time_rfc3339(record::now());
+    let mut d = Disks::new();
+    let disks = unsafe { d.info() };
+
+    return disks
+        .iter()
+        .filter(|x| config.include_disk(x))
+        .map(|x| {
+            vec![
+                time.clone(),
+                x.drive.clone(),
+                format!("{:?}", x.drive_type),
+                x.file_system.clone(),
+                x.total_bytes.to_string(),
+                x.available_bytes.to_string(),
+                x.total_bytes.saturating_sub(x.available_bytes).to_string(),
+            ]
+        })
+        .collect();
+}
+
+//One sample as a JSON object, the row sections as fields and the disks section as a "disks"
+//array. This is what `--format json`, the agent and `--host` over SSH exchange
+pub(crate) fn snapshot(sections: &[Section], config: &Config) -> Result<Value, Box<dyn Error>> {
+    let rows: Vec<Section> = sections.iter().copied().filter(|s| ROW_SECTIONS.contains(s)).collect();
+    let mut fields: Vec<(String, Value)> = sample(&rows, config)?.iter().map(|(c, v)| json_field(c, v)).collect();
+
+    if sections.contains(&Section::Disks) {
+        let disks = disk_rows(config)
+            .iter()
+            .map(|row| Value::Object(DISK_COLUMNS.iter().zip(row).skip(1).map(|(c, v)| json_field(c, v)).collect()))
+            .collect();
+        fields.push(("disks".to_string(), Value::Array(disks)));
+    }
+    return Ok(Value::Object(fields));
+}
+
+fn run_json(config: &Config) -> Result<(), Box<dyn Error>> {
+    let skipped: Vec<String> = config
+        .sections
+        .iter()
+        .filter(|s| !ROW_SECTIONS.contains(s) && **s != Section::Disks)
+        .map(|s| format!("{:?}", s).to_lowercase())
+        .collect();
+    if skipped.len() == config.sections.len() {
+        return Err("--format json supports the host, uptime, memory, load, cpu and disks sections".into());
+    }
+    if !skipped.is_empty() {
+        eprintln!("Not included in the JSON: {}", skipped.join(", "));
+    }
+
+    loop {
+        match snapshot(&config.sections, config) {
+            Ok(value) => {
+                let mut out = std::io::stdout();
+                writeln!(out, "{}", value)?;
+                out.flush()?;
+            }
+            Err(e) if config.watch.is_some() => eprintln!("Sample skipped: {}", e),
+            Err(e) => return Err(e),
+        }
+
+        match config.watch {
+            Some(seconds) => std::thread::sleep(std::time::Duration::from_secs(seconds as u64)),
+            None => break,
+        }
+    }
+    return Ok(());
+}
+
+//Entry point for `--format csv|tsv|json`. JSON is one object per sample on a line of its own. For
+//csv and tsv the disks section on its own gives one row per disk, otherwise the row sections give
+//one row per sample. With --watch rows keep streaming
+pub(crate) fn run(config: &Config) -> Result<(), Box<dyn Error>> {
+    if config.format == Format::Json {
+        return run_json(config);
+    }
+    let disks_only = config.sections == [Section::Disks];
+    let sections: Vec<Section> = config.sections.iter().copied().filter(|s| ROW_SECTIONS.contains(s)).collect();
+
+    if !disks_only {
+        let skipped: Vec<String> = config
+            .sections
+            .iter()
+            .filter(|s| !ROW_SECTIONS.contains(s))
+            .map(|s| format!("{:?}", s).to_lowercase())
+            .collect();
+        if sections.is_empty() {
+            return Err("--format csv|tsv supports the host, uptime, memory, load and cpu sections, or disks on its own".into());
+        }
+        if !skipped.is_empty() {
+            eprintln!("Not included in the table: {} (use --sections disks for the per disk table)", skipped.join(", "));
+        }
+    }
+
+    let mut header = false;
+    loop {
+        if disks_only {
+            if !header {
+                write_row(&DISK_COLUMNS, config.format)?;
+                header = true;
+            }
+            for row in disk_rows(config) {
+                write_row(&row, config.format)?;
+            }
+        } else {
+            match sample(&sections, config) {
+                Ok(row) => {
+                    if !header {
+                        let columns: Vec<&String> = row.iter().map(|(c, _)| c).collect();
+                        write_row(&columns, config.format)?;
+                        header = true;
+                    }
+                    let values: Vec<&String> = row.iter().map(|(_, v)| v).collect();
+                    write_row(&values, config.format)?;
+                }
+                //When streaming a missed sample is better than stopping, the next one may work
+                Err(e) if config.watch.is_some() => eprintln!("Sample skipped: {}", e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        match config.watch {
+            Some(seconds) => std::thread::sleep(std::time::Duration::from_secs(seconds as u64)),
+            None => break,
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_and_tsv_escaping() {
+        assert_eq!(escape("plain", Format::Csv), "plain");
+        assert_eq!(escape("a,b", Format::Csv), "\"a,b\"");
+        assert_eq!(escape("say \"hi\"", Format::Csv), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape("a\tb\nc", Format::Tsv), "a b c");
+        assert_eq!(escape("a,b", Format::Tsv), "a,b");
+    }
+
+    #[test]
+    fn json_numbers_and_text_columns() {
+        assert_eq!(json_field("load1", "0.05"), ("load1".to_string(), Value::Number(0.05)));
+        assert_eq!(json_field("host", "1234"), ("host".to_string(), Value::String("1234".to_string())));
+        assert_eq!(
+            json_field("time", "2026-10-19T10:00:00+02:00"),
+            ("time".to_string(), Value::String("2026-10-19T10:00:00+02:00".to_string()))
+        );
+    }
+}
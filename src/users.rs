@@ -0,0 +1,260 @@
+use crate::history::{format_duration, format_time};
+use crate::utmp;
+
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Default, Debug, Clone)]
+pub struct Session {
+    pub user: String,
+    pub session_id: u32,
+    pub kind: SessionKind,
+    //Window station on Windows (Console, RDP-Tcp#3), terminal line on Linux (tty1, pts/0)
+    pub station: String,
+    pub state: String,
+    //Unix timestamp in seconds
+    pub logon_time: i64,
+    pub idle_seconds: Option<u64>,
+    pub client: String,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum SessionKind {
+    #[default]
+    Other,
+    Console,
+    Rdp,
+    Ssh,
+}
+
+impl SessionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionKind::Other => "other",
+            SessionKind::Console => "console",
+            SessionKind::Rdp => "rdp",
+            SessionKind::Ssh => "ssh",
+        }
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+//Logged in users from utmp records, the same source `w` and `who` use
+pub(crate) fn sessions_from_utmp(records: &[utmp::UtmpRecord], dev: Option<&Path>) -> Vec<Session> {
+    let now = now();
+
+    return records
+        .iter()
+        .filter(|r| r.kind == utmp::USER_PROCESS && !r.user.is_empty())
+        .map(|r| {
+            let client = if !r.host.is_empty() {
+                r.host.clone()
+            } else if r.addr_v6[4..].iter().any(|&b| b != 0) {
+                let mut addr = [0u8; 16];
+                addr.copy_from_slice(&r.addr_v6);
+                Ipv6Addr::from(addr).to_string()
+            } else if r.addr_v6[..4].iter().any(|&b| b != 0) {
+                Ipv4Addr::new(r.addr_v6[0], r.addr_v6[1], r.addr_v6[2], r.addr_v6[3]).to_string()
+            } else {
+                String::new()
+            };
+
+            let kind = if r.line.starts_with("tty") || r.line.starts_with(':') {
+                SessionKind::Console
+            } else if !client.is_empty() {
+                SessionKind::Ssh
+            } else {
+                SessionKind::Other
+            };
+
+            //Idle time is the time since the terminal was last read from
+            let idle_seconds = dev
+                .and_then(|d| std::fs::metadata(d.join(&r.line)).ok())
+                .and_then(|m| m.accessed().ok())
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|t| (now - t.as_secs() as i64).max(0) as u64);
+
+            Session {
+                user: r.user.clone(),
+                session_id: if r.session != 0 { r.session as u32 } else { r.pid as u32 },
+                kind,
+                station: r.line.clone(),
+                state: "Active".to_string(),
+                logon_time: r.time,
+                idle_seconds,
+                client,
+            }
+        })
+        .collect();
+}
+
+#[cfg(windows)]
+unsafe fn query_session<T: Copy>(session_id: u32, class: windows::Win32::System::RemoteDesktop::WTS_INFO_CLASS) -> Option<T> {
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Foundation::PWSTR;
+    use windows::Win32::System::RemoteDesktop::WTSFreeMemory;
+    use windows::Win32::System::RemoteDesktop::WTSQuerySessionInformationW;
+
+    let mut buffer = PWSTR(std::ptr::null_mut());
+    let mut bytes = 0_u32;
+
+    if !WTSQuerySessionInformationW(HANDLE(0), session_id, class, &mut buffer, &mut bytes).as_bool() {
+        return None;
+    }
+
+    let value = if bytes as usize >= std::mem::size_of::<T>() {
+        Some(std::ptr::read_unaligned(buffer.0 as *const T))
+    } else {
+        None
+    };
+    WTSFreeMemory(buffer.0 as _);
+
+    return value;
+}
+
+#[cfg(windows)]
+fn wide_to_string(wide: &[u16]) -> String {
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..len])
+}
+
+#[cfg(windows)]
+impl Session {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub unsafe fn info(&mut self) -> Vec<Session> {
+        use crate::history::filetime_to_unix;
+        use windows::Win32::Foundation::HANDLE;
+        use windows::Win32::System::RemoteDesktop::*;
+
+        let mut sessions: Vec<Session> = vec![];
+        let mut info: *mut WTS_SESSION_INFOW = std::ptr::null_mut();
+        let mut count = 0_u32;
+
+        if !WTSEnumerateSessionsW(HANDLE(0), 0, 1, &mut info, &mut count).as_bool() {
+            return sessions;
+        }
+
+        for s in std::slice::from_raw_parts(info, count as usize) {
+            let detail: WTSINFOW = match query_session(s.SessionId, WTSSessionInfo) {
+                Some(d) => d,
+                None => continue,
+            };
+
+            //Session 0 services and listeners have no user
+            self.user = wide_to_string(&detail.UserName);
+            if self.user.is_empty() {
+                continue;
+            }
+            let domain = wide_to_string(&detail.Domain);
+            if !domain.is_empty() {
+                self.user = format!("{}\\{}", domain, self.user);
+            }
+
+            self.session_id = s.SessionId;
+            self.station = wide_to_string(&detail.WinStationName);
+            self.kind = if self.station.eq_ignore_ascii_case("Console") {
+                SessionKind::Console
+            } else if self.station.starts_with("RDP-") {
+                SessionKind::Rdp
+            } else {
+                SessionKind::Other
+            };
+            self.state = match s.State {
+                x if x == WTSActive => "Active",
+                x if x == WTSConnected => "Connected",
+                x if x == WTSDisconnected => "Disconnected",
+                x if x == WTSIdle => "Idle",
+                x if x == WTSShadow => "Shadow",
+                _ => "Other",
+            }
+            .to_string();
+            self.logon_time = filetime_to_unix(detail.LogonTime);
+            self.idle_seconds = if detail.LastInputTime > 0 && detail.CurrentTime >= detail.LastInputTime {
+                Some(((detail.CurrentTime - detail.LastInputTime) / 10_000_000) as u64)
+            } else {
+                None
+            };
+
+            self.client = match query_session::<WTS_CLIENT_ADDRESS>(s.SessionId, WTSClientAddress) {
+                //AF_INET, the address starts at offset 2
+                Some(a) if a.AddressFamily == 2 && a.Address[2..6].iter().any(|&b| b != 0) => {
+                    Ipv4Addr::new(a.Address[2], a.Address[3], a.Address[4], a.Address[5]).to_string()
+                }
+                //AF_INET6
+                Some(a) if a.AddressFamily == 23 => {
+                    let mut addr = [0u8; 16];
+                    addr.copy_from_slice(&a.Address[2..18]);
+                    Ipv6Addr::from(addr).to_string()
+                }
+                _ => String::new(),
+            };
+
+            sessions.push(self.to_owned());
+        }
+
+        WTSFreeMemory(info as _);
+
+        return sessions;
+    }
+}
+
+#[cfg(not(windows))]
+impl Session {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub unsafe fn info(&mut self) -> Vec<Session> {
+        match std::fs::read("/var/run/utmp") {
+            Ok(bytes) => sessions_from_utmp(&utmp::parse(&bytes), Some(Path::new("/dev"))),
+            Err(_) => vec![],
+        }
+    }
+}
+
+pub(crate) fn print_sessions(sessions: &[Session]) {
+    println!(
+        "{:<24}{:<9}{:<9}{:<14}{:<14}{:<21}{:<14}Client",
+        "User", "Session", "Type", "Station", "State", "Logon Time", "Idle"
+    );
+    for s in sessions {
+        println!(
+            "{:<24}{:<9}{:<9}{:<14}{:<14}{:<21}{:<14}{}",
+            s.user,
+            s.session_id,
+            s.kind.name(),
+            s.station,
+            s.state,
+            format_time(s.logon_time),
+            s.idle_seconds.map(|i| format_duration(i as i64)).unwrap_or_else(|| "-".to_string()),
+            s.client
+        );
+    }
+}
+
+//Entry point for `uptime users [--file <utmp file>]`
+pub(crate) fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let sessions = match args {
+        [] => unsafe { Session::new().info() },
+        [flag, path] if flag == "--file" => match std::fs::read(path) {
+            Ok(bytes) => sessions_from_utmp(&utmp::parse(&bytes), None),
+            Err(e) => return Err(format!("{}: {}", path, e).into()),
+        },
+        _ => return Err("usage: uptime users [--file PATH]".into()),
+    };
+
+    print_sessions(&sessions);
+
+    return Ok(());
+}
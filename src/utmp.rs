@@ -0,0 +1,61 @@
+//Parser for the glibc utmp/wtmp record layout used on Linux x86_64
+//https://man7.org/linux/man-pages/man5/utmp.5.html
+//Kept platform independent so copied wtmp files can be read anywhere
+
+pub(crate) const UTMP_RECORD_SIZE: usize = 384;
+
+pub(crate) const RUN_LVL: i16 = 1;
+pub(crate) const BOOT_TIME: i16 = 2;
+pub(crate) const USER_PROCESS: i16 = 7;
+
+#[derive(Default, Debug, Clone)]
+pub(crate) struct UtmpRecord {
+    pub(crate) kind: i16,
+    pub(crate) pid: i32,
+    pub(crate) line: String,
+    pub(crate) user: String,
+    pub(crate) host: String,
+    pub(crate) session: i32,
+    pub(crate) time: i64,
+    pub(crate) addr_v6: [u8; 16],
+}
+
+fn read_i16(buf: &[u8], offset: usize) -> i16 {
+    i16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_i32(buf: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+//Fixed size fields are NUL padded and not always NUL terminated
+fn read_str(buf: &[u8], offset: usize, len: usize) -> String {
+    let field = &buf[offset..offset + len];
+    let end = field.iter().position(|&b| b == 0).unwrap_or(len);
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+impl UtmpRecord {
+    fn parse(buf: &[u8]) -> UtmpRecord {
+        let mut addr_v6 = [0u8; 16];
+        addr_v6.copy_from_slice(&buf[348..364]);
+
+        UtmpRecord {
+            kind: read_i16(buf, 0),
+            pid: read_i32(buf, 4),
+            line: read_str(buf, 8, 32),
+            user: read_str(buf, 44, 32),
+            host: read_str(buf, 76, 256),
+            session: read_i32(buf, 336),
+            time: read_i32(buf, 340) as u32 as i64,
+            addr_v6,
+        }
+    }
+}
+
+pub(crate) fn parse(bytes: &[u8]) -> Vec<UtmpRecord> {
+    return bytes
+        .chunks_exact(UTMP_RECORD_SIZE)
+        .map(UtmpRecord::parse)
+        .collect();
+}
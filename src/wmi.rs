@@ -0,0 +1,594 @@
+//Minimal WMI client: COM setup, WQL queries and decoding of the returned VARIANTs into Rust
+//values. Everything above the COM layer is plain Rust so rows can be built by hand and
+//decoded on any platform
+
+use crate::config::Config;
+use crate::json;
+
+use std::error::Error;
+
+//Outside Windows only the tests build values and rows
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl Value {
+    pub(crate) fn display(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Bool(b) => b.to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::UInt(u) => u.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::String(s) => s.clone(),
+            Value::Array(a) => a.iter().map(|v| v.display()).collect::<Vec<String>>().join(", "),
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        match self {
+            Value::Null => "null".to_string(),
+            Value::Float(f) if !f.is_finite() => "null".to_string(),
+            Value::String(s) => json::quote(s),
+            Value::Array(a) => format!("[{}]", a.iter().map(|v| v.to_json()).collect::<Vec<String>>().join(",")),
+            _ => self.display(),
+        }
+    }
+}
+
+//One WMI object, properties in the order WMI enumerated them
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) struct Row {
+    pub(crate) columns: Vec<(String, Value)>,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl Row {
+    //Property names are case insensitive in WMI
+    pub(crate) fn value(&self, name: &str) -> Option<&Value> {
+        self.columns.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v)
+    }
+
+    //A missing property decodes like a NULL one, so it is only an error for non Option types
+    #[allow(dead_code)]
+    pub(crate) fn get<T: FromValue>(&self, name: &str) -> Result<T, Box<dyn Error>> {
+        let value = self.value(name).unwrap_or(&Value::Null);
+        return T::from_value(value).ok_or_else(|| {
+            format!("WMI property {}: cannot read {:?} as {}", name, value, std::any::type_name::<T>()).into()
+        });
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        let fields: Vec<String> = self.columns.iter().map(|(n, v)| format!("{}:{}", json::quote(n), v.to_json())).collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+//The typed layer has no collector built on it yet, only the tests
+#[allow(dead_code)]
+pub(crate) trait FromValue: Sized {
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+//Implemented by the structs collectors decode query results into
+#[allow(dead_code)]
+pub(crate) trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self, Box<dyn Error>>;
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Float(f) => Some(*f),
+            Value::Int(i) => Some(*i as f64),
+            Value::UInt(u) => Some(*u as f64),
+            _ => None,
+        }
+    }
+}
+
+//CIM uint64 and sint64 properties arrive as strings, so integers also parse from strings
+macro_rules! from_value_int {
+    ($($t:ty),*) => {
+        $(impl FromValue for $t {
+            fn from_value(value: &Value) -> Option<Self> {
+                match value {
+                    Value::Int(i) => <$t>::try_from(*i).ok(),
+                    Value::UInt(u) => <$t>::try_from(*u).ok(),
+                    Value::String(s) => s.trim().parse().ok(),
+                    _ => None,
+                }
+            }
+        })*
+    };
+}
+
+from_value_int!(u8, u16, u32, u64, i16, i32, i64);
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(None),
+            v => T::from_value(v).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(vec![]),
+            Value::Array(a) => a.iter().map(T::from_value).collect(),
+            _ => None,
+        }
+    }
+}
+
+//CIM_DATETIME, "20240131093015.500000+060": local time with the UTC offset in minutes
+#[allow(dead_code)]
+pub(crate) fn parse_datetime(s: &str) -> Option<i64> {
+    use chrono::{FixedOffset, NaiveDateTime, TimeZone};
+
+    if s.len() < 25 || !s.is_char_boundary(14) || !s.is_char_boundary(21) {
+        return None;
+    }
+    let local = NaiveDateTime::parse_from_str(&s[..14], "%Y%m%d%H%M%S").ok()?;
+    let minutes: i32 = s[21..].parse().ok()?;
+    let offset = FixedOffset::east_opt(minutes * 60)?;
+    return offset.from_local_datetime(&local).single().map(|t| t.timestamp());
+}
+
+#[cfg(windows)]
+mod com {
+    use super::{Row, Value};
+
+    use std::error::Error;
+    use std::ptr::null_mut;
+    use winapi::ctypes::c_void;
+    use winapi::shared::rpcdce::{RPC_C_AUTHN_LEVEL_CALL, RPC_C_AUTHN_LEVEL_DEFAULT, RPC_C_AUTHN_WINNT, RPC_C_AUTHZ_NONE, RPC_C_IMP_LEVEL_IMPERSONATE};
+    use winapi::shared::winerror::{HRESULT, RPC_E_CHANGED_MODE, RPC_E_TOO_LATE};
+    use winapi::shared::wtypes::*;
+    use winapi::shared::wtypesbase::CLSCTX_INPROC_SERVER;
+    use winapi::um::combaseapi::{CoCreateInstance, CoInitializeEx, CoInitializeSecurity, CoSetProxyBlanket, CoUninitialize};
+    use winapi::um::oaidl::{SAFEARRAY, VARIANT};
+    use winapi::um::objbase::COINIT_MULTITHREADED;
+    use winapi::um::objidl::EOAC_NONE;
+    use winapi::um::oleauto::{SafeArrayAccessData, SafeArrayGetLBound, SafeArrayGetUBound, SafeArrayUnaccessData, SysAllocString, SysFreeString, SysStringLen, VariantClear};
+    use winapi::um::unknwnbase::IUnknown;
+    use winapi::um::wbemcli::{
+        CLSID_WbemLocator, IEnumWbemClassObject, IWbemClassObject, IWbemLocator, IWbemServices, WBEM_FLAG_FORWARD_ONLY,
+        WBEM_FLAG_NONSYSTEM_ONLY, WBEM_FLAG_RETURN_IMMEDIATELY, WBEM_INFINITE,
+    };
+    use winapi::Interface;
+
+    fn check(hr: HRESULT, what: &str) -> Result<(), Box<dyn Error>> {
+        if hr < 0 {
+            return Err(format!("{} failed: 0x{:08X}", what, hr as u32).into());
+        }
+        Ok(())
+    }
+
+    struct Bstr(BSTR);
+
+    impl Bstr {
+        fn new(s: &str) -> Bstr {
+            let wide: Vec<u16> = s.encode_utf16().chain(std::iter::once(0)).collect();
+            Bstr(unsafe { SysAllocString(wide.as_ptr()) })
+        }
+    }
+
+    impl Drop for Bstr {
+        fn drop(&mut self) {
+            unsafe { SysFreeString(self.0) };
+        }
+    }
+
+    unsafe fn from_bstr(b: BSTR) -> String {
+        if b.is_null() {
+            return String::new();
+        }
+        String::from_utf16_lossy(std::slice::from_raw_parts(b, SysStringLen(b) as usize))
+    }
+
+    //Reads one value of type vt from where the VARIANT union or a SAFEARRAY element starts
+    unsafe fn scalar(vt: u32, data: *const c_void) -> Value {
+        match vt {
+            VT_BOOL => Value::Bool(*(data as *const i16) != 0),
+            VT_I1 => Value::Int(*(data as *const i8) as i64),
+            VT_I2 => Value::Int(*(data as *const i16) as i64),
+            VT_I4 | VT_INT => Value::Int(*(data as *const i32) as i64),
+            VT_I8 => Value::Int(*(data as *const i64)),
+            VT_UI1 => Value::UInt(*(data as *const u8) as u64),
+            VT_UI2 => Value::UInt(*(data as *const u16) as u64),
+            VT_UI4 | VT_UINT => Value::UInt(*(data as *const u32) as u64),
+            VT_UI8 => Value::UInt(*(data as *const u64)),
+            VT_R4 => Value::Float(*(data as *const f32) as f64),
+            VT_R8 => Value::Float(*(data as *const f64)),
+            VT_BSTR => Value::String(from_bstr(*(data as *const BSTR))),
+            //VT_EMPTY, VT_NULL and embedded objects
+            _ => Value::Null,
+        }
+    }
+
+    unsafe fn array(psa: *mut SAFEARRAY, vt: u32) -> Value {
+        let (mut lower, mut upper) = (0, -1);
+        let mut data: *mut c_void = null_mut();
+        if psa.is_null()
+            || SafeArrayGetLBound(psa, 1, &mut lower) < 0
+            || SafeArrayGetUBound(psa, 1, &mut upper) < 0
+            || SafeArrayAccessData(psa, &mut data) < 0
+        {
+            return Value::Null;
+        }
+
+        let size = (*psa).cbElements as usize;
+        let items = (0..(upper - lower + 1).max(0) as usize)
+            .map(|i| scalar(vt, (data as *const u8).add(i * size) as *const c_void))
+            .collect();
+        SafeArrayUnaccessData(psa);
+        return Value::Array(items);
+    }
+
+    unsafe fn variant(v: &VARIANT) -> Value {
+        let tag = v.n1.n2();
+        let vt = tag.vt as u32;
+        if vt & VT_ARRAY != 0 {
+            return array(*tag.n3.parray(), vt & !VT_ARRAY);
+        }
+        return scalar(vt, &tag.n3 as *const _ as *const c_void);
+    }
+
+    unsafe fn release<T: Interface>(p: *mut T) {
+        if !p.is_null() {
+            (*(p as *mut IUnknown)).Release();
+        }
+    }
+
+    unsafe fn read_row(object: *mut IWbemClassObject) -> Row {
+        let mut row = Row::default();
+        if (*object).BeginEnumeration(WBEM_FLAG_NONSYSTEM_ONLY as i32) < 0 {
+            return row;
+        }
+        loop {
+            let mut name: BSTR = null_mut();
+            let mut value: VARIANT = std::mem::zeroed();
+            //WBEM_S_NO_MORE_DATA is a success code, so stop on anything other than S_OK
+            if (*object).Next(0, &mut name, &mut value, null_mut(), null_mut()) != 0 {
+                break;
+            }
+            row.columns.push((from_bstr(name), variant(&value)));
+            SysFreeString(name);
+            VariantClear(&mut value);
+        }
+        (*object).EndEnumeration();
+        return row;
+    }
+
+    pub(crate) struct Connection {
+        services: *mut IWbemServices,
+        //Only balance CoInitializeEx if this call was the one that initialised COM
+        uninitialize: bool,
+    }
+
+    impl Connection {
+        pub(crate) fn new(namespace: &str) -> Result<Connection, Box<dyn Error>> {
+            unsafe {
+                let hr = CoInitializeEx(null_mut(), COINIT_MULTITHREADED);
+                //Already initialised as an STA by someone else on this thread is fine for WMI
+                if hr < 0 && hr != RPC_E_CHANGED_MODE {
+                    check(hr, "CoInitializeEx")?;
+                }
+                let mut connection = Connection { services: null_mut(), uninitialize: hr >= 0 };
+
+                let hr = CoInitializeSecurity(
+                    null_mut(),
+                    -1,
+                    null_mut(),
+                    null_mut(),
+                    RPC_C_AUTHN_LEVEL_DEFAULT,
+                    RPC_C_IMP_LEVEL_IMPERSONATE,
+                    null_mut(),
+                    EOAC_NONE,
+                    null_mut(),
+                );
+                if hr != RPC_E_TOO_LATE {
+                    check(hr, "CoInitializeSecurity")?;
+                }
+
+                let mut locator: *mut IWbemLocator = null_mut();
+                check(
+                    CoCreateInstance(
+                        &CLSID_WbemLocator,
+                        null_mut(),
+                        CLSCTX_INPROC_SERVER,
+                        &IWbemLocator::uuidof(),
+                        &mut locator as *mut _ as *mut *mut c_void,
+                    ),
+                    "CoCreateInstance(WbemLocator)",
+                )?;
+
+                let ns = Bstr::new(namespace);
+                let hr = (*locator).ConnectServer(ns.0, null_mut(), null_mut(), null_mut(), 0, null_mut(), null_mut(), &mut connection.services);
+                release(locator);
+                check(hr, &format!("ConnectServer({})", namespace))?;
+
+                check(
+                    CoSetProxyBlanket(
+                        connection.services as *mut IUnknown,
+                        RPC_C_AUTHN_WINNT,
+                        RPC_C_AUTHZ_NONE,
+                        null_mut(),
+                        RPC_C_AUTHN_LEVEL_CALL,
+                        RPC_C_IMP_LEVEL_IMPERSONATE,
+                        null_mut(),
+                        EOAC_NONE,
+                    ),
+                    "CoSetProxyBlanket",
+                )?;
+
+                return Ok(connection);
+            }
+        }
+
+        pub(crate) fn raw_query(&self, wql: &str) -> Result<Vec<Row>, Box<dyn Error>> {
+            unsafe {
+                let (language, query) = (Bstr::new("WQL"), Bstr::new(wql));
+                let mut enumerator: *mut IEnumWbemClassObject = null_mut();
+                check(
+                    (*self.services).ExecQuery(
+                        language.0,
+                        query.0,
+                        (WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY) as i32,
+                        null_mut(),
+                        &mut enumerator,
+                    ),
+                    "ExecQuery",
+                )?;
+
+                let mut rows = vec![];
+                let result = loop {
+                    let mut object: *mut IWbemClassObject = null_mut();
+                    let mut returned = 0_u32;
+                    let hr = (*enumerator).Next(WBEM_INFINITE as i32, 1, &mut object, &mut returned);
+                    if hr < 0 {
+                        break check(hr, "IEnumWbemClassObject::Next");
+                    }
+                    if returned == 0 {
+                        break Ok(());
+                    }
+                    rows.push(read_row(object));
+                    release(object);
+                };
+                release(enumerator);
+
+                result?;
+                return Ok(rows);
+            }
+        }
+    }
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            unsafe {
+                release(self.services);
+                if self.uninitialize {
+                    CoUninitialize();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+pub(crate) use com::Connection;
+
+#[cfg(windows)]
+impl Connection {
+    #[allow(dead_code)]
+    pub(crate) fn query<T: FromRow>(&self, wql: &str) -> Result<Vec<T>, Box<dyn Error>> {
+        return self.raw_query(wql)?.iter().map(T::from_row).collect();
+    }
+}
+
+pub(crate) const DEFAULT_NAMESPACE: &str = "ROOT\\CIMV2";
+
+//Column order comes from the first row, later rows can only have the same properties
+#[cfg(windows)]
+pub(crate) fn print_table(rows: &[Row]) {
+    let headers: Vec<&str> = match rows.first() {
+        Some(row) => row.columns.iter().map(|(n, _)| n.as_str()).collect(),
+        None => {
+            println!("No results");
+            return;
+        }
+    };
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|r| headers.iter().map(|h| r.value(h).map(|v| v.display()).unwrap_or_default()).collect())
+        .collect();
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| cells.iter().map(|c| c[i].chars().count()).chain(std::iter::once(h.len())).max().unwrap_or(0))
+        .collect();
+
+    let line = |values: Vec<&str>| {
+        let padded: Vec<String> = values.iter().zip(&widths).map(|(v, w)| format!("{:<w$}", v, w = *w)).collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(headers.clone());
+    line(widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<String>>().iter().map(|s| s.as_str()).collect());
+    for row in &cells {
+        line(row.iter().map(|s| s.as_str()).collect());
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn print_json(rows: &[Row]) {
+    let objects: Vec<String> = rows.iter().map(|r| r.to_json()).collect();
+    println!("[{}]", objects.join(","));
+}
+
+//Entry point for `uptime wmi "<WQL>" [--namespace NS] [--json]`
+pub(crate) fn run(args: &[String], _config: &Config) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: uptime wmi \"<WQL>\" [--namespace ROOT\\CIMV2] [--json]";
+    let mut wql: Option<&str> = None;
+    let mut namespace = DEFAULT_NAMESPACE;
+    let mut json = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--namespace" => namespace = iter.next().ok_or(usage)?,
+            "--json" => json = true,
+            q if wql.is_none() && !q.starts_with("--") => wql = Some(q),
+            _ => return Err(usage.into()),
+        }
+    }
+    let wql = wql.ok_or(usage)?;
+
+    #[cfg(windows)]
+    {
+        let rows = Connection::new(namespace)?.raw_query(wql)?;
+        if json {
+            print_json(&rows);
+        } else {
+            print_table(&rows);
+        }
+        return Ok(());
+    }
+
+    #[cfg(not(windows))]
+    {
+        let _ = (wql, namespace, json);
+        return Err("WMI is only available on Windows".into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(columns: &[(&str, Value)]) -> Row {
+        Row {
+            columns: columns.iter().map(|(n, v)| (n.to_string(), v.clone())).collect(),
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Disk {
+        name: String,
+        size: u64,
+        free: Option<u64>,
+        flags: Vec<u16>,
+    }
+
+    impl FromRow for Disk {
+        fn from_row(row: &Row) -> Result<Self, Box<dyn Error>> {
+            return Ok(Disk {
+                name: row.get("Name")?,
+                size: row.get("Size")?,
+                free: row.get("FreeSpace")?,
+                flags: row.get("Capabilities")?,
+            });
+        }
+    }
+
+    #[test]
+    fn integers_parse_from_cim_strings() {
+        let r = row(&[
+            ("Size", Value::String("17179869184".to_string())),
+            ("Count", Value::String(" 42 ".to_string())),
+            ("Bad", Value::String("lots".to_string())),
+            ("Negative", Value::Int(-1)),
+            ("Unsigned", Value::UInt(7)),
+        ]);
+        assert_eq!(r.get::<u64>("Size").unwrap(), 17_179_869_184);
+        assert_eq!(r.get::<u32>("count").unwrap(), 42);
+        assert!(r.get::<u32>("Bad").is_err());
+        assert!(r.get::<u32>("Negative").is_err());
+        assert_eq!(r.get::<i64>("Negative").unwrap(), -1);
+        assert_eq!(r.get::<i64>("Unsigned").unwrap(), 7);
+        assert!(r.get::<u8>("Size").is_err());
+        assert_eq!(r.get::<f64>("Unsigned").unwrap(), 7.0);
+    }
+
+    #[test]
+    fn options_and_arrays() {
+        let r = row(&[
+            ("Present", Value::UInt(3)),
+            ("Missing", Value::Null),
+            ("List", Value::Array(vec![Value::UInt(3), Value::UInt(4)])),
+            ("Mixed", Value::Array(vec![Value::UInt(3), Value::String("x".to_string())])),
+        ]);
+        assert_eq!(r.get::<Option<u32>>("Present").unwrap(), Some(3));
+        assert_eq!(r.get::<Option<u32>>("Missing").unwrap(), None);
+        assert_eq!(r.get::<Option<u32>>("NotAProperty").unwrap(), None);
+        assert!(r.get::<u32>("NotAProperty").is_err());
+        assert_eq!(r.get::<Vec<u16>>("List").unwrap(), vec![3, 4]);
+        assert_eq!(r.get::<Vec<u16>>("Missing").unwrap(), Vec::<u16>::new());
+        assert!(r.get::<Vec<u16>>("Mixed").is_err());
+        assert!(r.get::<Vec<u16>>("Present").is_err());
+    }
+
+    #[test]
+    fn struct_from_row() {
+        let r = row(&[
+            ("Name", Value::String("C:".to_string())),
+            ("Size", Value::String("1000".to_string())),
+            ("FreeSpace", Value::Null),
+            ("Capabilities", Value::Array(vec![Value::UInt(3), Value::UInt(4)])),
+        ]);
+        let disk = Disk::from_row(&r).unwrap();
+        assert_eq!(disk, Disk { name: "C:".to_string(), size: 1000, free: None, flags: vec![3, 4] });
+        assert!(Disk::from_row(&row(&[("Name", Value::String("D:".to_string()))])).is_err());
+    }
+
+    #[test]
+    fn cim_datetime_offsets() {
+        //09:30:15 at UTC+1 and 09:30:15 at UTC-5
+        assert_eq!(parse_datetime("20240131093015.500000+060"), Some(1_706_689_815));
+        assert_eq!(parse_datetime("20240131093015.000000-300"), Some(1_706_711_415));
+        assert_eq!(parse_datetime("20240131083015.000000+000"), Some(1_706_689_815));
+        assert_eq!(parse_datetime("20240131093015.500000"), None);
+        assert_eq!(parse_datetime("2024013109301x.500000+060"), None);
+    }
+
+    #[test]
+    fn rows_to_json() {
+        let r = row(&[
+            ("Name", Value::String("a \"b\"\n".to_string())),
+            ("Load", Value::Float(f64::NAN)),
+            ("Ids", Value::Array(vec![Value::Int(-1), Value::Bool(true)])),
+        ]);
+        assert_eq!(r.to_json(), r#"{"Name":"a \"b\"\n","Load":null,"Ids":[-1,true]}"#);
+    }
+}